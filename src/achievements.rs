@@ -0,0 +1,208 @@
+//! Achievements that players unlock by hitting milestones during the game.
+//!
+//! The game state reports each [`AchievementEvent`] that happens to a player (e.g. surviving a
+//! nose-goes event), which updates the player's [`AchievementProgress`]. Afterwards the player's
+//! progress is checked against every [`Achievement`], and any newly unlocked achievements are
+//! stored on the player and announced to the hosts and players.
+//!
+//! Some achievements (like being the first to 100 points) can only be unlocked by one player per
+//! game.
+//!
+//! [`AchievementEvent`]: ./enum.AchievementEvent.html
+//! [`AchievementProgress`]: ./struct.AchievementProgress.html
+//! [`Achievement`]: ./enum.Achievement.html
+
+use game::Player;
+use std::cmp;
+use std::collections::HashSet;
+
+/// Something that happened to a player that can count towards an achievement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AchievementEvent {
+    /// The player's score went up.
+    Scored,
+
+    /// The player survived a nose-goes event without losing a life.
+    SurvivedNoseGoes,
+
+    /// The player got some of the bonus at the end of a nose-goes event.
+    WonBonus,
+
+    /// The player has been wearing the crown for the given number of milliseconds without losing
+    /// it.
+    HeldCrown(u64),
+}
+
+/// A player's running totals for the events that count towards achievements.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct AchievementProgress {
+    pub nose_goes_survived: usize,
+    pub bonuses_won: usize,
+
+    /// The longest the player has worn the crown without losing it, in milliseconds.
+    pub longest_reign: u64,
+}
+
+impl AchievementProgress {
+    /// Updates the totals for `event`.
+    pub fn record(&mut self, event: AchievementEvent) {
+        match event {
+            AchievementEvent::Scored => {}
+            AchievementEvent::SurvivedNoseGoes => self.nose_goes_survived += 1,
+            AchievementEvent::WonBonus => self.bonuses_won += 1,
+            AchievementEvent::HeldCrown(held_for) => {
+                self.longest_reign = cmp::max(self.longest_reign, held_for);
+            }
+        }
+    }
+}
+
+/// An achievement that a player can unlock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Achievement {
+    /// The first player in the game to reach 100 points.
+    FirstToHundred,
+
+    /// Survived 5 nose-goes events.
+    Survivor,
+
+    /// Got part of the bonus in 3 nose-goes events.
+    BonusHunter,
+
+    /// Wore the crown for 60 seconds without losing it.
+    LongReign,
+}
+
+/// Every achievement, in the order they're checked.
+pub static ACHIEVEMENTS: &'static [Achievement] = &[
+    Achievement::FirstToHundred,
+    Achievement::Survivor,
+    Achievement::BonusHunter,
+    Achievement::LongReign,
+];
+
+impl Achievement {
+    /// Returns the achievement's display name.
+    pub fn title(&self) -> &'static str {
+        match *self {
+            Achievement::FirstToHundred => "Hungriest Hippo",
+            Achievement::Survivor => "Survivor",
+            Achievement::BonusHunter => "Bonus Hunter",
+            Achievement::LongReign => "Long Live the King",
+        }
+    }
+
+    /// Returns a description of how the achievement is unlocked.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Achievement::FirstToHundred => "First to 100 points",
+            Achievement::Survivor => "Survived 5 nose-goes events",
+            Achievement::BonusHunter => "Won 3 nose-goes bonuses",
+            Achievement::LongReign => "Held the crown for 60 seconds",
+        }
+    }
+
+    /// Returns whether only the first player to unlock the achievement gets it.
+    pub fn is_unique(&self) -> bool {
+        match *self {
+            Achievement::FirstToHundred => true,
+            Achievement::Survivor | Achievement::BonusHunter | Achievement::LongReign => false,
+        }
+    }
+
+    /// Returns whether `player` has done enough to unlock the achievement.
+    fn is_earned(&self, player: &Player) -> bool {
+        match *self {
+            Achievement::FirstToHundred => player.score >= 100,
+            Achievement::Survivor => player.progress.nose_goes_survived >= 5,
+            Achievement::BonusHunter => player.progress.bonuses_won >= 3,
+            Achievement::LongReign => player.progress.longest_reign >= 60_000,
+        }
+    }
+}
+
+/// Returns the achievements that `player` has earned but doesn't have yet.
+///
+/// `claimed` are the unique achievements that another player has already unlocked.
+pub fn newly_earned(player: &Player, claimed: &HashSet<Achievement>) -> Vec<Achievement> {
+    ACHIEVEMENTS.iter()
+        .filter(|achievement| !player.achievements.contains(*achievement))
+        .filter(|achievement| !(achievement.is_unique() && claimed.contains(*achievement)))
+        .filter(|achievement| achievement.is_earned(player))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmetics::Cosmetics;
+    use game::PlayerId;
+    use super::*;
+
+    fn player(score: usize) -> Player {
+        Player {
+            id: PlayerId::new(),
+            name: "Test Hippo".into(),
+            score,
+            lives: 1,
+            cosmetics: Cosmetics::default(),
+            achievements: Vec::new(),
+            progress: AchievementProgress::default(),
+            partial_points: 0,
+            scored_at: 0,
+            peak_score: 0,
+        }
+    }
+
+    #[test]
+    fn progress_totals() {
+        let mut progress = AchievementProgress::default();
+        progress.record(AchievementEvent::Scored);
+        progress.record(AchievementEvent::SurvivedNoseGoes);
+        progress.record(AchievementEvent::WonBonus);
+        progress.record(AchievementEvent::HeldCrown(30_000));
+        progress.record(AchievementEvent::HeldCrown(10_000));
+
+        assert_eq!(progress.nose_goes_survived, 1);
+        assert_eq!(progress.bonuses_won, 1);
+        assert_eq!(progress.longest_reign, 30_000);
+    }
+
+    #[test]
+    fn nothing_is_earned_at_the_start() {
+        assert!(newly_earned(&player(0), &HashSet::new()).is_empty());
+        assert!(newly_earned(&player(99), &HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn milestones_unlock_achievements() {
+        let mut player = player(100);
+        player.progress.nose_goes_survived = 5;
+        player.progress.bonuses_won = 3;
+        player.progress.longest_reign = 60_000;
+
+        assert_eq!(newly_earned(&player, &HashSet::new()), ACHIEVEMENTS.to_vec());
+    }
+
+    #[test]
+    fn achievements_are_only_unlocked_once() {
+        let mut player = player(100);
+        player.progress.nose_goes_survived = 5;
+        player.achievements.push(Achievement::FirstToHundred);
+
+        assert_eq!(newly_earned(&player, &HashSet::new()), vec![Achievement::Survivor]);
+    }
+
+    #[test]
+    fn claimed_unique_achievements_are_not_unlocked_again() {
+        let claimed = ACHIEVEMENTS.iter()
+            .cloned()
+            .filter(Achievement::is_unique)
+            .collect::<HashSet<_>>();
+        assert!(claimed.contains(&Achievement::FirstToHundred));
+
+        let mut player = player(100);
+        player.progress.bonuses_won = 3;
+        assert_eq!(newly_earned(&player, &claimed), vec![Achievement::BonusHunter]);
+    }
+}
@@ -0,0 +1,128 @@
+//! Endpoints for keeping an eye on a running game.
+//!
+//! Admin endpoints are mounted under `/admin` and require the `X-Admin-Token` header to match the
+//! `admin_token` option in `Rocket.toml` (or the `ROCKET_ADMIN_TOKEN` environment variable). If no
+//! token is configured the admin endpoints are disabled entirely.
+
+use broadcast::*;
+use elimination::Elimination;
+use error::*;
+use game_loop::GameHandle;
+use rocket::{Outcome, Request, State};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket_contrib::Json;
+
+/// The header that clients use to send the admin token.
+static TOKEN_HEADER: &'static str = "X-Admin-Token";
+
+/// Configuration for the admin endpoints, managed by Rocket.
+#[derive(Debug)]
+pub struct AdminConfig {
+    /// The token that admin requests must provide, or `None` if the admin endpoints are disabled.
+    pub token: Option<String>,
+}
+
+/// Request guard that only succeeds if the request has the correct admin token.
+///
+/// Fails with a 401 (and an `unauthorized` error body) if the token is missing or wrong, and with
+/// a 403 if no admin token has been configured.
+#[derive(Debug)]
+pub struct AdminToken;
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<AdminToken, ()> {
+        let config = match request.guard::<State<AdminConfig>>() {
+            Outcome::Success(config) => config,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        let expected = match config.token {
+            Some(ref token) => token,
+            None => return Outcome::Failure((Status::Forbidden, ())),
+        };
+
+        match request.headers().get_one(TOKEN_HEADER) {
+            Some(token) if token == expected => Outcome::Success(AdminToken),
+            _ => {
+                warn!("Rejected admin request with a missing or invalid token: {}", request);
+                Outcome::Failure((Status::Unauthorized, ()))
+            }
+        }
+    }
+}
+
+/// The websockets connected to a single stream.
+#[derive(Debug, Serialize)]
+pub struct StreamConnections {
+    /// The number of live connections.
+    pub count: usize,
+
+    /// Details for each live connection.
+    pub connections: Vec<ConnectionSummary>,
+}
+
+impl StreamConnections {
+    fn new(connections: Vec<ConnectionSummary>) -> StreamConnections {
+        StreamConnections {
+            count: connections.len(),
+            connections,
+        }
+    }
+}
+
+/// The response for the `/admin/connections` endpoint.
+#[derive(Debug, Serialize)]
+pub struct ConnectionsResponse {
+    /// The connection counts as last seen by the game loop.
+    ///
+    /// The game loop refreshes its counts every tick, so these may lag slightly behind the
+    /// per-stream details.
+    pub game_loop: ConnectionCounts,
+
+    /// The websockets connected to the host stream.
+    pub host: StreamConnections,
+
+    /// The websockets connected to the player stream.
+    pub player: StreamConnections,
+}
+
+/// Reports the websockets that are currently connected to each stream.
+#[get("/connections")]
+pub fn connections(
+    _token: AdminToken,
+    game: State<GameHandle>,
+    host_broadcaster: State<HostBroadcaster>,
+    player_broadcaster: State<PlayerBroadcaster>,
+) -> Result<Json<ConnectionsResponse>> {
+    let stats = game.stats()?;
+
+    Ok(Json(ConnectionsResponse {
+        game_loop: stats.connections,
+        host: StreamConnections::new(host_broadcaster.connection_summaries()),
+        player: StreamConnections::new(player_broadcaster.connection_summaries()),
+    }))
+}
+
+/// Picks the elimination rule for the next nose-goes event.
+///
+/// The rule only applies to the next event, after which events go back to cycling through the
+/// configured rules.
+///
+/// # Errors
+///
+/// Returns `Err(InvalidRequest)` if the rule's settings are out of range, e.g. a percentage over
+/// 100.
+#[post("/nose-goes/elimination", format = "application/json", data = "<elimination>")]
+pub fn set_next_elimination(
+    _token: AdminToken,
+    elimination: Json<Elimination>,
+    game: State<GameHandle>,
+) -> Result<Json<Elimination>> {
+    let elimination = elimination.into_inner();
+    elimination.validate()?;
+    game.set_next_elimination(elimination)?;
+    Ok(Json(elimination))
+}
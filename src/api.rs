@@ -1,9 +1,11 @@
-use broadcast::*;
-use game;
+use achievements::Achievement;
+use clock::Clock;
+use cosmetics::{CosmeticCatalog, Cosmetics};
+use error::*;
 use game::*;
-use rocket::http::Status;
-use rocket::response::*;
+use game_loop::*;
 use rocket::State;
+use schema::ApiVersion;
 
 /// The current state for a player that is needed by the host site.
 ///
@@ -12,60 +14,76 @@ use rocket::State;
 #[derive(Debug, Serialize, Responder)]
 pub struct PlayerData {
     /// The player's ID.
-    id: PlayerId,
+    pub id: PlayerId,
 
     /// The player's display name.
-    name: String,
+    pub name: String,
 
     /// The player's current score.
-    score: usize,
+    pub score: usize,
+
+    /// How many nose-goes events the player can lose before they're knocked out.
+    pub lives: usize,
+
+    /// How the player's hippo looks.
+    pub cosmetics: Cosmetics,
+
+    /// The achievements the player has unlocked, in the order they were unlocked.
+    pub achievements: Vec<Achievement>,
 
     /// Whether or not the player has the crown (i.e. if the player is winning).
-    has_crown: bool,
+    pub has_crown: bool,
 }
 
-/// Generates a `PlayerId` for a new player.
 // TODO: Allow players to specify a username when registering.
+/// Generates a `PlayerId` for a new player.
+///
+/// The new player's hippo gets a random color. Use `POST /register-player` to pick the hippo's
+/// cosmetics up front instead.
 #[get("/register-player")]
-pub fn register_player(
-    players: State<PlayerMap>,
-    winner: State<Winner>,
-    host_broadcaster: State<HostBroadcaster>,
-    player_broadcaster: State<PlayerBroadcaster>,
-) -> PlayerData {
-    let id = PlayerId::new();
-    let name = game::generate_username();
-    let score = 0;
-
-    let player = Player {
-        id,
-        name: name.clone(),
-        score,
-    };
+pub fn register_player(game: State<GameHandle>, _version: ApiVersion) -> Result<PlayerData> {
+    game.register(None)
+}
 
-    // Add the player to the game state.
-    let mut players = players.write().expect("Players map was poisoned!");
-    let old = players.insert(id, player);
-    assert!(old.is_none(), "Player ID was registered twice");
-
-    // Broadcast to all hosts that a new player has joined.
-    host_broadcaster.send(HostBroadcast::PlayerRegister {
-        id,
-        name: name.clone(),
-        score,
-    });
-
-    // Update winner if this is the first player.
-    let mut winner = winner.lock().expect("Winner was poisoned!");
-    let has_crown = winner.is_none();
-    if winner.is_none() {
-        *winner = Some(id);
-        host_broadcaster.send(HostBroadcast::UpdateWinner { id });
-        player_broadcaster.send(PlayerBroadcast::UpdateWinner { id });
-    }
-
-    // Respond to the client.
-    PlayerData { id, name, score, has_crown }
+/// Generates a `PlayerId` for a new player, whose hippo has the given cosmetics.
+///
+/// The new player has no points yet, so only options without an `unlock_score` can be picked.
+#[post("/register-player", format = "application/json", data = "<cosmetics>")]
+pub fn register_player_with_cosmetics(
+    cosmetics: Cosmetics,
+    game: State<GameHandle>,
+    _version: ApiVersion,
+) -> Result<PlayerData> {
+    game.register(Some(cosmetics))
+}
+
+/// Returns every cosmetic option that players can pick for their hippo.
+#[get("/cosmetics")]
+pub fn cosmetics(_version: ApiVersion) -> CosmeticCatalog {
+    CosmeticCatalog::new()
+}
+
+/// Changes how the player's hippo looks.
+///
+/// Every option must be in the catalog returned by `/cosmetics`, and the player's score must have
+/// reached the option's `unlock_score`.
+#[post("/cosmetics/<id>", format = "application/json", data = "<cosmetics>")]
+pub fn set_cosmetics(
+    id: PlayerId,
+    cosmetics: Cosmetics,
+    game: State<GameHandle>,
+    _version: ApiVersion,
+) -> Result<PlayerData> {
+    game.set_cosmetics(id, cosmetics)
+}
+
+/// Brings a knocked-out player back into the game with the same ID and name.
+///
+/// Only works if respawning is turned on, and only once the player's cooldown is over. The
+/// player's score is reduced by the respawn penalty.
+#[post("/respawn/<id>")]
+pub fn respawn(id: PlayerId, game: State<GameHandle>, _version: ApiVersion) -> Result<PlayerData> {
+    game.respawn(id)
 }
 
 /// The request expected from the client for the `/feed-me` endpoint.
@@ -86,96 +104,127 @@ pub struct FeedMeResponse {
 /// # Errors
 ///
 /// If the `player` member of `payload` isn't a valid `PlayerId` (i.e. the ID isn't in `scores`),
-/// Then `Err(InvalidPlayer)` is returned.
+/// Then `Err(InvalidPlayer)` is returned. If the player is feeding faster than the rate limit
+/// allows, `Err(RateLimited)` is returned.
 #[post("/feed-me", format = "application/json", data = "<payload>")]
 pub fn feed_player(
     payload: FeedMeRequest,
-    players: State<PlayerMap>,
-    winner: State<Winner>,
-    host_broadcaster: State<HostBroadcaster>,
-    player_broadcaster: State<PlayerBroadcaster>,
+    game: State<GameHandle>,
+    _version: ApiVersion,
 ) -> Result<FeedMeResponse> {
-    let id = payload.id;
-
-    // Add 1 to the player's score, returning the new score. We create an explicit scope here to
-    // limit how long we hold the lock on the player map.
-    let mut players = players.write().expect("Player map was poisoned!");
+    let score = game.feed(payload.id)?;
+    Ok(FeedMeResponse { score })
+}
 
-    // Get the player's current score, or return an `InvalidPlayer` error if it's not in
-    // the scoreboard.
-    let score = {
-        let player = players
-            .get_mut(&id)
-            .ok_or(Error::InvalidPlayer(id))?;
+/// The request expected from the client for the `/feed-batch` endpoint.
+#[derive(Debug, Deserialize, FromData)]
+pub struct FeedBatchRequest {
+    /// The `PlayerId` for the player that tapped.
+    pub id: PlayerId,
 
-        player.score += 1;
-        player.score
-    };
+    /// The time of each tap on the client, in milliseconds since the Unix epoch.
+    ///
+    /// Must be in the order the taps happened. Taps closer together than anyone can tap don't
+    /// count.
+    pub taps: Vec<u64>,
+}
 
-    // Update the host displays.
-    host_broadcaster.send(HostBroadcast::HippoEat { id, score });
+/// The response sent back from the `/feed-batch` endpoint.
+#[derive(Debug, Serialize, Responder)]
+pub struct FeedBatchResponse {
+    /// The number of taps that counted.
+    ///
+    /// Taps over the rate limit, or closer together than anyone can tap, are dropped, so this may
+    /// be less than the number of taps sent.
+    pub accepted: usize,
 
-    let mut winner = winner.lock().expect("Winner was poisoned!");
-    let winner = winner.as_mut().expect("There must be a winner if a hippo is being fed");
-    let winner_score = players.get(winner).unwrap().score;
-    if score > winner_score && id != *winner {
-        // Make the current player the new winner.
-        *winner = id;
-        host_broadcaster.send(HostBroadcast::UpdateWinner { id });
-        player_broadcaster.send(PlayerBroadcast::UpdateWinner { id });
-    }
+    /// The player's total score.
+    pub score: usize,
+}
 
-    Ok(FeedMeResponse { score })
+/// Feeds a player's hippo once for each tap in a batch of taps.
+///
+/// Clients that tap rapidly should collect taps and send them in batches rather than sending a
+/// `/feed-me` request for every tap.
+///
+/// # Errors
+///
+/// Returns `Err(InvalidPlayer)` if the player isn't in the game, `Err(InvalidRequest)` if the
+/// batch is too large or the taps are out of order, or `Err(RateLimited)` if none of the taps
+/// counted because the player has used up their rate limit.
+#[post("/feed-batch", format = "application/json", data = "<payload>")]
+pub fn feed_batch(
+    payload: FeedBatchRequest,
+    game: State<GameHandle>,
+    _version: ApiVersion,
+) -> Result<FeedBatchResponse> {
+    game.feed_batch(payload.id, payload.taps)
 }
 
-#[derive(Debug, Serialize, Responder)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Responder)]
 pub enum NoseGoesResponse {
+    /// The tap was recorded, and the outcome will be decided when the event ends.
+    Pending,
     Survived,
+
+    /// The player lost the event, but had a life to spare so they're still in the game.
+    LostLife,
     Died,
 }
 
-#[post("/nose-goes/<id>")]
+/// The optional request body for the `/nose-goes/<id>` endpoint.
+#[derive(Debug, Deserialize, FromData)]
+pub struct NoseGoesTapRequest {
+    /// When the player tapped, in milliseconds since the Unix epoch (server time).
+    #[serde(default)]
+    pub tapped_at: Option<u64>,
+}
+
+/// Taps the player's poison marble during a nose-goes event.
+///
+/// Clients can send when the player tapped in the request body, which is used (within limits) to
+/// make up for the time the request took to arrive. Tapping more than once during the same event
+/// is harmless, the repeat taps get the same response as the first one.
+#[post("/nose-goes/<id>", data = "<payload>")]
 pub fn nose_goes(
     id: PlayerId,
-    nose_goes: State<NoseGoesState>,
-    winner: State<Winner>,
-    host_broadcaster: State<HostBroadcaster>,
-    player_broadcaster: State<PlayerBroadcaster>,
+    payload: Option<NoseGoesTapRequest>,
+    game: State<GameHandle>,
+    _version: ApiVersion,
 ) -> Result<NoseGoesResponse> {
-    let mut nose_goes = nose_goes.lock().expect("Nose-goes state was poisoned!");
-    match *nose_goes {
-        NoseGoes::Inactive { .. } => {
-            Err(Error::InvalidNoesGoes)
-        }
-
-        NoseGoes::InProgress { ref mut remaining_players, ref mut bonus_winner, .. } => {
-            let winner = winner.lock().expect("Winner was poisoned!");
-
-            // It's an error for the player to not be part of the nose-goes event.
-            if !remaining_players.contains(&id) {
-                return Err(Error::InvalidNoesGoes);
-            }
-
-            // If there are multiple players still in the event, remove the player. If the player
-            // is the last one left, they die.
-            if remaining_players.len() > 1 {
-                remaining_players.remove(&id);
-
-                // If the player is not winning and first one to tap, they get the bonus points.
-                if bonus_winner.is_none() && *winner != Some(id) {
-                    *bonus_winner = Some(id);
-
-                    // Notify the player and host that a bonus winner has been selected.
-                    host_broadcaster.send(HostBroadcast::BonusWinner { id });
-                    player_broadcaster.send(PlayerBroadcast::BonusWinner { id });
-                }
-
-                Ok(NoseGoesResponse::Survived)
-            } else {
-                Ok(NoseGoesResponse::Died)
-            }
-        }
-    }
+    let timing = TapTiming {
+        tapped_at: payload.and_then(|payload| payload.tapped_at),
+        rtt: None,
+    };
+    game.tap(id, timing)
+}
+
+/// The response sent back from the `/nose-goes/status/<id>` endpoint.
+#[derive(Debug, Serialize, Responder)]
+pub struct NoseGoesStatus {
+    /// Whether a nose-goes event is in progress. If not, the rest of the status describes the most
+    /// recent event.
+    pub active: bool,
+
+    /// Whether the player is (or was) part of the event.
+    pub in_round: bool,
+
+    /// The player's tap during the event, if they tapped.
+    pub tap: Option<NoseGoesTap>,
+
+    /// What happened to the player, or `None` while the event is still in progress.
+    pub outcome: Option<NoseGoesResponse>,
+}
+
+/// Returns how the player is doing in the current nose-goes event, or how they did in the most
+/// recent one.
+#[get("/nose-goes/status/<id>")]
+pub fn nose_goes_status(
+    id: PlayerId,
+    game: State<GameHandle>,
+    _version: ApiVersion,
+) -> Result<NoseGoesStatus> {
+    game.nose_goes_status(id)
 }
 
 /// The response sent back from the `/scoreboard` endpoint.
@@ -190,18 +239,80 @@ pub struct PlayersResponse {
 #[get("/player/<id>")]
 pub fn get_player(
     id: PlayerId,
-    players: State<PlayerMap>,
-    winner: State<Winner>,
-) -> Option<PlayerData> {
-    let players = players.read().expect("Player map was poisoned!");
-    let winner = winner.lock().expect("Winner was poisoned!");
-
-    players.get(&id).map(|player| PlayerData {
-        id: player.id,
-        name: player.name.clone(),
-        score: player.score,
-        has_crown: Some(player.id) == *winner,
-    })
+    game: State<GameHandle>,
+    _version: ApiVersion,
+) -> Result<Option<PlayerData>> {
+    game.player(id)
+}
+
+/// The response sent back from the `/crown-history` endpoint.
+#[derive(Debug, Serialize, Responder)]
+pub struct CrownHistoryResponse {
+    /// Who has worn the crown and for how long, oldest first.
+    pub history: Vec<CrownTenure>,
+}
+
+/// Returns who has worn the crown over the course of the game, and for how long.
+///
+/// Only the most recent tenures are kept, see `MAX_CROWN_HISTORY`.
+#[get("/crown-history")]
+pub fn crown_history(
+    game: State<GameHandle>,
+    _version: ApiVersion,
+) -> Result<CrownHistoryResponse> {
+    let history = game.crown_history()?;
+    Ok(CrownHistoryResponse { history })
+}
+
+/// A player's place in the rankings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RankedPlayer {
+    /// The player's place, starting from 1. Players with the same score share a rank.
+    pub rank: usize,
+
+    /// The player's ID.
+    pub id: PlayerId,
+
+    /// The player's display name.
+    pub name: String,
+
+    /// The player's current score.
+    pub score: usize,
+}
+
+/// The query parameters for the `/rankings` endpoint.
+#[derive(Debug, FromForm)]
+pub struct RankingsQuery {
+    /// How many of the top players to return.
+    pub limit: usize,
+}
+
+/// The response sent back from the `/rankings` endpoint.
+#[derive(Debug, Serialize, Responder)]
+pub struct RankingsResponse {
+    /// The players in rank order, highest score first.
+    pub rankings: Vec<RankedPlayer>,
+}
+
+/// Returns the top `limit` players, ranked by score.
+///
+/// Players with the same score are ordered by who reached it first, so hosts can show a podium
+/// without sorting `/players` themselves.
+#[get("/rankings?<query>")]
+pub fn rankings(
+    query: RankingsQuery,
+    game: State<GameHandle>,
+    _version: ApiVersion,
+) -> Result<RankingsResponse> {
+    let rankings = game.rankings(Some(query.limit))?;
+    Ok(RankingsResponse { rankings })
+}
+
+/// Returns every player, ranked by score.
+#[get("/rankings", rank = 2)]
+pub fn all_rankings(game: State<GameHandle>, _version: ApiVersion) -> Result<RankingsResponse> {
+    let rankings = game.rankings(None)?;
+    Ok(RankingsResponse { rankings })
 }
 
 /// Returns a list of players and their scores.
@@ -209,48 +320,25 @@ pub fn get_player(
 /// This is used by new host connections to update thier display to match the current state of the
 /// game.
 #[get("/players")]
-pub fn get_players(players: State<PlayerMap>, winner: State<Winner>) -> PlayersResponse {
-    let players = players.read().expect("Player map was poisoned!");
-    let winner = winner.lock().expect("Winner was poisoned!");
-    let players = players.values()
-        .map(|player| {
-            PlayerData {
-                id: player.id,
-                name: player.name.clone(),
-                score: player.score,
-                has_crown: Some(player.id) == *winner,
-            }
-        })
-        .collect();
-
-    PlayersResponse { players }
+pub fn get_players(game: State<GameHandle>, _version: ApiVersion) -> Result<PlayersResponse> {
+    let players = game.players()?;
+    Ok(PlayersResponse { players })
 }
 
-/// The error type for an API requests that can fail.
-#[derive(Debug, Serialize)]
-pub enum Error {
-    /// Indicates that an invalid player was specified for the operation.
-    ///
-    /// This might occur if the client code cached the player ID from a previous session, and is
-    /// now trying to use the ID in a session where it is no longer valid. Re-registering the
-    /// player to generate a new ID should fix the issue.
-    InvalidPlayer(PlayerId),
-
-    /// Indicates that a noes-goes request was not valid.
-    ///
-    /// This can occur for two reasons:
-    ///
-    /// - The request arrived when no noes-goes event was active.
-    /// - The player was not a part of the active noes-goes event.
-    InvalidNoesGoes,
+/// The response sent back from the `/time` endpoint.
+#[derive(Debug, Serialize, Responder)]
+pub struct TimeResponse {
+    /// The server's current time, in milliseconds since the Unix epoch.
+    pub server_time: u64,
 }
 
-impl<'r> Responder<'r> for Error {
-    fn respond_to(self, request: &::rocket::request::Request) -> ::std::result::Result<Response<'r>, Status> {
-        use rocket::response::status::Custom;
-
-        Custom(Status::BadRequest, ::rocket_contrib::Json(self)).respond_to(request)
-    }
+/// Returns the server's current time.
+///
+/// Broadcasts include server times (e.g. when a nose-goes event ends), so clients use this to
+/// estimate the offset between their clock and the server's. Given the client time when the
+/// request was sent (`sent`) and when the response arrived (`received`), the offset is roughly
+/// `server_time - (sent + received) / 2`.
+#[get("/time")]
+pub fn time(clock: State<Clock>, _version: ApiVersion) -> TimeResponse {
+    TimeResponse { server_time: clock.now() }
 }
-
-pub type Result<T> = ::std::result::Result<T, Error>;
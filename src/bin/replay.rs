@@ -0,0 +1,258 @@
+//! Replays an event log through the game rules and prints the results.
+//!
+//! Usage: `cargo run --bin replay -- <path to event log>`
+//!
+//! Each game in the log is replayed from a fresh game state, printing every registration, every
+//! nose-goes tap (along with whether the tap counted), and the outcome of every nose-goes event.
+//! Once the game is over the final scores are printed.
+
+extern crate hangry_river_horse;
+
+use hangry_river_horse::broadcast::HostBroadcast;
+use hangry_river_horse::clock::Clock;
+use hangry_river_horse::event_log::{self, Event, Input};
+use hangry_river_horse::game::{GameState, PlayerId};
+use std::collections::HashMap;
+use std::env;
+use std::process;
+use std::time::*;
+
+/// A game that is being replayed.
+struct Replay {
+    state: GameState,
+
+    /// The instant that `state` considers to be the start of the game.
+    start: Instant,
+
+    /// The timestamp of the game's `Start` event, in milliseconds since the Unix epoch.
+    start_timestamp: u64,
+
+    /// Maps game times back to the wall-clock times in the event log.
+    ///
+    /// This is anchored so that `start` is `start_timestamp`. Event timestamps are the game
+    /// clock's readings on the server, so each event is applied at the same game time as it was
+    /// originally, and broadcast times match the original ones.
+    clock: Clock,
+
+    /// The names of all players that joined, including those that have since been knocked out.
+    names: HashMap<PlayerId, String>,
+}
+
+impl Replay {
+    fn new(start_timestamp: u64) -> Replay {
+        let start = Instant::now();
+        let clock = Clock::anchored(start, start_timestamp);
+        Replay {
+            state: GameState::new(start, clock),
+            start,
+            start_timestamp,
+            clock,
+            names: HashMap::new(),
+        }
+    }
+
+    /// Returns a display name for `id`, including the ID so that duplicate names can be told apart.
+    fn name(&self, id: PlayerId) -> String {
+        match self.names.get(&id) {
+            Some(name) => format!("{} ({:?})", name, id),
+            None => format!("{:?}", id),
+        }
+    }
+
+    /// Applies `event` to the game state, printing anything interesting that happened.
+    fn apply(&mut self, event: Event) {
+        let elapsed = Duration::from_millis(event.timestamp.saturating_sub(self.start_timestamp));
+        let now = self.start + elapsed;
+        let time = format!("[{:>4}.{:03}s]", elapsed.as_secs(), elapsed.subsec_nanos() / 1_000_000);
+
+        match event.input {
+            // `Start` events are handled by the caller.
+            Input::Start => {}
+
+            Input::Register { id, name, cosmetics } => {
+                self.names.insert(id, name.clone());
+                match self.state.register(id, name, cosmetics) {
+                    Ok(_) => println!("{} {} joined the game", time, self.name(id)),
+                    Err(error) => println!("{} {} failed to join: {:?}", time, self.name(id), error),
+                }
+            }
+
+            Input::SetCosmetics { id, cosmetics } => {
+                if let Err(error) = self.state.set_cosmetics(id, cosmetics) {
+                    println!("{} {} failed to change cosmetics: {:?}", time, self.name(id), error);
+                }
+            }
+
+            Input::Respawn { id } => {
+                match self.state.respawn(id, now) {
+                    Ok(_) => println!("{} {} respawned", time, self.name(id)),
+                    Err(error) => println!("{} {} failed to respawn: {:?}", time, self.name(id), error),
+                }
+            }
+
+            Input::Feed { id } => {
+                // Feeding happens way too often to print, only print when it goes wrong.
+                if let Err(error) = self.state.feed(id, now) {
+                    println!("{} {} failed to feed: {:?}", time, self.name(id), error);
+                }
+            }
+
+            Input::FeedBatch { id, taps } => {
+                if let Err(error) = self.state.feed_batch(id, &taps, now) {
+                    println!("{} {} failed to feed: {:?}", time, self.name(id), error);
+                }
+            }
+
+            Input::Tap { id, timing } => {
+                match self.state.tap(id, timing, now) {
+                    Ok(response) => println!("{} {} tapped: {:?}", time, self.name(id), response),
+                    Err(error) => println!("{} {} tapped, but it didn't count: {:?}", time, self.name(id), error),
+                }
+            }
+
+            Input::SetRules(rules) => {
+                self.state.set_rules(rules);
+            }
+
+            Input::SetNextElimination(elimination) => {
+                println!("{} The next nose-goes event will use {:?}", time, elimination);
+                self.state.set_next_elimination(elimination);
+            }
+
+            Input::Restore(snapshot) => {
+                for player in &snapshot.players {
+                    self.names.insert(player.id, player.name.clone());
+                }
+                println!("{} Resumed a saved game with {} players", time, snapshot.players.len());
+                let rules = self.state.rules().clone();
+                self.state = GameState::restore(snapshot, rules, now, self.clock);
+            }
+
+            Input::Tick => self.state.tick(now),
+
+            Input::Shutdown => {
+                println!("{} The server shut down", time);
+                self.state.shut_down();
+            }
+        }
+
+        // Print the nose-goes related broadcasts, since those are what disputes are about.
+        let (host_broadcasts, _) = self.state.take_broadcasts();
+        for broadcast in host_broadcasts {
+            match broadcast {
+                HostBroadcast::BeginNoseGoes { players, elimination, .. } => {
+                    println!(
+                        "{} Nose-goes started with {} players ({:?})",
+                        time,
+                        players.len(),
+                        elimination,
+                    );
+                }
+
+                HostBroadcast::BonusWinner { id, .. } => {
+                    println!("{} {} is the bonus winner", time, self.name(id));
+                }
+
+                HostBroadcast::LifeLost { id, lives } => {
+                    println!("{} {} lost a life, {} left", time, self.name(id), lives);
+                }
+
+                HostBroadcast::EndNoseGoes { losers, payouts, .. } => {
+                    for loser in losers {
+                        println!("{} {} was knocked out", time, self.name(loser));
+                    }
+                    for payout in payouts {
+                        println!(
+                            "{} {} won {} bonus points, new score is {}",
+                            time,
+                            self.name(payout.id),
+                            payout.amount,
+                            payout.score,
+                        );
+                    }
+                    println!("{} Nose-goes ended", time);
+                }
+
+                HostBroadcast::AchievementUnlocked { id, title, .. } => {
+                    println!("{} {} unlocked {:?}", time, self.name(id), title);
+                }
+
+                HostBroadcast::UpdateWinner { holders } => {
+                    let names = holders.iter()
+                        .map(|&id| self.name(id))
+                        .collect::<Vec<_>>();
+                    if names.is_empty() {
+                        println!("{} Nobody has the crown", time);
+                    } else {
+                        println!("{} {} took the crown", time, names.join(" and "));
+                    }
+                }
+
+                _ => {}
+            }
+        }
+    }
+
+    /// Prints the final scores for the game.
+    fn print_results(&self) {
+        let crown = self.state.players()
+            .into_iter()
+            .filter(|player| player.has_crown)
+            .map(|player| player.id)
+            .collect::<Vec<_>>();
+
+        println!();
+        println!("Final scores:");
+        for player in self.state.rankings(None) {
+            let crown = if crown.contains(&player.id) { " 👑" } else { "" };
+            println!(
+                "    {:>3}. {:>8}  {}{}",
+                player.rank,
+                player.score,
+                self.name(player.id),
+                crown,
+            );
+        }
+        println!();
+    }
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: replay <path to event log>");
+            process::exit(1);
+        }
+    };
+
+    let events = match event_log::read(&path) {
+        Ok(events) => events,
+        Err(error) => {
+            eprintln!("Failed to read event log {}: {}", path, error);
+            process::exit(1);
+        }
+    };
+
+    let mut replay: Option<Replay> = None;
+    for event in events {
+        if let Input::Start = event.input {
+            if let Some(ref replay) = replay {
+                replay.print_results();
+            }
+
+            println!("=== Game started at {} ===", event.timestamp);
+            replay = Some(Replay::new(event.timestamp));
+            continue;
+        }
+
+        match replay {
+            Some(ref mut replay) => replay.apply(event),
+            None => eprintln!("Ignoring {:?}, it happened before the game started", event),
+        }
+    }
+
+    if let Some(ref replay) = replay {
+        replay.print_results();
+    }
+}
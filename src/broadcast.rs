@@ -2,10 +2,30 @@
 //!
 //! Broadcasts are split between host broadcasts and player broadcasts, based on what info each
 //! one needs.
+//!
+//! Each websocket server also keeps track of the websockets connected to it. Connections are
+//! pinged periodically, and any connection that stops responding (e.g. a phone that went to sleep
+//! without closing its socket) is dropped, so the connection counts only include clients that are
+//! actually still there.
+//!
+//! Each connection can pick its own wire format when connecting, see the [`encoding`] module for
+//! details. Broadcasts are encoded once for each format in use, then sent to each connection.
+//!
+//! [`encoding`]: ../encoding/index.html
 
+use achievements::Achievement;
+use api::RankedPlayer;
+use cosmetics::Cosmetics;
+use elimination::Elimination;
+use encoding::Encoding;
+use error::{self, Recover};
 use game::*;
-use std::collections::HashSet;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::mem;
 use std::sync::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
 use std::time::*;
 use ws;
@@ -26,6 +46,45 @@ pub enum HostBroadcast {
 
         /// The starting score for the player.
         score: usize,
+
+        /// How many nose-goes events the player can lose before they're knocked out.
+        lives: usize,
+
+        /// How the player's hippo looks.
+        cosmetics: Cosmetics,
+    },
+
+    /// A knocked-out player has rejoined the game with the same ID and name.
+    PlayerRespawn {
+        id: PlayerId,
+        name: String,
+
+        /// The player's score after the respawn penalty.
+        score: usize,
+
+        /// How many nose-goes events the player can lose before they're knocked out again.
+        lives: usize,
+
+        /// How the player's hippo looks.
+        cosmetics: Cosmetics,
+    },
+
+    /// A player has changed how their hippo looks.
+    PlayerCosmetics {
+        id: PlayerId,
+        cosmetics: Cosmetics,
+    },
+
+    /// A player has unlocked an achievement.
+    AchievementUnlocked {
+        id: PlayerId,
+        achievement: Achievement,
+
+        /// The achievement's display name.
+        title: &'static str,
+
+        /// How the achievement was unlocked.
+        description: &'static str,
     },
 
     /// A hippo has eaten a marble from their food pile.
@@ -39,11 +98,20 @@ pub enum HostBroadcast {
 
     /// A nose-goes event has begun, and the host should display the event.
     BeginNoseGoes {
-        /// The duration that the nose-goes event will last.
-        duration: Duration,
+        /// How long the nose-goes event will last from now, in milliseconds.
+        duration: u64,
+
+        /// When the nose-goes event started, in milliseconds since the Unix epoch (server time).
+        start_time: u64,
+
+        /// When the nose-goes event will end, in milliseconds since the Unix epoch (server time).
+        end_time: u64,
 
         /// The players that are participating in the event.
         players: HashSet<PlayerId>,
+
+        /// The rule that decides who is knocked out when the event ends.
+        elimination: Elimination,
     },
 
     /// The first player to tap during a nose-goes event has been selected to earn bonus points.
@@ -51,40 +119,138 @@ pub enum HostBroadcast {
         id: PlayerId,
     },
 
+    /// A player lost a nose-goes event but had a life to spare, so they're still in the game.
+    LifeLost {
+        id: PlayerId,
+
+        /// How many lives the player has left.
+        lives: usize,
+    },
+
     /// A nose-goes event has ended, and one or more players have been knocked out.
     EndNoseGoes {
-        /// The players that have been knocked out. Will always have at least one member.
+        /// The players that have been knocked out.
+        ///
+        /// This is empty if the event's elimination rule didn't knock anyone out (e.g. if everyone
+        /// tapped in time under the `MissedDeadline` rule).
         losers: HashSet<PlayerId>,
 
         /// The player that won the bonus points in the nose-goes round, if any.
         ///
         /// The first element of the tuple is the ID of the bonus winner, and the second element
-        /// is the player's new score.
-        bonus_winner: Option<(PlayerId, usize)>
+        /// is the player's new score. If the bonus was split, this is the first player in
+        /// `payouts`.
+        bonus_winner: Option<(PlayerId, usize)>,
+
+        /// Every player that got bonus points, in the order that they tapped.
+        payouts: Vec<BonusPayout>,
     },
 
-    /// A new player has taken the lead.
+    /// The crown has changed hands.
+    ///
+    /// `holders` are every player now wearing the crown, which is more than one if tied players
+    /// share it, or none if there are no players left.
     UpdateWinner {
-        id: PlayerId,
+        holders: Vec<PlayerId>,
+    },
+
+    /// The server is shutting down, and the websocket is about to be closed.
+    ServerShuttingDown {},
+
+    /// Several hippos have eaten since the last score batch.
+    ///
+    /// Only sent if score batching is enabled (see [`ScoreBatcher`]), in which case it replaces
+    /// `HippoEat`.
+    ///
+    /// [`ScoreBatcher`]: ./struct.ScoreBatcher.html
+    ScoreBatch {
+        /// One update for each player whose score changed, in the order the players first ate.
+        scores: Vec<ScoreUpdate>,
     },
+
+    /// Somebody has moved on or off the podium, or players on the podium have changed places.
+    ///
+    /// Only sent when the order changes, so the scores in `top` go stale as players keep eating.
+    RankingChanged {
+        /// The top players in rank order, up to the configured `podium_size`.
+        top: Vec<RankedPlayer>,
+    },
+}
+
+/// The change in a single player's score over a score batch.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreUpdate {
+    /// The ID for the player whose hippo ate.
+    pub id: PlayerId,
+
+    /// The player's total score.
+    pub score: usize,
+
+    /// How much the player's score went up since the last update for the player.
+    pub delta: usize,
+}
+
+/// The bonus points paid to a single player at the end of a nose-goes event.
+#[derive(Debug, Clone, Serialize)]
+pub struct BonusPayout {
+    /// The ID for the player that got the bonus.
+    pub id: PlayerId,
+
+    /// How many bonus points the player got.
+    pub amount: usize,
+
+    /// The player's total score, including the bonus.
+    pub score: usize,
 }
 
 /// A message to be broadcast to connected player clients.
 #[derive(Debug, Serialize)]
 pub enum PlayerBroadcast {
     /// A nose-goes event has begun, and the player should be prompted to participate.
-    BeginNoseGoes {},
+    BeginNoseGoes {
+        /// When the nose-goes event started, in milliseconds since the Unix epoch (server time).
+        start_time: u64,
+
+        /// When the nose-goes event will end, in milliseconds since the Unix epoch (server time).
+        end_time: u64,
+    },
 
     /// The first player to tap during a nose-goes event has been selected to earn bonus points.
     BonusWinner {
         id: PlayerId,
     },
 
+    /// A player has unlocked an achievement.
+    AchievementUnlocked {
+        id: PlayerId,
+        achievement: Achievement,
+
+        /// The achievement's display name.
+        title: &'static str,
+
+        /// How the achievement was unlocked.
+        description: &'static str,
+    },
+
+    /// A player lost a nose-goes event but had a life to spare, so they're still in the game.
+    ///
+    /// This is sent before the `EndNoseGoes` event for the nose-goes event.
+    LifeLost {
+        id: PlayerId,
+
+        /// How many lives the player has left.
+        lives: usize,
+    },
+
     /// A nose-goes event has finished, and one or more playeres have been knocked out.
     ///
-    /// One `PlayerLose` event will also be sent for each knocked-out player.
+    /// One `PlayerLose` event will also be sent for each knocked-out player, and one `LifeLost`
+    /// event for each player that lost but had a life to spare.
     EndNoseGoes {
-        bonus_winner: Option<(PlayerId, usize)>
+        bonus_winner: Option<(PlayerId, usize)>,
+
+        /// Every player that got bonus points, in the order that they tapped.
+        payouts: Vec<BonusPayout>,
     },
 
     /// A player has lost the game and has been removed.
@@ -94,18 +260,255 @@ pub enum PlayerBroadcast {
 
         /// The final score for the player that lost.
         score: usize,
+
+        /// When the player can respawn, in milliseconds since the Unix epoch (server time), or
+        /// `None` if respawning is turned off.
+        respawn_at: Option<u64>,
     },
 
-    /// A new player has taken the lead.
+    /// The crown has changed hands.
+    ///
+    /// `holders` are every player now wearing the crown, which is more than one if tied players
+    /// share it, or none if there are no players left.
     UpdateWinner {
-        id: PlayerId,
+        holders: Vec<PlayerId>,
     },
+
+    /// The server is shutting down, and the websocket is about to be closed.
+    ServerShuttingDown {},
+}
+
+/// The number of live websocket connections on each stream.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ConnectionCounts {
+    /// The number of connected host clients.
+    pub host: usize,
+
+    /// The number of connected player clients.
+    pub player: usize,
+}
+
+/// Information about a single connected websocket, used for reporting.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionSummary {
+    /// The ID the websocket server assigned to the connection.
+    pub id: u32,
+
+    /// How long ago the websocket connected, in milliseconds.
+    pub connected_for: u64,
+
+    /// How long ago we last heard anything from the websocket, in milliseconds.
+    pub idle_for: u64,
+
+    /// The wire format the websocket negotiated.
+    pub encoding: Encoding,
+
+    /// The estimated round trip time to the client in milliseconds, or `None` if the client
+    /// hasn't answered a ping yet.
+    pub rtt: Option<u64>,
+}
+
+/// What we know about a connected websocket.
+#[derive(Debug)]
+struct ConnectionInfo {
+    /// The sender for sending messages to just this websocket.
+    out: ws::Sender,
+
+    /// The wire format the websocket negotiated.
+    encoding: Encoding,
+
+    /// When the websocket connected.
+    connected_at: Instant,
+
+    /// When we last received a frame (of any kind) from the websocket.
+    last_seen: Instant,
+
+    /// The estimated round trip time to the client in milliseconds.
+    rtt: Option<u64>,
+}
+
+type ConnectionMap = Arc<Mutex<HashMap<u32, ConnectionInfo>>>;
+
+/// Coalesces `HippoEat` host broadcasts into `ScoreBatch` broadcasts.
+///
+/// When lots of players are tapping at once the host gets a `HippoEat` for nearly every tap, which
+/// is far more messages than it needs. The batcher holds on to score updates for up to `window`,
+/// then sends a single `ScoreBatch` with the latest score for each player.
+///
+/// Any other broadcast causes the pending batch to be sent first, so hosts always see score
+/// changes before the winner updates and nose-goes events that depend on them.
+#[derive(Debug)]
+pub struct ScoreBatcher {
+    /// How long to hold on to score updates, or `None` if batching is disabled.
+    window: Option<Duration>,
+
+    /// The pending score updates, in the order the players first ate.
+    pending: Vec<ScoreUpdate>,
+
+    /// When the pending batch needs to be sent.
+    deadline: Option<Instant>,
+
+    /// The last score sent to the hosts for each player, used to calculate score deltas.
+    scores: HashMap<PlayerId, usize>,
+}
+
+impl ScoreBatcher {
+    /// Creates a batcher that holds on to score updates for `window`.
+    ///
+    /// If `window` is `None` batching is disabled and all broadcasts are passed through unchanged.
+    pub fn new(window: Option<Duration>) -> ScoreBatcher {
+        ScoreBatcher {
+            window,
+            pending: Vec::new(),
+            deadline: None,
+            scores: HashMap::new(),
+        }
+    }
+
+    /// Returns when the pending batch needs to be sent, if there is one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Adds `broadcast` to the batcher, returning the broadcasts that are ready to be sent in the
+    /// order they should be sent.
+    pub fn push(&mut self, broadcast: HostBroadcast, now: Instant) -> Vec<HostBroadcast> {
+        let window = match self.window {
+            Some(window) => window,
+            None => return vec![broadcast],
+        };
+
+        match broadcast {
+            HostBroadcast::HippoEat { id, score } => {
+                let previous = self.scores.insert(id, score);
+
+                // If we haven't seen the player's score before (e.g. hosts were told about the
+                // player before the server restarted) the best we can do is count this as one
+                // point, the same as a `HippoEat` would have.
+                let delta = match previous {
+                    Some(previous) => score.saturating_sub(previous),
+                    None => 1,
+                };
+
+                match self.pending.iter().position(|update| update.id == id) {
+                    Some(index) => {
+                        self.pending[index].score = score;
+                        self.pending[index].delta += delta;
+                    }
+
+                    None => self.pending.push(ScoreUpdate { id, score, delta }),
+                }
+
+                if self.deadline.is_none() {
+                    self.deadline = Some(now + window);
+                }
+
+                Vec::new()
+            }
+
+            broadcast => {
+                // Keep track of scores that change outside of `HippoEat` so that deltas stay
+                // accurate.
+                match broadcast {
+                    HostBroadcast::PlayerRegister { id, score, .. }
+                    | HostBroadcast::PlayerRespawn { id, score, .. } => {
+                        self.scores.insert(id, score);
+                    }
+
+                    HostBroadcast::EndNoseGoes { ref losers, ref payouts, .. } => {
+                        for loser in losers {
+                            self.scores.remove(loser);
+                        }
+                        for payout in payouts {
+                            self.scores.insert(payout.id, payout.score);
+                        }
+                    }
+
+                    _ => {}
+                }
+
+                let mut ready = self.flush();
+                ready.push(broadcast);
+                ready
+            }
+        }
+    }
+
+    /// Returns the pending batch if its deadline has passed.
+    pub fn flush_due(&mut self, now: Instant) -> Vec<HostBroadcast> {
+        match self.deadline {
+            Some(deadline) if now >= deadline => self.flush(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the pending batch, if any, regardless of its deadline.
+    pub fn flush(&mut self) -> Vec<HostBroadcast> {
+        self.deadline = None;
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+
+        let scores = mem::replace(&mut self.pending, Vec::new());
+        vec![HostBroadcast::ScoreBatch { scores }]
+    }
+}
+
+/// Handles messages that clients send over a websocket.
+///
+/// Messages are handed off to a separate message thread rather than being handled on the
+/// websocket server's thread, so `handle` can block (e.g. on a round trip to the game loop)
+/// without holding up pings and broadcasts for every other connection. Messages from all
+/// connections are handled one at a time in the order they arrived.
+pub trait MessageHandler: Send + Sync {
+    /// Handles a message from a client, returning the reply to send back to that client, if any.
+    ///
+    /// Messages are decoded using the connection's encoding before being handed to the handler,
+    /// and the reply is encoded the same way. `message` is an error if the message couldn't be
+    /// decoded, so that the handler can reply with the error. `rtt` is the connection's estimated
+    /// round trip time in milliseconds, if it has been measured yet.
+    fn handle(&self, message: error::Result<Value>, rtt: Option<u64>) -> Option<Value>;
+}
+
+/// A message from a client, waiting to be handled on the message thread.
+struct IncomingMessage {
+    /// The decoded message, or the error if it couldn't be decoded.
+    message: error::Result<Value>,
+
+    /// The connection's estimated round trip time in milliseconds, if it has been measured yet.
+    rtt: Option<u64>,
+
+    /// The sender for replying to just the client that sent the message.
+    out: ws::Sender,
+
+    /// The wire format to encode the reply in.
+    encoding: Encoding,
+}
+
+/// A message sent from a `Broadcaster` to its broadcast thread.
+#[derive(Debug)]
+enum Message<T> {
+    /// Broadcast the value to all connected websockets.
+    Broadcast(T),
+
+    /// Close all connected websockets, then notify the sender.
+    Close(mpsc::Sender<()>),
 }
 
 /// Broadcasts messages to websocket subscribers.
 #[derive(Debug)]
 pub struct Broadcaster<T> {
-    inner: Mutex<mpsc::Sender<T>>,
+    inner: Mutex<mpsc::Sender<Message<T>>>,
+
+    /// The number of broadcasts that have been sent but not yet picked up by the broadcast
+    /// thread.
+    queue_depth: Arc<AtomicUsize>,
+
+    /// The websockets currently connected to the server, keyed by connection ID.
+    connections: ConnectionMap,
+
+    /// Whether the websocket server is currently listening for connections.
+    listening: Arc<AtomicBool>,
 }
 
 impl<T> Broadcaster<T> {
@@ -115,80 +518,624 @@ impl<T> Broadcaster<T> {
     /// there are no clients listening for the broadcast, the "broadcast" will still be vacuously
     /// successful.
     ///
-    /// # Panics
-    ///
-    /// This method will panic if the broadcast thread (started by calling
-    /// [`start_websocket_server`]) has panicked. The broadcast thread panicking indicates that
-    /// there is an error in the program (likely JSON serialization of the broadcast failed), so
-    /// there is no way to recover at that point, hence why `send` panics in turn.
+    /// If the broadcast thread (started by calling [`start_server`]) panics it is restarted by
+    /// its supervisor, and any broadcasts sent in the meantime are queued up until the new thread
+    /// picks them up. As such, a bug in the broadcast thread never takes down the sender.
     ///
-    /// [`start_websocket_server`]: ./fn.start_websocket_server.html
+    /// [`start_server`]: ./fn.start_server.html
     pub fn send(&self, broadcast: T) {
-        self.inner
-            // This function is the only place where the mutex is locked, so the mutex can only
-            // get poisoned if this function panics. This function will only panic if the mutex
-            // is poisoned or the broadcast thread has panicked, so either way we can't make
-            // broadcasts. As such, we can safely `expect` the lock operation to succeed.
-            .lock().expect("Somehow the broadcast mutex got poisoned")
+        // The broadcast receiver is owned by the supervisor thread, which never exits while a
+        // `Broadcaster` is alive, so sending should only fail if the supervisor itself couldn't
+        // spawn a thread. There's nothing the caller can do about that, so we log and move on.
+        //
+        // NOTE: The queue depth is incremented before sending so that the broadcast thread never
+        // sees the broadcast before it has been counted.
+        self.queue_depth.fetch_add(1, Ordering::SeqCst);
+        let result = self.inner.lock().recover("Broadcast sender").send(Message::Broadcast(broadcast));
+        if result.is_err() {
+            self.queue_depth.fetch_sub(1, Ordering::SeqCst);
+            error!("The broadcast supervisor has stopped, dropping broadcast");
+        }
+    }
+
+    /// Closes all connected websockets once every broadcast that has already been sent has been
+    /// delivered.
+    ///
+    /// Blocks until the websockets have been closed, or until `timeout` has passed. This is used
+    /// when the server is shutting down so that clients see a clean close instead of a dropped
+    /// connection.
+    pub fn close(&self, timeout: Duration) {
+        let (done_sender, done_receiver) = mpsc::channel();
+        let result = self.inner.lock().recover("Broadcast sender").send(Message::Close(done_sender));
+        if result.is_err() {
+            error!("The broadcast supervisor has stopped, unable to close websockets");
+            return;
+        }
+
+        if done_receiver.recv_timeout(timeout).is_err() {
+            warn!("Timed out waiting for websockets to close");
+        }
+    }
+
+    /// Returns the number of broadcasts waiting to be sent to the websockets.
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of websockets currently connected to the server.
+    ///
+    /// Connections that have stopped responding to pings are dropped, so this only counts clients
+    /// that are still alive.
+    pub fn connections(&self) -> usize {
+        self.connections.lock().recover("Websocket connections").len()
+    }
 
-            // The same goes for sending the broadcast: This will only return `Err` if the
-            // broadcast thread has panicked, in which case we can't recover anyway, so we may as
-            // well panic this thread while we're at it.
-            .send(broadcast).expect("The broadcast thread has crashed, can no longer make broadcasts");
+    /// Returns a summary of each websocket currently connected to the server, ordered by
+    /// connection ID.
+    pub fn connection_summaries(&self) -> Vec<ConnectionSummary> {
+        let now = Instant::now();
+        let connections = self.connections.lock().recover("Websocket connections");
+        let mut summaries = connections.iter()
+            .map(|(&id, info)| ConnectionSummary {
+                id,
+                connected_for: as_millis(now.duration_since(info.connected_at)),
+                idle_for: as_millis(now.duration_since(info.last_seen)),
+                encoding: info.encoding,
+                rtt: info.rtt,
+            })
+            .collect::<Vec<_>>();
+        summaries.sort_by_key(|summary| summary.id);
+        summaries
+    }
+
+    /// Returns `true` if the websocket server is listening for connections.
+    ///
+    /// This is `false` until the server has bound its port, and while the server is being
+    /// restarted after a failure.
+    pub fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::SeqCst)
     }
 }
 
+/// How long to wait before restarting a crashed thread or websocket server.
+///
+/// NOTE: This should be a `const`, but you can't make a const `Duration`.
+fn restart_delay() -> Duration {
+    Duration::from_millis(1_000)
+}
+
+/// How often to ping each websocket to check that it's still alive.
+///
+/// NOTE: This should be a `const`, but you can't make a const `Duration`.
+fn ping_interval() -> Duration {
+    Duration::from_millis(5_000)
+}
+
+/// How long a websocket can go without sending us anything before it's considered dead.
+///
+/// Browsers answer pings automatically, so a live client is never idle for much longer than
+/// `ping_interval`.
+///
+/// NOTE: This should be a `const`, but you can't make a const `Duration`.
+fn connection_timeout() -> Duration {
+    Duration::from_millis(15_000)
+}
+
 /// Spawns the websocket server, returning a sender for broadcasting messages.
 ///
-/// The websocket server is run on a separate thread listening on port 6768. This is a necessary
-/// workaround because Rocket doesn't yet directly support websockets. The returned `mpsc::Sender`
-/// allows for API messages to be sent from any number of threads to the websocket server, at which
-/// point they will be broadcast to any connected clients.
-pub fn start_server<T>(server_address: &'static str) -> Arc<Broadcaster<T>>
+/// The websocket server is run on a separate thread listening on `server_address`. This is a
+/// necessary workaround because Rocket doesn't yet directly support websockets. The returned
+/// `Broadcaster` allows for API messages to be sent from any number of threads to the websocket
+/// server, at which point they will be broadcast to any connected clients.
+///
+/// If `handler` is given, any messages that clients send over the websocket are passed to it on a
+/// separate message thread, and its replies are sent back to the client that sent the message.
+/// Otherwise messages from clients are ignored.
+///
+/// The websocket server, the broadcast thread and the message thread are all supervised: If one
+/// panics it is restarted, and if the websocket server fails to start it is retried.
+pub fn start_server<T>(
+    server_address: &'static str,
+    handler: Option<Arc<MessageHandler>>,
+) -> Arc<Broadcaster<T>>
 where
     T: 'static + ::serde::ser::Serialize + Send,
 {
     // Create a sender/reciever pair so that the Rocket server can send messages to be broadcast
-    // to all websocket listeners.
+    // to all websocket listeners. The receiver is shared so that the broadcast thread can pick up
+    // where it left off if it gets restarted.
     let (broadcast_sender, broadcast_receiver) = mpsc::channel();
-    let (socket_sender, socket_receiver) = mpsc::sync_channel(1);
-
-    // Spawn a thread to host the websocket server. The websocket server must listen on a different
-    // port than the Rocket server, so we use 6768. As websocket connections are opened, the sender
-    // end of the connection is saved so that
-    thread::spawn(move || {
-        let mut has_sent = false;
-        ws::listen(server_address, |ws_sender| {
-            // Send the first `Sender` we get to the broadcast thread. It doesn't matter which
-            // one gets sent, any `Sender` can be used to broadcast a message to all websockets
-            // connected to the server.
-            if !has_sent {
-                socket_sender.send(ws_sender).expect("Failed to send socket sender to broadcast thread");
-                has_sent = true;
-            }
+    let broadcast_receiver = Arc::new(Mutex::new(broadcast_receiver));
 
-            // Create a noop handler for the connection. We don't care about listening for messages
-            // or doing any advanced handling (for now, at least), so we don't need the handler to
-            // to do anything.
-            |_| { Ok(()) }
-        }).expect("Something failed in websocket server");
-    });
+    // The websocket server publishes its broadcast `Sender` here every time it (re)starts, that
+    // way the broadcast thread always uses the sender for the currently running server.
+    let socket = Arc::new(Mutex::new(None));
 
-    // Spawn a thread to read broadcasts and multiplex them to the websockets.
-    thread::spawn(move || {
-        // Receive the socket that was sent from the websocket thread. We can use any `Sender` to
-        // broadcast to all socket connections, so we only need to get one.
-        let socket = socket_receiver.recv().expect("Failed to receive a websocket sender ;__;");
+    let queue_depth = Arc::new(AtomicUsize::new(0));
+    let connections = Arc::new(Mutex::new(HashMap::new()));
+    let listening = Arc::new(AtomicBool::new(false));
 
-        // Pull broadcasts from the channel, serialize each one to JSON, then broadcast it to all
-        // websockets connected to the server.
-        for broadcast in broadcast_receiver {
-            let payload = ::serde_json::to_string(&broadcast).expect("Failed to serialize payload");
-            socket.broadcast(payload.clone()).unwrap();
-        }
+    // Messages from clients are queued up for the message thread, so that the websocket server
+    // never waits on the handler. Like the broadcast receiver, the message receiver is shared so
+    // that a restarted message thread picks up where the last one left off.
+    let messages = handler.map(|handler| {
+        let (message_sender, message_receiver) = mpsc::channel();
+        let message_receiver = Arc::new(Mutex::new(message_receiver));
+        thread::spawn(move || {
+            supervise("websocket messages", move || {
+                run_message_loop(&message_receiver, &*handler)
+            });
+        });
+
+        Mutex::new(message_sender)
     });
 
+    {
+        let socket = socket.clone();
+        let connections = connections.clone();
+        let listening = listening.clone();
+        thread::spawn(move || {
+            supervise("websocket server", move || {
+                run_websocket_server(server_address, &socket, &connections, &listening, &messages)
+            });
+        });
+    }
+
+    {
+        let connections = connections.clone();
+        let queue_depth = queue_depth.clone();
+        thread::spawn(move || {
+            supervise("broadcast", move || {
+                run_broadcast_loop(&broadcast_receiver, &socket, &connections, &queue_depth)
+            });
+        });
+    }
+
     Arc::new(Broadcaster {
         inner: Mutex::new(broadcast_sender),
+        queue_depth,
+        connections,
+        listening,
     })
 }
+
+/// Runs `body` on a worker thread, restarting the worker if it panics.
+///
+/// Returns once `body` returns normally.
+fn supervise<F>(name: &'static str, body: F)
+where
+    F: 'static + Fn() + Send + Sync,
+{
+    let body = Arc::new(body);
+    loop {
+        let worker_body = body.clone();
+        let result = thread::Builder::new()
+            .name(name.into())
+            .spawn(move || (*worker_body)())
+            .map(|worker| worker.join());
+
+        match result {
+            Ok(Ok(())) => return,
+            Ok(Err(_)) => error!("The {} thread panicked, restarting it", name),
+            Err(error) => error!("Failed to spawn the {} thread, retrying: {}", name, error),
+        }
+
+        thread::sleep(restart_delay());
+    }
+}
+
+/// Runs the websocket server on the current thread, restarting it if it fails.
+///
+/// Never returns.
+fn run_websocket_server(
+    server_address: &str,
+    socket: &Mutex<Option<ws::Sender>>,
+    connections: &ConnectionMap,
+    listening: &AtomicBool,
+    messages: &Option<Mutex<mpsc::Sender<IncomingMessage>>>,
+) {
+    loop {
+        let result = ws::WebSocket::new(|out| {
+            let messages = messages.as_ref()
+                .map(|messages| messages.lock().recover("Websocket message sender").clone());
+            Connection::new(out, connections.clone(), messages)
+        })
+            .and_then(|server| server.bind(server_address))
+            .and_then(|server| {
+                // Any `Sender` can be used to broadcast a message to all websockets connected to
+                // the server, so we hand the server's broadcaster to the broadcast thread.
+                *socket.lock().recover("Websocket sender") = Some(server.broadcaster());
+                listening.store(true, Ordering::SeqCst);
+                server.run()
+            });
+
+        listening.store(false, Ordering::SeqCst);
+        if let Err(error) = result {
+            error!("Websocket server on {} failed, restarting: {}", server_address, error);
+        }
+
+        *socket.lock().recover("Websocket sender") = None;
+        thread::sleep(restart_delay());
+    }
+}
+
+/// Passes each message from the channel to `handler`, sending any reply back to the client that
+/// sent the message.
+///
+/// Returns once every connection and the websocket server have been dropped.
+fn run_message_loop(
+    message_receiver: &Mutex<mpsc::Receiver<IncomingMessage>>,
+    handler: &MessageHandler,
+) {
+    let message_receiver = message_receiver.lock().recover("Websocket message receiver");
+    for incoming in message_receiver.iter() {
+        let reply = match handler.handle(incoming.message, incoming.rtt) {
+            Some(reply) => reply,
+            None => continue,
+        };
+
+        // The client may have disconnected while the message was being handled, in which case
+        // there's nobody left to reply to.
+        match incoming.encoding.encode(&reply) {
+            Ok(reply) => {
+                if let Err(error) = incoming.out.send(reply) {
+                    warn!("Failed to send websocket reply: {}", error);
+                }
+            }
+            Err(error) => error!("Failed to encode reply, dropping it: {:?}", error),
+        }
+    }
+}
+
+/// Pulls broadcasts from the channel, encodes each one in every wire format that's in use, then
+/// sends it to all websockets connected to the server.
+///
+/// Returns once every `Broadcaster` has been dropped.
+fn run_broadcast_loop<T>(
+    broadcast_receiver: &Mutex<mpsc::Receiver<Message<T>>>,
+    socket: &Mutex<Option<ws::Sender>>,
+    connections: &ConnectionMap,
+    queue_depth: &AtomicUsize,
+)
+where
+    T: ::serde::ser::Serialize,
+{
+    let broadcast_receiver = broadcast_receiver.lock().recover("Broadcast receiver");
+    for message in broadcast_receiver.iter() {
+        let broadcast = match message {
+            Message::Broadcast(broadcast) => broadcast,
+            Message::Close(done) => {
+                if let Some(ref socket) = *socket.lock().recover("Websocket sender") {
+                    if let Err(error) = socket.close(ws::CloseCode::Away) {
+                        warn!("Failed to close websockets: {}", error);
+                    }
+                }
+
+                let _ = done.send(());
+                continue;
+            }
+        };
+        queue_depth.fetch_sub(1, Ordering::SeqCst);
+
+        // Encode the broadcast once for each encoding in use, rather than once per connection.
+        // If the websocket server is being restarted there's nobody connected, so the broadcast
+        // is dropped.
+        let connections = connections.lock().recover("Websocket connections");
+        let encodings = connections.values()
+            .map(|info| info.encoding)
+            .collect::<HashSet<_>>();
+        let mut payloads = HashMap::new();
+        for encoding in encodings {
+            match encoding.encode(&broadcast) {
+                Ok(payload) => { payloads.insert(encoding, payload); }
+                Err(error) => error!("Failed to encode broadcast, dropping it: {:?}", error),
+            }
+        }
+
+        for info in connections.values() {
+            if let Some(payload) = payloads.get(&info.encoding) {
+                if let Err(error) = info.out.send(payload.clone()) {
+                    let id = info.out.connection_id();
+                    warn!("Failed to broadcast to websocket {}: {}", id, error);
+                }
+            }
+        }
+    }
+}
+
+/// The timeout token used for heartbeats.
+const HEARTBEAT: ws::util::Token = ws::util::Token(1);
+
+/// The handler for a single websocket connection.
+///
+/// Messages from the client are queued up for the server's `MessageHandler`, if it has one.
+/// Otherwise the handler only negotiates the connection's wire format, keeps the connection alive,
+/// and keeps track of it in the server's connection map. Every `ping_interval` the handler checks
+/// when it last heard from the client: If it's been longer than `connection_timeout` the
+/// connection is closed, otherwise the client is pinged again. The connection is removed from the map when the
+/// handler is dropped, which happens however the connection ends.
+///
+/// The time it takes for the client to answer each ping is used to estimate the connection's round
+/// trip time, which is passed along to the `MessageHandler` so that it can compensate for slow
+/// connections.
+struct Connection {
+    out: ws::Sender,
+    connections: ConnectionMap,
+
+    /// Where to queue up messages from the client for the message thread, if the server handles
+    /// messages.
+    messages: Option<mpsc::Sender<IncomingMessage>>,
+
+    /// The wire format negotiated for the connection.
+    encoding: Encoding,
+
+    /// Whether we've already tried to close the connection because the client stopped responding.
+    closing: bool,
+
+    /// When we sent the ping that the client hasn't answered yet, if any.
+    ping_sent: Option<Instant>,
+
+    /// The smoothed round trip time to the client in milliseconds, if it has been measured yet.
+    rtt: Option<u64>,
+}
+
+impl Connection {
+    fn new(
+        out: ws::Sender,
+        connections: ConnectionMap,
+        messages: Option<mpsc::Sender<IncomingMessage>>,
+    ) -> Connection {
+        let now = Instant::now();
+        connections.lock().recover("Websocket connections").insert(
+            out.connection_id(),
+            ConnectionInfo {
+                out: out.clone(),
+                encoding: Encoding::default(),
+                connected_at: now,
+                last_seen: now,
+                rtt: None,
+            },
+        );
+
+        Connection {
+            out,
+            connections,
+            messages,
+            encoding: Encoding::default(),
+            closing: false,
+            ping_sent: None,
+            rtt: None,
+        }
+    }
+
+    /// Returns how long it has been since we last heard from the client.
+    fn idle_time(&self) -> Duration {
+        let connections = self.connections.lock().recover("Websocket connections");
+        match connections.get(&self.out.connection_id()) {
+            Some(info) => info.last_seen.elapsed(),
+            None => Duration::from_millis(0),
+        }
+    }
+
+    fn schedule_heartbeat(&self) -> ws::Result<()> {
+        self.out.timeout(as_millis(ping_interval()), HEARTBEAT)
+    }
+
+    /// Updates the round trip estimate after the client answers a ping.
+    ///
+    /// Samples are smoothed the same way TCP does it, so that a single slow pong doesn't throw the
+    /// estimate off too much.
+    fn record_pong(&mut self, now: Instant) -> Option<u64> {
+        let sent = match self.ping_sent.take() {
+            Some(sent) => sent,
+            None => return self.rtt,
+        };
+
+        let sample = as_millis(duration_until(sent, now));
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => (rtt * 7 + sample) / 8,
+            None => sample,
+        });
+        self.rtt
+    }
+}
+
+impl ws::Handler for Connection {
+    fn on_request(&mut self, request: &ws::Request) -> ws::Result<ws::Response> {
+        let mut response = ws::Response::from_request(request)?;
+
+        // Use the first subprotocol requested by the client that we support. If the client didn't
+        // request any that we support we stick with JSON and don't pick a subprotocol.
+        let negotiated = request.protocols()?
+            .into_iter()
+            .filter_map(|protocol| {
+                Encoding::from_protocol(protocol).map(|encoding| (protocol, encoding))
+            })
+            .next();
+        if let Some((protocol, encoding)) = negotiated {
+            response.set_protocol(protocol);
+            self.encoding = encoding;
+
+            let mut connections = self.connections.lock().recover("Websocket connections");
+            if let Some(info) = connections.get_mut(&self.out.connection_id()) {
+                info.encoding = encoding;
+            }
+        }
+
+        Ok(response)
+    }
+
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        self.schedule_heartbeat()
+    }
+
+    fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+        let messages = match self.messages {
+            Some(ref messages) => messages,
+            None => return Ok(()),
+        };
+
+        let incoming = IncomingMessage {
+            message: self.encoding.decode(message),
+            rtt: self.rtt,
+            out: self.out.clone(),
+            encoding: self.encoding,
+        };
+
+        // The message receiver is owned by the message thread's supervisor, which never exits, so
+        // this should only fail if the supervisor couldn't spawn a thread.
+        if messages.send(incoming).is_err() {
+            error!("The websocket message thread has stopped, dropping message");
+        }
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> ws::Result<Option<ws::Frame>> {
+        let now = Instant::now();
+        let rtt = if frame.opcode() == ws::OpCode::Pong { self.record_pong(now) } else { self.rtt };
+
+        // Any frame shows that the client is still there, not just pongs.
+        let mut connections = self.connections.lock().recover("Websocket connections");
+        if let Some(info) = connections.get_mut(&self.out.connection_id()) {
+            info.last_seen = now;
+            info.rtt = rtt;
+        }
+
+        Ok(Some(frame))
+    }
+
+    fn on_timeout(&mut self, event: ws::util::Token) -> ws::Result<()> {
+        if event != HEARTBEAT {
+            return Ok(());
+        }
+
+        // If the client didn't answer our close frame either, the connection is half-open and
+        // will never close on its own. Returning an I/O error makes the server drop it outright.
+        if self.closing {
+            return Err(ws::Error::new(
+                ws::ErrorKind::Io(io::Error::new(io::ErrorKind::TimedOut, "Websocket timed out")),
+                "The client didn't respond to the close handshake",
+            ));
+        }
+
+        if self.idle_time() > connection_timeout() {
+            info!("Websocket {} stopped responding, closing it", self.out.connection_id());
+            self.closing = true;
+            self.out.close(ws::CloseCode::Away)?;
+        } else {
+            self.out.ping(Vec::new())?;
+            self.ping_sent = Some(Instant::now());
+        }
+
+        self.schedule_heartbeat()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.connections.lock().recover("Websocket connections").remove(&self.out.connection_id());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use game::PlayerId;
+    use std::time::{Duration, Instant};
+    use super::*;
+
+    fn eat(id: PlayerId, score: usize) -> HostBroadcast {
+        HostBroadcast::HippoEat { id, score }
+    }
+
+    /// Returns the `(id, score, delta)` of each update if `broadcast` is a `ScoreBatch`.
+    fn batch(broadcast: &HostBroadcast) -> Vec<(PlayerId, usize, usize)> {
+        match *broadcast {
+            HostBroadcast::ScoreBatch { ref scores } => {
+                scores.iter().map(|update| (update.id, update.score, update.delta)).collect()
+            }
+
+            ref broadcast => panic!("Expected a score batch, got {:?}", broadcast),
+        }
+    }
+
+    #[test]
+    fn no_window_passes_broadcasts_through() {
+        let now = Instant::now();
+        let id = PlayerId::new();
+        let mut batcher = ScoreBatcher::new(None);
+
+        let ready = batcher.push(eat(id, 1), now);
+        assert_eq!(ready.len(), 1);
+        match ready[0] {
+            HostBroadcast::HippoEat { id: eaten, score: 1 } => assert_eq!(eaten, id),
+            ref broadcast => panic!("Expected the HippoEat to pass through, got {:?}", broadcast),
+        }
+        assert!(batcher.deadline().is_none());
+        assert!(batcher.flush().is_empty());
+    }
+
+    #[test]
+    fn scores_are_held_until_the_deadline() {
+        let now = Instant::now();
+        let window = Duration::from_millis(100);
+        let id = PlayerId::new();
+        let mut batcher = ScoreBatcher::new(Some(window));
+
+        assert!(batcher.push(eat(id, 1), now).is_empty());
+        assert!(batcher.push(eat(id, 2), now + window / 2).is_empty());
+        assert_eq!(batcher.deadline(), Some(now + window));
+        assert!(batcher.flush_due(now + window - Duration::from_millis(1)).is_empty());
+
+        let ready = batcher.flush_due(now + window);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(batch(&ready[0]), vec![(id, 2, 2)]);
+        assert!(batcher.deadline().is_none());
+        assert!(batcher.flush_due(now + window * 2).is_empty());
+    }
+
+    #[test]
+    fn batches_are_in_the_order_players_first_ate() {
+        let now = Instant::now();
+        let first = PlayerId::new();
+        let second = PlayerId::new();
+        let mut batcher = ScoreBatcher::new(Some(Duration::from_millis(100)));
+
+        batcher.push(eat(first, 1), now);
+        batcher.push(eat(second, 1), now);
+        batcher.push(eat(first, 2), now);
+
+        assert_eq!(batch(&batcher.flush()[0]), vec![(first, 2, 2), (second, 1, 1)]);
+    }
+
+    #[test]
+    fn other_broadcasts_flush_the_batch_first() {
+        let now = Instant::now();
+        let id = PlayerId::new();
+        let mut batcher = ScoreBatcher::new(Some(Duration::from_millis(100)));
+
+        batcher.push(eat(id, 1), now);
+        let ready = batcher.push(HostBroadcast::UpdateWinner { holders: vec![id] }, now);
+
+        assert_eq!(ready.len(), 2);
+        assert_eq!(batch(&ready[0]), vec![(id, 1, 1)]);
+        match ready[1] {
+            HostBroadcast::UpdateWinner { ref holders } => assert_eq!(*holders, vec![id]),
+            ref broadcast => panic!("Expected UpdateWinner, got {:?}", broadcast),
+        }
+        assert!(batcher.deadline().is_none());
+    }
+
+    #[test]
+    fn deltas_are_since_the_last_batch() {
+        let now = Instant::now();
+        let id = PlayerId::new();
+        let mut batcher = ScoreBatcher::new(Some(Duration::from_millis(100)));
+
+        batcher.push(eat(id, 5), now);
+        assert_eq!(batch(&batcher.flush()[0]), vec![(id, 5, 1)]);
+
+        batcher.push(eat(id, 8), now);
+        assert_eq!(batch(&batcher.flush()[0]), vec![(id, 8, 3)]);
+    }
+}
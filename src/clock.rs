@@ -0,0 +1,53 @@
+//! Converting the server's monotonic time into wall-clock time for clients.
+//!
+//! The game rules run on `Instant`s, which can't be sent to clients. Broadcasts that include a
+//! time (e.g. when a nose-goes event ends) convert it to milliseconds since the Unix epoch using
+//! a [`Clock`], and clients use the `/api/v1/time` endpoint (which uses the same `Clock`) to work
+//! out how far their own clock is from the server's.
+//!
+//! The clock is anchored once, when the server starts, so wall-clock adjustments on the server
+//! don't make broadcast times jump around mid-game.
+//!
+//! [`Clock`]: ./struct.Clock.html
+
+use event_log;
+use game::as_millis;
+use std::time::*;
+
+/// Maps `Instant`s to milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    /// The instant that the clock was anchored at.
+    instant: Instant,
+
+    /// The wall-clock time at `instant`, in milliseconds since the Unix epoch.
+    unix_millis: u64,
+}
+
+impl Clock {
+    /// Creates a clock anchored to the current time.
+    pub fn new() -> Clock {
+        Clock::anchored(Instant::now(), event_log::unix_millis(SystemTime::now()))
+    }
+
+    /// Creates a clock where `instant` is `unix_millis` milliseconds since the Unix epoch.
+    ///
+    /// This is used when replaying a game, so that broadcast times match the event log.
+    pub fn anchored(instant: Instant, unix_millis: u64) -> Clock {
+        Clock { instant, unix_millis }
+    }
+
+    /// Converts `instant` to milliseconds since the Unix epoch.
+    pub fn unix_millis(&self, instant: Instant) -> u64 {
+        if instant >= self.instant {
+            self.unix_millis + as_millis(instant - self.instant)
+        } else {
+            self.unix_millis.saturating_sub(as_millis(self.instant - instant))
+        }
+    }
+
+    /// Returns the current time in milliseconds since the Unix epoch.
+    pub fn now(&self) -> u64 {
+        self.unix_millis(Instant::now())
+    }
+}
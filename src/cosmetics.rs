@@ -0,0 +1,198 @@
+//! Cosmetic options that players can pick to make their hippo stand out on the host.
+//!
+//! The catalog of options is defined here on the server so that every host draws the same hippos,
+//! and so that clients can only pick options that exist. Clients fetch the catalog from
+//! `GET /api/v1/cosmetics` and pick from it with `POST /api/v1/cosmetics/<id>`, or pick their
+//! starting options when registering with `POST /api/v1/register-player`.
+//!
+//! Some options are locked until the player's score reaches a milestone (`unlock_score`). Options
+//! are checked against the highest score the player has ever had, so once an option is unlocked
+//! it stays unlocked even if the player's score drops later (e.g. after respawning).
+
+use error::*;
+use rand::{self, Rng};
+
+/// A single option in the catalog.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CosmeticItem {
+    /// The ID that clients use to pick the option.
+    pub id: &'static str,
+
+    /// The option's display name.
+    pub name: &'static str,
+
+    /// The score a player needs to pick the option, or 0 if it's always available.
+    pub unlock_score: usize,
+}
+
+/// The colors a hippo can be.
+pub static COLORS: &'static [CosmeticItem] = &[
+    CosmeticItem { id: "grey", name: "Classic Grey", unlock_score: 0 },
+    CosmeticItem { id: "pink", name: "Bubblegum Pink", unlock_score: 0 },
+    CosmeticItem { id: "blue", name: "River Blue", unlock_score: 0 },
+    CosmeticItem { id: "green", name: "Swamp Green", unlock_score: 0 },
+    CosmeticItem { id: "purple", name: "Royal Purple", unlock_score: 100 },
+    CosmeticItem { id: "gold", name: "Solid Gold", unlock_score: 500 },
+];
+
+/// The hats a hippo can wear. Hippos don't have to wear a hat.
+pub static HATS: &'static [CosmeticItem] = &[
+    CosmeticItem { id: "bow", name: "Bow", unlock_score: 0 },
+    CosmeticItem { id: "top-hat", name: "Top Hat", unlock_score: 50 },
+    CosmeticItem { id: "party-hat", name: "Party Hat", unlock_score: 150 },
+    CosmeticItem { id: "pirate-hat", name: "Pirate Hat", unlock_score: 300 },
+    CosmeticItem { id: "wizard-hat", name: "Wizard Hat", unlock_score: 750 },
+];
+
+/// The patterns a hippo's skin can have.
+pub static SKINS: &'static [CosmeticItem] = &[
+    CosmeticItem { id: "plain", name: "Plain", unlock_score: 0 },
+    CosmeticItem { id: "spotted", name: "Spotted", unlock_score: 200 },
+    CosmeticItem { id: "striped", name: "Striped", unlock_score: 400 },
+    CosmeticItem { id: "robot", name: "Robot", unlock_score: 1000 },
+];
+
+/// The full catalog of cosmetic options, as sent by the `/cosmetics` endpoint.
+#[derive(Debug, Serialize, Responder)]
+pub struct CosmeticCatalog {
+    pub colors: &'static [CosmeticItem],
+    pub hats: &'static [CosmeticItem],
+    pub skins: &'static [CosmeticItem],
+}
+
+impl CosmeticCatalog {
+    pub fn new() -> CosmeticCatalog {
+        CosmeticCatalog {
+            colors: COLORS,
+            hats: HATS,
+            skins: SKINS,
+        }
+    }
+}
+
+/// The cosmetic options a player has picked.
+///
+/// Each option is the `id` of an item in the catalog.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromData)]
+pub struct Cosmetics {
+    pub color: String,
+
+    #[serde(default)]
+    pub hat: Option<String>,
+
+    pub skin: String,
+}
+
+impl Cosmetics {
+    /// Picks a random color for a new player, with no hat and a plain skin.
+    pub fn random() -> Cosmetics {
+        let colors = COLORS.iter()
+            .filter(|item| item.unlock_score == 0)
+            .collect::<Vec<_>>();
+        let color = rand::thread_rng().choose(&colors).map(|item| item.id).unwrap_or("grey");
+
+        Cosmetics {
+            color: color.into(),
+            hat: None,
+            skin: "plain".into(),
+        }
+    }
+
+    /// Checks that every option is in the catalog, and has been unlocked by a player whose best
+    /// score is `score` points.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(InvalidRequest)` if an option isn't in the catalog, or `Err(CosmeticLocked)`
+    /// if the player's score is too low for an option.
+    pub fn validate(&self, score: usize) -> Result<()> {
+        check(COLORS, "color", &self.color, score)?;
+        if let Some(ref hat) = self.hat {
+            check(HATS, "hat", hat, score)?;
+        }
+        check(SKINS, "skin", &self.skin, score)
+    }
+}
+
+impl Default for Cosmetics {
+    fn default() -> Cosmetics {
+        Cosmetics {
+            color: "grey".into(),
+            hat: None,
+            skin: "plain".into(),
+        }
+    }
+}
+
+/// Checks that `id` is one of `items` and has been unlocked at `score`.
+///
+/// `slot` names the kind of item, for the error message.
+fn check(items: &[CosmeticItem], slot: &str, id: &str, score: usize) -> Result<()> {
+    match items.iter().find(|item| item.id == id) {
+        Some(item) if item.unlock_score > score => {
+            Err(Error::CosmeticLocked {
+                item: id.into(),
+                unlock_score: item.unlock_score,
+            })
+        }
+        Some(_) => Ok(()),
+        None => Err(Error::InvalidRequest(format!("Unknown {} {:?}", slot, id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cosmetics(color: &str, hat: Option<&str>, skin: &str) -> Cosmetics {
+        Cosmetics {
+            color: color.into(),
+            hat: hat.map(Into::into),
+            skin: skin.into(),
+        }
+    }
+
+    #[test]
+    fn free_options_are_always_available() {
+        assert!(Cosmetics::default().validate(0).is_ok());
+        assert!(cosmetics("pink", Some("bow"), "plain").validate(0).is_ok());
+
+        for _ in 0 .. 10 {
+            assert!(Cosmetics::random().validate(0).is_ok());
+        }
+    }
+
+    #[test]
+    fn unknown_options_are_invalid() {
+        let invalid = [
+            cosmetics("plaid", None, "plain"),
+            cosmetics("grey", Some("crown"), "plain"),
+            cosmetics("grey", None, "scaly"),
+        ];
+        for cosmetics in &invalid {
+            match cosmetics.validate(10_000) {
+                Err(Error::InvalidRequest(_)) => {}
+                result => panic!("Expected {:?} to be invalid, got {:?}", cosmetics, result),
+            }
+        }
+    }
+
+    #[test]
+    fn locked_options_need_the_unlock_score() {
+        let wizard = cosmetics("grey", Some("wizard-hat"), "plain");
+        match wizard.validate(749) {
+            Err(Error::CosmeticLocked { ref item, unlock_score: 750 }) if item == "wizard-hat" => {}
+            result => panic!("Expected the wizard hat to be locked, got {:?}", result),
+        }
+        assert!(wizard.validate(750).is_ok());
+    }
+
+    #[test]
+    fn options_unlocked_by_a_best_score_stay_unlocked() {
+        // A player that reached 500 points and dropped back to 250 (e.g. after respawning) is
+        // checked against their best score, so the gold color is still available to them.
+        let gold = cosmetics("gold", None, "plain");
+        assert!(gold.validate(250).is_err());
+        assert!(gold.validate(500).is_ok());
+    }
+}
@@ -0,0 +1,338 @@
+//! Rules for deciding who gets knocked out at the end of a nose-goes event.
+//!
+//! When a nose-goes event ends, the taps are put in the order they happened (see
+//! `game::TapTiming`) and handed to an [`EliminationRule`] along with the players that didn't tap
+//! at all. The rule picks the losers.
+//!
+//! Which rule applies is picked when each event starts. The server cycles through the rules listed
+//! in the `elimination_rules` option in `Rocket.toml`, e.g.:
+//!
+//! ```toml
+//! elimination_rules = "last-tapper, bottom:2, percent:25, missed-deadline"
+//! ```
+//!
+//! An admin can also pick the rule for the next event with `POST /admin/nose-goes/elimination`.
+//!
+//! [`EliminationRule`]: ./trait.EliminationRule.html
+
+use error::{self, Error};
+use game::PlayerId;
+use std::cmp;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Decides which players are knocked out at the end of a nose-goes event.
+pub trait EliminationRule {
+    /// Returns the players to knock out.
+    ///
+    /// `taps` are the players that tapped, in the order that they tapped. `missed` are the players
+    /// that were part of the event but didn't tap before it ended.
+    fn losers(&self, taps: &[PlayerId], missed: &HashSet<PlayerId>) -> HashSet<PlayerId>;
+
+    /// Returns whether the event can end as soon as at most one player hasn't tapped.
+    ///
+    /// This is only true for rules where the last player standing loses whether or not they tap,
+    /// since the outcome can't change by waiting for them.
+    fn ends_when_one_remains(&self) -> bool {
+        true
+    }
+}
+
+/// Knocks out everyone that didn't tap, or the last player to tap if everyone tapped.
+///
+/// This is the original nose-goes rule.
+#[derive(Debug, Clone, Copy)]
+pub struct LastTapper;
+
+impl EliminationRule for LastTapper {
+    fn losers(&self, taps: &[PlayerId], missed: &HashSet<PlayerId>) -> HashSet<PlayerId> {
+        slowest(taps, missed, 1)
+    }
+}
+
+/// Knocks out the `count` slowest players.
+#[derive(Debug, Clone, Copy)]
+pub struct BottomTappers {
+    pub count: usize,
+}
+
+impl EliminationRule for BottomTappers {
+    fn losers(&self, taps: &[PlayerId], missed: &HashSet<PlayerId>) -> HashSet<PlayerId> {
+        slowest(taps, missed, self.count)
+    }
+}
+
+/// Knocks out the slowest `percent` of the players in the event, rounded up.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldPercentage {
+    pub percent: u32,
+}
+
+impl EliminationRule for FieldPercentage {
+    fn losers(&self, taps: &[PlayerId], missed: &HashSet<PlayerId>) -> HashSet<PlayerId> {
+        let field = taps.len() + missed.len();
+        let count = (field * self.percent as usize + 99) / 100;
+        slowest(taps, missed, count)
+    }
+}
+
+/// Only knocks out the players that didn't tap before the event ended.
+///
+/// If everyone taps in time, nobody is knocked out.
+#[derive(Debug, Clone, Copy)]
+pub struct MissedDeadline;
+
+impl EliminationRule for MissedDeadline {
+    fn losers(&self, _taps: &[PlayerId], missed: &HashSet<PlayerId>) -> HashSet<PlayerId> {
+        missed.clone()
+    }
+
+    /// The last player standing survives if they tap before the deadline, so they get until the
+    /// end of the event like everyone else.
+    fn ends_when_one_remains(&self) -> bool {
+        false
+    }
+}
+
+/// Returns the `count` slowest players.
+///
+/// Players that didn't tap are always the slowest, and are always knocked out even if there are
+/// more than `count` of them since there's no fair way to pick between them. The first player to
+/// tap is never knocked out, so that every event has at least one survivor if anyone tapped.
+fn slowest(taps: &[PlayerId], missed: &HashSet<PlayerId>, count: usize) -> HashSet<PlayerId> {
+    let from_taps = cmp::min(count.saturating_sub(missed.len()), taps.len().saturating_sub(1));
+
+    let mut losers = missed.clone();
+    losers.extend(taps.iter().rev().take(from_taps).cloned());
+    losers
+}
+
+/// Selects one of the elimination rules.
+///
+/// Unlike the rules themselves this can be saved in snapshots and the event log, and sent to
+/// clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Elimination {
+    LastTapper,
+    BottomTappers { count: usize },
+    FieldPercentage { percent: u32 },
+    MissedDeadline,
+}
+
+impl Elimination {
+    /// Returns the rule that this selects.
+    pub fn rule(&self) -> Box<EliminationRule> {
+        match *self {
+            Elimination::LastTapper => Box::new(LastTapper),
+            Elimination::BottomTappers { count } => Box::new(BottomTappers { count }),
+            Elimination::FieldPercentage { percent } => Box::new(FieldPercentage { percent }),
+            Elimination::MissedDeadline => Box::new(MissedDeadline),
+        }
+    }
+
+    /// Checks that the rule's settings make sense: A `BottomTappers` rule has to knock out at
+    /// least one player, and a `FieldPercentage` rule has to be between 1 and 100 percent.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(InvalidRequest)` if a setting is out of range.
+    pub fn validate(&self) -> error::Result<()> {
+        match *self {
+            Elimination::BottomTappers { count: 0 } => {
+                Err(Error::InvalidRequest("The count has to be at least 1".into()))
+            }
+
+            Elimination::FieldPercentage { percent } if percent == 0 || percent > 100 => {
+                Err(Error::InvalidRequest("The percentage has to be between 1 and 100".into()))
+            }
+
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for Elimination {
+    fn default() -> Elimination {
+        Elimination::LastTapper
+    }
+}
+
+impl FromStr for Elimination {
+    type Err = String;
+
+    /// Parses a rule in the form used by the `elimination_rules` option: `last-tapper`,
+    /// `bottom:<count>`, `percent:<percent>` or `missed-deadline`.
+    fn from_str(rule: &str) -> Result<Elimination, String> {
+        let mut parts = rule.trim().splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let argument = parts.next().map(str::trim);
+
+        let elimination = match (name, argument) {
+            ("last-tapper", None) => Elimination::LastTapper,
+            ("missed-deadline", None) => Elimination::MissedDeadline,
+
+            ("bottom", Some(count)) => {
+                let count = count.parse()
+                    .map_err(|_| format!("Invalid count in elimination rule {:?}", rule))?;
+                Elimination::BottomTappers { count }
+            }
+
+            ("percent", Some(percent)) => {
+                let percent = percent.parse()
+                    .map_err(|_| format!("Invalid percentage in elimination rule {:?}", rule))?;
+                Elimination::FieldPercentage { percent }
+            }
+
+            _ => return Err(format!("Unknown elimination rule {:?}", rule)),
+        };
+
+        elimination.validate()
+            .map_err(|error| format!("Invalid elimination rule {:?}: {}", rule, error.message()))?;
+        Ok(elimination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use game::{PlayerId, test_player_ids};
+    use std::collections::HashSet;
+    use super::*;
+
+    fn set(ids: &[PlayerId]) -> HashSet<PlayerId> {
+        ids.iter().cloned().collect()
+    }
+
+    #[test]
+    fn last_tapper_knocks_out_the_last_tap() {
+        let taps = test_player_ids(3);
+        assert_eq!(LastTapper.losers(&taps, &HashSet::new()), set(&taps[2 ..]));
+    }
+
+    #[test]
+    fn last_tapper_only_knocks_out_missed_players() {
+        let taps = test_player_ids(2);
+        let missed = set(&test_player_ids(2));
+        assert_eq!(LastTapper.losers(&taps, &missed), missed);
+    }
+
+    #[test]
+    fn bottom_tappers_knocks_out_the_slowest() {
+        let taps = test_player_ids(5);
+        let rule = BottomTappers { count: 2 };
+        assert_eq!(rule.losers(&taps, &HashSet::new()), set(&taps[3 ..]));
+    }
+
+    #[test]
+    fn bottom_tappers_counts_missed_players_first() {
+        let taps = test_player_ids(4);
+        let missed = set(&test_player_ids(1));
+        let rule = BottomTappers { count: 2 };
+
+        let mut expected = missed.clone();
+        expected.insert(taps[3]);
+        assert_eq!(rule.losers(&taps, &missed), expected);
+    }
+
+    #[test]
+    fn missed_players_are_knocked_out_even_past_the_count() {
+        let taps = test_player_ids(2);
+        let missed = set(&test_player_ids(3));
+        let rule = BottomTappers { count: 1 };
+        assert_eq!(rule.losers(&taps, &missed), missed);
+    }
+
+    #[test]
+    fn first_tapper_always_survives() {
+        let taps = test_player_ids(3);
+        let rules: Vec<Box<EliminationRule>> = vec![
+            Box::new(LastTapper),
+            Box::new(BottomTappers { count: 10 }),
+            Box::new(FieldPercentage { percent: 100 }),
+        ];
+
+        for rule in rules {
+            assert!(!rule.losers(&taps, &HashSet::new()).contains(&taps[0]));
+            assert!(rule.losers(&taps[.. 1], &HashSet::new()).is_empty());
+        }
+    }
+
+    #[test]
+    fn field_percentage_rounds_up() {
+        let taps = test_player_ids(5);
+        let rule = FieldPercentage { percent: 25 };
+        assert_eq!(rule.losers(&taps, &HashSet::new()), set(&taps[3 ..]));
+    }
+
+    #[test]
+    fn field_percentage_includes_missed_players_in_the_field() {
+        let taps = test_player_ids(3);
+        let missed = set(&test_player_ids(1));
+        let rule = FieldPercentage { percent: 50 };
+
+        let mut expected = missed.clone();
+        expected.insert(taps[2]);
+        assert_eq!(rule.losers(&taps, &missed), expected);
+    }
+
+    #[test]
+    fn missed_deadline_only_knocks_out_missed_players() {
+        let taps = test_player_ids(3);
+        assert!(MissedDeadline.losers(&taps, &HashSet::new()).is_empty());
+
+        let missed = set(&test_player_ids(2));
+        assert_eq!(MissedDeadline.losers(&taps, &missed), missed);
+    }
+
+    #[test]
+    fn no_players_means_no_losers() {
+        let rules = [
+            Elimination::LastTapper,
+            Elimination::BottomTappers { count: 3 },
+            Elimination::FieldPercentage { percent: 50 },
+            Elimination::MissedDeadline,
+        ];
+
+        for elimination in &rules {
+            assert!(elimination.rule().losers(&[], &HashSet::new()).is_empty());
+        }
+    }
+
+    #[test]
+    fn only_missed_deadline_waits_for_the_last_player() {
+        assert!(Elimination::LastTapper.rule().ends_when_one_remains());
+        assert!(Elimination::BottomTappers { count: 2 }.rule().ends_when_one_remains());
+        assert!(Elimination::FieldPercentage { percent: 10 }.rule().ends_when_one_remains());
+        assert!(!Elimination::MissedDeadline.rule().ends_when_one_remains());
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!("last-tapper".parse(), Ok(Elimination::LastTapper));
+        assert_eq!(" missed-deadline ".parse(), Ok(Elimination::MissedDeadline));
+        assert_eq!("bottom: 2".parse(), Ok(Elimination::BottomTappers { count: 2 }));
+        assert_eq!("percent:100".parse(), Ok(Elimination::FieldPercentage { percent: 100 }));
+
+        for rule in &["", "bottom", "bottom:0", "percent:0", "percent:101", "last-tapper:1"] {
+            assert!(rule.parse::<Elimination>().is_err(), "{:?} should be rejected", rule);
+        }
+    }
+
+    #[test]
+    fn validate_settings() {
+        assert!(Elimination::LastTapper.validate().is_ok());
+        assert!(Elimination::BottomTappers { count: 1 }.validate().is_ok());
+        assert!(Elimination::FieldPercentage { percent: 100 }.validate().is_ok());
+
+        let invalid = [
+            Elimination::BottomTappers { count: 0 },
+            Elimination::FieldPercentage { percent: 0 },
+            Elimination::FieldPercentage { percent: 500 },
+        ];
+        for elimination in &invalid {
+            match elimination.validate() {
+                Err(Error::InvalidRequest(_)) => {}
+                result => panic!("Expected {:?} to be invalid, got {:?}", elimination, result),
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Wire formats for websocket messages.
+//!
+//! Websocket messages are JSON by default, but enum-tagged JSON is pretty verbose for mobile
+//! clients. A client can ask for a more compact encoding by requesting one of the following
+//! websocket subprotocols when connecting:
+//!
+//! - `hrh.v{SCHEMA_VERSION}.json` - JSON, the same as not requesting a subprotocol.
+//! - `hrh.v{SCHEMA_VERSION}.msgpack` - [MessagePack], with structs encoded as arrays of their
+//!   fields.
+//! - `hrh.v{SCHEMA_VERSION}.cbor` - [CBOR].
+//!
+//! `{SCHEMA_VERSION}` is the current [`SCHEMA_VERSION`]. MessagePack messages leave out field
+//! names, so a client can only decode them if it knows exactly which fields each message has and
+//! in which order. Any change to the messages other than adding a new message type must bump the
+//! schema version, that way old clients fail to negotiate a subprotocol rather than misreading
+//! messages.
+//!
+//! Messages sent by the client are decoded the same way: Text messages are always JSON, and
+//! binary messages use the negotiated encoding.
+//!
+//! [MessagePack]: https://msgpack.org/
+//! [CBOR]: http://cbor.io/
+//! [`SCHEMA_VERSION`]: ./constant.SCHEMA_VERSION.html
+
+use error::*;
+use serde::Serialize;
+use serde_json::{self, Value};
+use ws;
+
+/// The version of the websocket message schema.
+///
+/// Bump this once for each wire-incompatible change to the messages, i.e. anything other than
+/// adding a new message type. Changes that stay wire-compatible don't need a bump.
+pub const SCHEMA_VERSION: u32 = 9;
+
+/// An encoding for websocket messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Default for Encoding {
+    fn default() -> Encoding {
+        Encoding::Json
+    }
+}
+
+impl Encoding {
+    /// Returns the encoding for a websocket subprotocol, or `None` if the subprotocol isn't
+    /// supported (including if it's for a different schema version).
+    pub fn from_protocol(protocol: &str) -> Option<Encoding> {
+        [Encoding::Json, Encoding::MessagePack, Encoding::Cbor]
+            .iter()
+            .find(|encoding| encoding.protocol() == protocol)
+            .cloned()
+    }
+
+    /// Returns the websocket subprotocol for the encoding.
+    pub fn protocol(&self) -> String {
+        let name = match *self {
+            Encoding::Json => "json",
+            Encoding::MessagePack => "msgpack",
+            Encoding::Cbor => "cbor",
+        };
+
+        format!("hrh.v{}.{}", SCHEMA_VERSION, name)
+    }
+
+    /// Encodes `value` as a websocket message.
+    ///
+    /// JSON is sent as a text message, the other encodings are sent as binary messages.
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<ws::Message> {
+        let result = match *self {
+            Encoding::Json => {
+                serde_json::to_string(value)
+                    .map(ws::Message::Text)
+                    .map_err(|error| error.to_string())
+            }
+
+            Encoding::MessagePack => {
+                ::rmp_serde::to_vec(value)
+                    .map(ws::Message::Binary)
+                    .map_err(|error| error.to_string())
+            }
+
+            Encoding::Cbor => {
+                ::serde_cbor::to_vec(value)
+                    .map(ws::Message::Binary)
+                    .map_err(|error| error.to_string())
+            }
+        };
+
+        result.map_err(|error| {
+            Error::Internal(format!("Failed to encode message as {:?}: {}", self, error))
+        })
+    }
+
+    /// Decodes a websocket message sent by the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(InvalidRequest)` if the message isn't valid for the encoding.
+    pub fn decode(&self, message: ws::Message) -> Result<Value> {
+        let result = match (message, *self) {
+            (ws::Message::Text(text), _) => {
+                serde_json::from_str(&text).map_err(|error| error.to_string())
+            }
+
+            (ws::Message::Binary(bytes), Encoding::Json) => {
+                serde_json::from_slice(&bytes).map_err(|error| error.to_string())
+            }
+
+            (ws::Message::Binary(bytes), Encoding::MessagePack) => {
+                ::rmp_serde::from_slice(&bytes).map_err(|error| error.to_string())
+            }
+
+            (ws::Message::Binary(bytes), Encoding::Cbor) => {
+                ::serde_cbor::from_slice(&bytes).map_err(|error| error.to_string())
+            }
+        };
+
+        result.map_err(Error::InvalidRequest)
+    }
+}
@@ -0,0 +1,259 @@
+//! The error type shared by the API endpoints and the game loop.
+//!
+//! Errors that are the client's fault (e.g. using a stale player ID) are reported back to the
+//! client with a 4xx response. Errors that indicate a bug on the server are reported with a 500
+//! response instead of panicking, that way one bad request can't take the whole game down.
+//!
+//! Errors are sent to clients (over HTTP and the player websocket) as a JSON object like:
+//!
+//! ```json
+//! {
+//!     "code": "invalid_player",
+//!     "message": "Player 1234 isn't in the game",
+//!     "details": { "id": "1234" }
+//! }
+//! ```
+//!
+//! `code` is stable, so clients should switch on it rather than on `message`, which is only meant
+//! for humans. `details` holds any data specific to the error, or `null` if there isn't any.
+
+use game::PlayerId;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::response::status::Custom;
+use rocket_contrib::Json;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::sync::LockResult;
+
+/// The error type for operations that can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// Indicates that an invalid player was specified for the operation.
+    ///
+    /// This might occur if the client code cached the player ID from a previous session, and is
+    /// now trying to use the ID in a session where it is no longer valid. Re-registering the
+    /// player to generate a new ID should fix the issue.
+    InvalidPlayer(PlayerId),
+
+    /// Indicates that a nose-goes tap arrived when no nose-goes event was active.
+    NoseGoesNotActive,
+
+    /// Indicates that the player tapped during a nose-goes event they weren't a part of.
+    ///
+    /// This happens when a player joins the game after the event started.
+    NotInRound(PlayerId),
+
+    /// Indicates that a message sent over the player websocket couldn't be understood.
+    ///
+    /// The contained string describes what was wrong with the message.
+    InvalidRequest(String),
+
+    /// Indicates that a player tried to respawn while they were still in the game.
+    NotEliminated(PlayerId),
+
+    /// Indicates that a knocked-out player tried to respawn before their cooldown was over.
+    RespawnCooldown {
+        /// How long the player has to wait before they can respawn, in milliseconds.
+        retry_after: u64,
+    },
+
+    /// Indicates that a player picked a cosmetic option that their score hasn't unlocked yet.
+    CosmeticLocked {
+        /// The ID of the option.
+        item: String,
+
+        /// The score needed to unlock the option.
+        unlock_score: usize,
+    },
+
+    /// Indicates that the game is paused because the server is shutting down.
+    ///
+    /// The client should try again once the server is back up.
+    GamePaused,
+
+    /// Indicates that the player is sending taps faster than they're allowed to.
+    RateLimited {
+        /// How long the client should wait before sending more taps, in milliseconds.
+        retry_after: u64,
+    },
+
+    /// Indicates that the request needed credentials (e.g. an admin token) that were missing or
+    /// wrong.
+    Unauthorized,
+
+    /// Indicates that the client asked for a version of the API that the server doesn't support.
+    ///
+    /// The contained versions are the ones the server does support.
+    UnsupportedVersion {
+        supported: Vec<u32>,
+    },
+
+    /// Indicates that the server's game state was inconsistent and the request couldn't be
+    /// completed.
+    ///
+    /// This always indicates a bug on the server, the client did nothing wrong. The contained
+    /// string describes what went wrong.
+    Internal(String),
+}
+
+impl Error {
+    /// Returns the HTTP status that should be sent to the client for this error.
+    pub fn status(&self) -> Status {
+        match *self {
+            Error::InvalidPlayer(..) => Status::NotFound,
+            Error::NoseGoesNotActive
+            | Error::NotInRound(..)
+            | Error::NotEliminated(..)
+            | Error::RespawnCooldown { .. } => Status::Conflict,
+            Error::InvalidRequest(..) => Status::BadRequest,
+            Error::CosmeticLocked { .. } => Status::Forbidden,
+            Error::GamePaused => Status::ServiceUnavailable,
+            Error::RateLimited { .. } => Status::TooManyRequests,
+            Error::Unauthorized => Status::Unauthorized,
+            Error::UnsupportedVersion { .. } => Status::NotAcceptable,
+            Error::Internal(..) => Status::InternalServerError,
+        }
+    }
+
+    /// Returns the stable code that identifies the kind of error to clients.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Error::InvalidPlayer(..) => "invalid_player",
+            Error::NoseGoesNotActive => "nose_goes_not_active",
+            Error::NotInRound(..) => "player_not_in_round",
+            Error::NotEliminated(..) => "player_not_eliminated",
+            Error::RespawnCooldown { .. } => "respawn_cooldown",
+            Error::InvalidRequest(..) => "invalid_request",
+            Error::CosmeticLocked { .. } => "cosmetic_locked",
+            Error::GamePaused => "game_paused",
+            Error::RateLimited { .. } => "rate_limited",
+            Error::Unauthorized => "unauthorized",
+            Error::UnsupportedVersion { .. } => "unsupported_api_version",
+            Error::Internal(..) => "internal",
+        }
+    }
+
+    /// Returns a human-readable description of the error.
+    pub fn message(&self) -> String {
+        match *self {
+            Error::InvalidPlayer(id) => format!("Player {} isn't in the game", id),
+            Error::NoseGoesNotActive => "There is no nose-goes event in progress".into(),
+            Error::NotInRound(id) => {
+                format!("Player {} isn't part of the current nose-goes event", id)
+            }
+            Error::NotEliminated(id) => format!("Player {} is still in the game", id),
+            Error::RespawnCooldown { retry_after } => {
+                format!("Can't respawn yet, try again in {}ms", retry_after)
+            }
+            Error::InvalidRequest(ref reason) => reason.clone(),
+            Error::CosmeticLocked { ref item, unlock_score } => {
+                format!("{:?} is unlocked at {} points", item, unlock_score)
+            }
+            Error::GamePaused => "The game is paused because the server is shutting down".into(),
+            Error::RateLimited { retry_after } => {
+                format!("Too many taps, try again in {}ms", retry_after)
+            }
+            Error::Unauthorized => "Missing or invalid credentials".into(),
+            Error::UnsupportedVersion { ref supported } => {
+                let supported = supported.iter()
+                    .map(|version| version.to_string())
+                    .collect::<Vec<_>>();
+                format!("Unsupported API version, supported versions are {}", supported.join(", "))
+            }
+            Error::Internal(ref message) => message.clone(),
+        }
+    }
+
+    /// Returns data specific to the error, or `null` if there isn't any.
+    pub fn details(&self) -> Value {
+        match *self {
+            Error::InvalidPlayer(id)
+            | Error::NotInRound(id)
+            | Error::NotEliminated(id) => json!({ "id": id }),
+            Error::RateLimited { retry_after }
+            | Error::RespawnCooldown { retry_after } => json!({ "retry_after": retry_after }),
+            Error::CosmeticLocked { ref item, unlock_score } => {
+                json!({ "item": item, "unlock_score": unlock_score })
+            }
+            Error::UnsupportedVersion { ref supported } => json!({ "supported": supported }),
+            Error::NoseGoesNotActive
+            | Error::InvalidRequest(..)
+            | Error::GamePaused
+            | Error::Unauthorized
+            | Error::Internal(..) => Value::Null,
+        }
+    }
+}
+
+/// The form that errors take when they're sent to clients.
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    details: Value,
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            details: self.details(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'r> Responder<'r> for Error {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        if let Error::Internal(ref message) = self {
+            error!("Internal error while handling {}: {}", request, message);
+        }
+
+        Custom(self.status(), Json(self)).respond_to(request)
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Sends an `Unauthorized` error body when a request guard rejects a request's credentials.
+///
+/// Request guards can only fail with a status, so without this catcher the client would get
+/// Rocket's default HTML error page rather than an error it can switch on.
+#[error(401)]
+pub fn unauthorized() -> Error {
+    Error::Unauthorized
+}
+
+/// Extension trait for recovering the guard from a poisoned lock.
+///
+/// A lock only gets poisoned if a thread panicked while holding it. The game state behind the
+/// lock is still usable (at worst a single update was only partially applied), so rather than
+/// propagating the panic to every other thread we log a warning and keep going.
+///
+/// # Examples
+///
+/// ```
+/// use hangry_river_horse::error::Recover;
+/// use std::sync::Mutex;
+///
+/// let scores = Mutex::new(Vec::new());
+/// scores.lock().recover("Scores").push(10);
+/// assert_eq!(*scores.lock().recover("Scores"), vec![10]);
+/// ```
+pub trait Recover<G> {
+    /// Returns the lock guard, recovering it if the lock was poisoned.
+    ///
+    /// `name` is used to identify the lock in the log message.
+    fn recover(self, name: &str) -> G;
+}
+
+impl<G> Recover<G> for LockResult<G> {
+    fn recover(self, name: &str) -> G {
+        self.unwrap_or_else(|error| {
+            warn!("{} was poisoned, recovering", name);
+            error.into_inner()
+        })
+    }
+}
@@ -0,0 +1,184 @@
+//! Recording game inputs so that a game can be replayed later.
+//!
+//! The event log is a file with one JSON-encoded [`Event`] per line. Every input that the game
+//! loop applies to the game state is appended to the log along with the time it was applied,
+//! taken from the game's [`Clock`] so that it matches the `Instant` that the game state saw. Since
+//! the game rules are deterministic given the same inputs at the same times, replaying the log
+//! through a fresh `GameState` reproduces the game, with every input applied at the same game time
+//! to the millisecond. This is what the `replay` tool does, which is handy for settling disputes
+//! like "I tapped in time!" after a showcase.
+//!
+//! A log can contain multiple games (e.g. if the server was restarted); Each game starts with an
+//! `Input::Start` event.
+//!
+//! [`Clock`]: ../clock/struct.Clock.html
+//! [`Event`]: ./struct.Event.html
+
+use cosmetics::Cosmetics;
+use elimination::Elimination;
+use game::{GameRules, PlayerId, Snapshot, TapTiming};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::*;
+
+/// A single input to the game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Input {
+    /// A new game was started.
+    ///
+    /// This is always the first input for a game, and any inputs that follow it apply to the new
+    /// game.
+    Start,
+
+    /// The new game was resumed from a saved game, using the rules from the preceding `SetRules`.
+    ///
+    /// If present, this always immediately follows `SetRules`.
+    Restore(Snapshot),
+
+    /// The rules for the game were set.
+    ///
+    /// This always immediately follows `Start`. Logs from older servers have it after `Restore`
+    /// instead.
+    SetRules(GameRules),
+
+    /// An admin picked the elimination rule for the next nose-goes event.
+    SetNextElimination(Elimination),
+
+    /// A new player joined the game with the given ID and name.
+    Register {
+        id: PlayerId,
+        name: String,
+
+        #[serde(default)]
+        cosmetics: Cosmetics,
+    },
+
+    /// A player changed how their hippo looks.
+    SetCosmetics {
+        id: PlayerId,
+        cosmetics: Cosmetics,
+    },
+
+    /// A knocked-out player rejoined the game.
+    Respawn {
+        id: PlayerId,
+    },
+
+    /// A player fed their hippo.
+    Feed {
+        id: PlayerId,
+    },
+
+    /// A player fed their hippo with a batch of taps.
+    FeedBatch {
+        id: PlayerId,
+
+        /// The client-side time of each tap, in milliseconds since the Unix epoch.
+        taps: Vec<u64>,
+    },
+
+    /// A player tapped their poison marble during a nose-goes event.
+    Tap {
+        id: PlayerId,
+
+        #[serde(default)]
+        timing: TapTiming,
+    },
+
+    /// The game loop advanced the game.
+    Tick,
+
+    /// The game was paused because the server is shutting down.
+    Shutdown,
+}
+
+/// An entry in the event log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    /// The time at which the input was applied, in milliseconds since the Unix epoch.
+    ///
+    /// This is the game clock's reading of the `Instant` that was passed to the game state, not
+    /// the time at which the event was written. Logs from older servers used the system time
+    /// instead, so they may be a few milliseconds off.
+    pub timestamp: u64,
+
+    /// The input that was applied to the game state.
+    pub input: Input,
+}
+
+/// Appends events to an event log file.
+#[derive(Debug)]
+pub struct EventLog {
+    writer: BufWriter<File>,
+}
+
+impl EventLog {
+    /// Opens the event log at `path`, creating the file if it doesn't already exist.
+    ///
+    /// New events are appended to the end of the file, so any existing games in the log are
+    /// preserved.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<EventLog> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        Ok(EventLog {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Appends `input` to the log, applied at `timestamp` milliseconds since the Unix epoch.
+    ///
+    /// Each event is flushed as soon as it's written so that the log is complete even if the
+    /// server crashes.
+    pub fn append(&mut self, input: &Input, timestamp: u64) -> io::Result<()> {
+        let event = Event {
+            timestamp,
+            input: input.clone(),
+        };
+
+        ::serde_json::to_writer(&mut self.writer, &event)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+/// Reads all events from the event log at `path`, in the order they were recorded.
+///
+/// # Errors
+///
+/// Returns an `InvalidData` error if any line in the log isn't a valid event.
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<Event>> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut events = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let event = ::serde_json::from_str(&line).map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid event on line {}: {}", index + 1, error),
+            )
+        })?;
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+/// Converts `time` to milliseconds since the Unix epoch.
+///
+/// Times before the epoch are clamped to 0.
+pub fn unix_millis(time: SystemTime) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() * 1_000 + duration.subsec_nanos() as u64 / 1_000_000,
+        Err(_) => 0,
+    }
+}
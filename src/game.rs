@@ -1,14 +1,22 @@
+use achievements::{self, Achievement, AchievementEvent, AchievementProgress};
+use api::{FeedBatchResponse, NoseGoesResponse, NoseGoesStatus, PlayerData, RankedPlayer};
 use broadcast::*;
+use clock::Clock;
+use cosmetics::Cosmetics;
+use elimination::Elimination;
+use error::{self, Error};
+use payout::{BonusCandidate, Payout};
+use ranking::Ranking;
 use rand::{self, Rng};
 use rocket::request::FromParam;
 use serde::*;
 use std::cmp;
 use std::collections::{ HashMap, HashSet };
+use std::fmt;
 use std::mem;
 use std::str::FromStr;
-use std::sync::*;
-use std::thread;
 use std::time::*;
+use tie_break::TieBreak;
 
 /// Uniquely identifies a connected player.
 ///
@@ -22,7 +30,7 @@ use std::time::*;
 /// `PlayerId` is serialized as a string so that it'll play nice with JavaScript on the client
 /// side. The IDs are meant to be treated as opaque, anyway, so sending them across the wire as
 /// strings makes sense.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PlayerId(usize);
 
 impl PlayerId {
@@ -34,6 +42,18 @@ impl PlayerId {
     }
 }
 
+/// Generates `count` new player IDs, for tests that just need some players.
+#[cfg(test)]
+pub fn test_player_ids(count: usize) -> Vec<PlayerId> {
+    (0 .. count).map(|_| PlayerId::new()).collect()
+}
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl Serialize for PlayerId {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
         // TODO: Can we do this without allocating a string?
@@ -59,19 +79,6 @@ impl<'a> FromParam<'a> for PlayerId {
     }
 }
 
-/// Type alias for a shareable `Option<PlayerId>` representing the curent winner.
-///
-/// # Examples
-///
-/// ```
-/// #[post("/winner/<id>")]
-/// fn set_winner(id: PlayerId, winner: State<Winner>) {
-///     let mut winner = winner.lock().expect("Winner was poisoned!");
-///     *winner = Some(id);
-/// }
-/// ```
-pub type Winner = Arc<Mutex<Option<PlayerId>>>;
-
 /// Generates a random username for new players.
 ///
 /// Names are chosen from a pre-written list of guaranteed-funny names.
@@ -224,7 +231,7 @@ pub fn generate_username() -> String {
 }
 
 /// The current state for a single player.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player {
     /// A unique identifier for the player.
     pub id: PlayerId,
@@ -234,165 +241,2396 @@ pub struct Player {
 
     /// The player's current score.
     pub score: usize,
+
+    /// How many more nose-goes events the player can lose, including the one that knocks them
+    /// out. A player with 1 life left is knocked out the next time they lose.
+    #[serde(default)]
+    pub lives: usize,
+
+    /// How the player's hippo looks on the host.
+    #[serde(default)]
+    pub cosmetics: Cosmetics,
+
+    /// The achievements the player has unlocked, in the order they were unlocked.
+    #[serde(default)]
+    pub achievements: Vec<Achievement>,
+
+    /// The player's progress towards achievements they haven't unlocked yet.
+    #[serde(default)]
+    pub progress: AchievementProgress,
+
+    /// Hundredths of a point left over from feeds that weren't worth a whole number of points.
+    #[serde(default)]
+    pub partial_points: usize,
+
+    /// When the player reached their current score, as a sequence number that increases every
+    /// time any player's score changes. Used to break ties for the crown.
+    #[serde(default)]
+    pub scored_at: u64,
+
+    /// The highest score the player had before their score last went down (e.g. from a respawn
+    /// penalty). See `best_score`.
+    #[serde(default)]
+    pub peak_score: usize,
 }
 
-pub type PlayerMap = Arc<RwLock<HashMap<PlayerId, Player>>>;
+impl Player {
+    /// Returns the highest score the player has ever had.
+    ///
+    /// Cosmetics are unlocked by this rather than the current score, so that players don't lose
+    /// unlocks when their score goes down.
+    pub fn best_score(&self) -> usize {
+        cmp::max(self.peak_score, self.score)
+    }
+
+    /// Adds `feeds` feeds worth `value` hundredths of a point each to the player's score.
+    ///
+    /// Returns whether the player's score changed, in which case `scored_at` needs updating.
+    fn add_feeds(&mut self, feeds: usize, value: usize) -> bool {
+        let points = self.partial_points + feeds * value;
+        self.partial_points = points % 100;
+        self.score += points / 100;
+        points >= 100
+    }
+}
 
-/// Runs the main logic of the game on a separate thread.
+/// The complete state of a running game.
 ///
-/// Spawns a thread that updates game state and broadcasts updates to the players and hosts.
-pub fn start_game_loop(
-    players: PlayerMap,
-    nose_goes: NoseGoesState,
-    winner: Winner,
-    host_broadcaster: HostBroadcaster,
-    player_broadcaster: PlayerBroadcaster,
-) {
-    thread::spawn(move || {
-        // NOTE: These should be `const`, but you can't make a const `Duration`.
-        let nose_goes_duration = Duration::from_millis(10_000);
-        let nose_goes_interval = Duration::from_millis(30_000);
-
-        loop {
-            // NOTE: Perform all logic for the loop body in this inner block. Doing so will ensure
-            // that any acquired locks will be released before the thread sleeps.
-            {
-                let now = Instant::now();
-                let mut nose_goes = nose_goes.lock().expect("Nose-goes state was poisoned!");
-
-                // Match the current nose-goes state, and return the new state.
-                //
-                // NOTE: We use `mem::replace` to move the current state out of the `Mutex`, so that we
-                // can safely destructure and mutate it.
-                *nose_goes = match mem::replace(&mut *nose_goes, NoseGoes::default()) {
-                    NoseGoes::Inactive { next_start_time } => {
-                        let players = players.read().expect("Player map was poisoned!");
-                        if now > next_start_time {
-                            if players.len() > 1 {
-                                // Add all players to the nose-goes event.
-                                let remaining_players: HashSet<PlayerId> = players.keys().cloned().collect();
-
-                                host_broadcaster.send(HostBroadcast::BeginNoseGoes {
-                                    duration: nose_goes_duration,
-                                    players: remaining_players.clone(),
-                                });
-                                player_broadcaster.send(PlayerBroadcast::BeginNoseGoes {});
-
-                                NoseGoes::InProgress {
-                                    start_time: next_start_time,
-                                    end_time: next_start_time + nose_goes_duration,
-                                    remaining_players,
-                                    bonus_winner: None,
-                                }
-                            } else {
-                                // There aren't enough players to run the nose-goes event. Delay until
-                                // later.
-                                let next_start_time = next_start_time + nose_goes_interval;
-                                NoseGoes::Inactive { next_start_time }
-                            }
-                        } else {
-                            NoseGoes::Inactive { next_start_time }
-                        }
-                    }
+/// `GameState` implements all of the rules of the game, but doesn't do any I/O on its own. Each
+/// operation updates the state and queues up any broadcasts that should be sent as a result,
+/// which the owner of the state then sends after calling [`take_broadcasts`]. The game loop owns
+/// the only `GameState` and applies commands to it one at a time, so operations never observe a
+/// partially-applied update.
+///
+/// [`take_broadcasts`]: #method.take_broadcasts
+#[derive(Debug)]
+pub struct GameState {
+    /// All players that are currently in the game.
+    players: HashMap<PlayerId, Player>,
 
-                    NoseGoes::InProgress { start_time, end_time, remaining_players, bonus_winner } => {
-                        if now > end_time || remaining_players.len() == 1 {
-                            // Remove all players who haven't tapped from the players map.
-                            let mut players = players.write().expect("Player map was poisoned!");
-                            let mut bonus = 0;
-                            for loser in &remaining_players {
-                                let loser_info = players.remove(&loser).expect("Loser wasn't in player map");
-                                player_broadcaster.send(PlayerBroadcast::PlayerLose {
-                                    id: *loser,
-                                    score: loser_info.score,
-                                });
-
-                                bonus += cmp::max(loser_info.score, 100);
-                            }
-
-                            // Apply bonus points to the bonus winner, if any.
-                            let bonus_winner = match bonus_winner {
-                                Some(bonus_winner) => {
-                                    let bonus_winner = players.get_mut(&bonus_winner).expect("Bonus winner wasn't in players map");
-                                    bonus_winner.score += bonus;
-                                    Some((bonus_winner.id, bonus_winner.score))
-                                },
-                                None => None,
-                            };
-
-                            // Recalculate the new winner after all losers have been removed.
-                            let mut winner = winner.lock().expect("Winner was poisoned!");
-                            let new_winner = players.iter()
-                                .fold(None, |winner, (id, player)| {
-                                    match winner {
-                                        Some((id, score)) => {
-                                            if player.score > score {
-                                                Some((id, player.score))
-                                            } else {
-                                                winner
-                                            }
-                                        }
-
-                                        None => { Some((id, player.score)) }
-                                    }
-                                })
-                                .map(|(&id, _)| id);
-
-                            if new_winner != *winner {
-                                if let Some(id) = new_winner {
-                                    host_broadcaster.send(HostBroadcast::UpdateWinner { id });
-                                    player_broadcaster.send(PlayerBroadcast::UpdateWinner { id });
-                                }
-                            }
-
-                            *winner = new_winner;
-
-                            // Broadcast player loss to players and hosts.
-                            host_broadcaster.send(HostBroadcast::EndNoseGoes {
-                                losers: remaining_players,
-                                bonus_winner,
-                            });
-                            player_broadcaster.send(PlayerBroadcast::EndNoseGoes { bonus_winner });
-
-                            NoseGoes::Inactive { next_start_time: end_time + nose_goes_interval }
-                        } else {
-                            NoseGoes::InProgress {
-                                start_time,
-                                end_time,
-                                remaining_players,
-                                bonus_winner,
-                            }
-                        }
-                    }
-                };
+    /// The players that currently have the crown, in the order they reached their score.
+    ///
+    /// Usually there's only one, but tied players can share the crown depending on
+    /// `rules.tie_break`.
+    crown: Vec<PlayerId>,
+
+    /// Every player in `players`, sorted by score.
+    ranking: Ranking,
+
+    /// The top of the ranking as it was last announced to the hosts, as each player's rank and ID.
+    podium: Vec<(usize, PlayerId)>,
+
+    /// Incremented every time a player's score changes, so that we know which of two tied players
+    /// reached their score first (see `Player::scored_at`).
+    score_sequence: u64,
+
+    /// The state of the current (or next) nose-goes event.
+    nose_goes: NoseGoes,
+
+    /// Broadcasts for the host clients that haven't been sent yet.
+    host_broadcasts: Vec<HostBroadcast>,
+
+    /// Broadcasts for the player clients that haven't been sent yet.
+    player_broadcasts: Vec<PlayerBroadcast>,
+
+    /// Running totals of events in the game.
+    stats: GameStats,
+
+    /// Whether the game has been paused because the server is shutting down.
+    paused: bool,
+
+    /// When to re-announce the in-progress nose-goes event after the game has been restored from
+    /// a snapshot.
+    ///
+    /// Clients lose track of the nose-goes event when the server restarts, so the event is
+    /// announced again once they've had time to reconnect.
+    resume_nose_goes_at: Option<Instant>,
+
+    /// The number of clients currently connected to each websocket stream, as last reported by
+    /// the game loop.
+    connections: ConnectionCounts,
+
+    /// Converts game times to wall-clock times for broadcasts.
+    clock: Clock,
+
+    /// How many more feeds each player is allowed to make right now, whether one at a time or in
+    /// batches.
+    tap_budgets: HashMap<PlayerId, TapBudget>,
+
+    /// Players that have been fed by a batch of taps since the last tick.
+    ///
+    /// Rather than broadcasting a `HippoEat` for every tap in a batch, one `HippoEat` with the
+    /// player's latest score is broadcast for each of these players on the next tick.
+    pending_eats: HashSet<PlayerId>,
+
+    /// The results of the most recent nose-goes event that has ended, so that players can check
+    /// how they did after the fact.
+    ///
+    /// NOTE: This isn't saved in snapshots, so the results are lost if the server restarts.
+    last_round: NoseGoesRound,
+
+    /// The configurable rules for nose-goes events.
+    rules: GameRules,
+
+    /// The elimination rule to use for the next nose-goes event instead of the next one in
+    /// `rules.elimination`, if an admin has picked one.
+    next_elimination: Option<Elimination>,
+
+    /// Players that have been knocked out but can still respawn.
+    ///
+    /// This is only filled in if `rules.respawn` is set. Only the most recent `MAX_ELIMINATED`
+    /// players are kept, so players that never come back don't pile up forever.
+    eliminated: HashMap<PlayerId, EliminatedPlayer>,
+
+    /// The achievements that only one player can unlock, and that have already been unlocked.
+    claimed_achievements: HashSet<Achievement>,
+
+    /// Who has worn the crown and for how long, oldest first.
+    ///
+    /// Each crown holder's latest tenure is still open (`ended_at` is `None`). Only
+    /// the most recent `MAX_CROWN_HISTORY` tenures are kept.
+    crown_history: Vec<CrownTenure>,
+}
+
+/// The configurable rules for the game: How nose-goes events are decided, how players are knocked
+/// out and come back, and how the crown and rankings are worked out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRules {
+    /// The elimination rules to cycle through, one per nose-goes event.
+    pub elimination: Vec<Elimination>,
+
+    /// How the bonus is divided up at the end of each event.
+    #[serde(default)]
+    pub payout: Payout,
+
+    /// Whether the player wearing the crown can get any of the bonus.
+    #[serde(default)]
+    pub crown_can_win_bonus: bool,
+
+    /// How many nose-goes events each player can lose before they're knocked out, or `None` if
+    /// they're knocked out the first time they lose.
+    #[serde(default)]
+    pub lives: Option<usize>,
+
+    /// Whether (and how) knocked-out players can rejoin the game, or `None` if they can't.
+    #[serde(default)]
+    pub respawn: Option<RespawnRules>,
+
+    /// How much feeding is handicapped to help trailing players catch up to the crown holder.
+    #[serde(default)]
+    pub catch_up: CatchUp,
+
+    /// Who gets the crown when players are tied for the highest score.
+    #[serde(default)]
+    pub tie_break: TieBreak,
+
+    /// How many of the top players are announced to the hosts when the ranking changes.
+    #[serde(default = "default_podium_size")]
+    pub podium_size: usize,
+}
+
+fn default_podium_size() -> usize {
+    3
+}
+
+impl Default for GameRules {
+    fn default() -> GameRules {
+        GameRules {
+            elimination: vec![Elimination::default()],
+            payout: Payout::default(),
+            crown_can_win_bonus: false,
+            lives: None,
+            respawn: None,
+            catch_up: CatchUp::default(),
+            tie_break: TieBreak::default(),
+            podium_size: default_podium_size(),
+        }
+    }
+}
+
+/// Settings for helping trailing players catch up to the player wearing the crown.
+///
+/// Each feed is normally worth one point. With catch-up turned on, feeds can be worth a fraction
+/// of a point (the leftover is carried over to the player's next feed) or more than a point.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct CatchUp {
+    /// The percentage that the crown holder's feeds are reduced by.
+    pub crown_penalty: u32,
+
+    /// The percentage that trailing players' feeds are boosted by.
+    pub trailing_boost: u32,
+
+    /// Players with less than this percentage of the crown holder's score count as trailing.
+    pub trailing_below: u32,
+}
+
+impl CatchUp {
+    /// Returns what a single feed is worth, in hundredths of a point.
+    ///
+    /// `score` is the player's score, and `winner_score` is the crown holder's score.
+    pub fn feed_value(&self, has_crown: bool, score: usize, winner_score: usize) -> usize {
+        if has_crown {
+            100 - cmp::min(self.crown_penalty, 100) as usize
+        } else if score * 100 < winner_score * self.trailing_below as usize {
+            100 + self.trailing_boost as usize
+        } else {
+            100
+        }
+    }
+}
+
+/// A stretch of time that one player wore the crown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrownTenure {
+    pub id: PlayerId,
+
+    /// The player's display name, kept so that the history still makes sense after the player
+    /// has been knocked out.
+    pub name: String,
+
+    /// When the player got the crown, in milliseconds since the Unix epoch (server time).
+    pub started_at: u64,
+
+    /// When the player lost the crown, or `None` if they're still wearing it.
+    pub ended_at: Option<u64>,
+
+    /// How long the player wore the crown (or has been wearing it so far), in milliseconds.
+    pub duration: u64,
+}
+
+/// The rules for letting knocked-out players rejoin the game.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RespawnRules {
+    /// How long a knocked-out player has to wait before they can respawn, in milliseconds.
+    pub cooldown: u64,
+
+    /// The percentage of their score that a player loses when they respawn.
+    pub penalty_percent: u32,
+}
+
+/// A player that has been knocked out, but can respawn once their cooldown is over.
+#[derive(Debug, Clone)]
+struct EliminatedPlayer {
+    /// The player as they were when they were knocked out.
+    player: Player,
+
+    /// When the player is allowed to respawn.
+    respawn_at: Instant,
+}
+
+/// A player's tap during a nose-goes event.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NoseGoesTap {
+    /// When the tap arrived, in milliseconds since the Unix epoch (server time).
+    pub received: u64,
+
+    /// When we think the player actually tapped, after compensating for the time the tap took to
+    /// reach the server. Taps are ordered by this time when the event ends.
+    pub time: u64,
+
+    /// What happened to the player, which is `Pending` until the event ends.
+    pub outcome: NoseGoesResponse,
+}
+
+/// What the client told us about the timing of a nose-goes tap.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TapTiming {
+    /// When the player tapped according to the client, in milliseconds since the Unix epoch.
+    ///
+    /// Clients should convert their own time to server time (see the `/time` endpoint) before
+    /// sending it.
+    #[serde(default)]
+    pub tapped_at: Option<u64>,
+
+    /// The round trip time to the client in milliseconds, if it has been measured.
+    #[serde(default)]
+    pub rtt: Option<u64>,
+}
+
+impl TapTiming {
+    /// Works out when a tap that arrived at `received` really happened.
+    ///
+    /// Without any other information we assume the tap spent half of the round trip in flight. If
+    /// the client says when the tap happened we trust it, within `tap_tolerance` of our own
+    /// estimate, so that a slow client isn't punished for its connection but a dishonest one can't
+    /// claim to have tapped first. Either way a tap can't have happened after it arrived.
+    fn compensate(&self, received: u64) -> u64 {
+        let tolerance = as_millis(tap_tolerance());
+        let latency = cmp::min(self.rtt.unwrap_or(0) / 2, tolerance);
+        let estimate = received.saturating_sub(latency);
+
+        match self.tapped_at {
+            Some(tapped_at) => {
+                cmp::min(cmp::max(tapped_at, estimate.saturating_sub(tolerance)), received)
             }
 
-            thread::sleep(Duration::from_millis(100));
+            None => estimate,
         }
-    });
+    }
 }
 
-/// State information for nose-goes events.
-#[derive(Debug)]
-pub enum NoseGoes {
-    Inactive {
-        next_start_time: Instant,
-    },
+/// The results of a nose-goes event that has ended.
+#[derive(Debug, Clone, Default)]
+struct NoseGoesRound {
+    /// The taps made during the event.
+    taps: HashMap<PlayerId, NoseGoesTap>,
 
-    InProgress {
-        start_time: Instant,
-        end_time: Instant,
-        remaining_players: HashSet<PlayerId>,
-        bonus_winner: Option<PlayerId>,
+    /// The players that were eliminated at the end of the event.
+    losers: HashSet<PlayerId>,
+
+    /// The players that lost the event but had lives left, so weren't eliminated.
+    lost_life: HashSet<PlayerId>,
+}
+
+/// The largest number of taps allowed in a single batch.
+pub const MAX_FEED_BATCH: usize = 64;
+
+/// The number of crown tenures to remember.
+pub const MAX_CROWN_HISTORY: usize = 500;
+
+/// The number of knocked-out players to hold on to so that they can respawn.
+pub const MAX_ELIMINATED: usize = 1000;
+
+/// The number of feeds per second that a player can keep up indefinitely.
+///
+/// Nobody can actually tap faster than this, so anything more is a bot.
+pub const MAX_TAPS_PER_SECOND: usize = 20;
+
+/// The number of feeds a player can make in a burst before being limited to
+/// `MAX_TAPS_PER_SECOND`.
+///
+/// The burst covers taps that pile up on the client (or in flight) and arrive together, not taps
+/// made faster than `MAX_TAPS_PER_SECOND`. See `plausible_taps`.
+pub const MAX_TAP_BURST: usize = 40;
+
+/// A per-player token bucket for rate limiting batched taps.
+#[derive(Debug, Clone, Copy)]
+struct TapBudget {
+    /// The number of taps the player can make right now.
+    available: f64,
+
+    /// When `available` was last updated.
+    updated: Instant,
+}
+
+impl TapBudget {
+    fn new(now: Instant) -> TapBudget {
+        TapBudget {
+            available: MAX_TAP_BURST as f64,
+            updated: now,
+        }
+    }
+
+    /// Spends up to `count` taps from the budget, returning the number of taps that were allowed.
+    fn spend(&mut self, count: usize, now: Instant) -> usize {
+        let elapsed = duration_until(self.updated, now);
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        self.available = (self.available + elapsed * MAX_TAPS_PER_SECOND as f64)
+            .min(MAX_TAP_BURST as f64);
+        self.updated = cmp::max(self.updated, now);
+
+        let allowed = cmp::min(count, self.available.floor() as usize);
+        self.available -= allowed as f64;
+        allowed
+    }
+
+    /// Returns how long until the budget has at least one tap available.
+    fn retry_after(&self) -> Duration {
+        if self.available >= 1.0 {
+            return Duration::from_millis(0);
+        }
+
+        let millis = (1.0 - self.available) / MAX_TAPS_PER_SECOND as f64 * 1_000.0;
+        Duration::from_millis(millis.ceil() as u64)
     }
 }
 
-impl Default for NoseGoes {
-    fn default() -> NoseGoes {
-        NoseGoes::Inactive {
-            next_start_time: Instant::now() + Duration::from_millis(10_000),
+/// Returns how many of the client-side tap times in `taps` a person could actually have made.
+///
+/// Starting from the first tap, any tap that came sooner after the last counted tap than
+/// `MAX_TAPS_PER_SECOND` allows isn't counted. So a batch of taps that all claim to have happened
+/// in the same millisecond only counts as one tap. `taps` must be in order.
+fn plausible_taps(taps: &[u64]) -> usize {
+    let interval = 1_000 / MAX_TAPS_PER_SECOND as u64;
+
+    let mut count = 0;
+    let mut last_counted = None;
+    for &tap in taps {
+        match last_counted {
+            Some(last) if tap < last + interval => continue,
+            _ => {}
         }
+
+        count += 1;
+        last_counted = Some(tap);
     }
+
+    count
 }
 
-pub type NoseGoesState = Arc<Mutex<NoseGoes>>;
+/// Statistics about a running game, used for reporting metrics.
+///
+/// All members except `players` are running totals since the game started.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GameStats {
+    /// The number of players currently in the game.
+    pub players: usize,
+
+    /// The number of players that have registered.
+    pub registrations: usize,
+
+    /// The number of times any hippo has been fed.
+    pub feeds: usize,
+
+    /// The number of nose-goes events that have started.
+    pub nose_goes_started: usize,
+
+    /// The number of players that have been knocked out.
+    pub eliminations: usize,
+
+    /// The number of lives lost by players that weren't knocked out.
+    #[serde(default)]
+    pub lives_lost: usize,
+
+    /// The number of times a knocked-out player has rejoined the game.
+    #[serde(default)]
+    pub respawns: usize,
+
+    /// The number of achievements unlocked by all players.
+    #[serde(default)]
+    pub achievements_unlocked: usize,
+
+    /// The total number of bonus points awarded to nose-goes bonus winners.
+    pub bonus_points_awarded: usize,
+
+    /// The number of clients currently connected to each websocket stream.
+    ///
+    /// Like `players` this is a current value rather than a running total.
+    #[serde(default)]
+    pub connections: ConnectionCounts,
+}
+
+impl GameState {
+    /// Creates a new game with no players.
+    ///
+    /// The first nose-goes event is scheduled relative to `now`. `clock` is used to convert game
+    /// times to wall-clock times for broadcasts.
+    pub fn new(now: Instant, clock: Clock) -> GameState {
+        GameState {
+            players: HashMap::new(),
+            crown: Vec::new(),
+            ranking: Ranking::new(),
+            podium: Vec::new(),
+            score_sequence: 0,
+            nose_goes: NoseGoes::Inactive { next_start_time: now + first_nose_goes_delay() },
+            host_broadcasts: Vec::new(),
+            player_broadcasts: Vec::new(),
+            stats: GameStats::default(),
+            paused: false,
+            resume_nose_goes_at: None,
+            connections: ConnectionCounts::default(),
+            clock,
+            tap_budgets: HashMap::new(),
+            pending_eats: HashSet::new(),
+            last_round: NoseGoesRound::default(),
+            rules: GameRules::default(),
+            next_elimination: None,
+            eliminated: HashMap::new(),
+            claimed_achievements: HashSet::new(),
+            crown_history: Vec::new(),
+        }
+    }
+
+    /// Recreates a game from a snapshot taken by [`snapshot`], playing by `rules`.
+    ///
+    /// The rules are applied before the crown and podium are worked out, so that they match what
+    /// a game that had been running with `rules` all along would have. Nose-goes timers are
+    /// resumed relative to `now`. If a nose-goes event was in progress it is
+    /// announced again after a delay, and its deadline is pushed back by the same amount, so that
+    /// players have time to reconnect before the event continues.
+    ///
+    /// [`snapshot`]: #method.snapshot
+    pub fn restore(snapshot: Snapshot, rules: GameRules, now: Instant, clock: Clock) -> GameState {
+        let mut state = GameState::new(now, clock);
+        state.set_rules(rules);
+        state.players = snapshot.players.into_iter()
+            .map(|player| (player.id, player))
+            .collect();
+        // Older snapshots only have a single winner.
+        let crown: Vec<PlayerId> = if snapshot.crown.is_empty() {
+            snapshot.winner.into_iter().collect()
+        } else {
+            snapshot.crown
+        };
+        let crown = crown.into_iter()
+            .filter(|id| state.players.contains_key(id))
+            .collect();
+        let score_sequence = state.players.values()
+            .map(|player| player.scored_at)
+            .max()
+            .unwrap_or(0);
+        state.crown = crown;
+        state.score_sequence = score_sequence;
+        for player in state.players.values() {
+            state.ranking.update(player.id, player.score, player.scored_at);
+        }
+        state.podium = state.ranking.top(Some(state.rules.podium_size));
+        state.stats = snapshot.stats;
+        state.next_elimination = snapshot.next_elimination;
+        state.claimed_achievements = snapshot.claimed_achievements;
+        state.crown_history = snapshot.crown_history;
+        state.eliminated = snapshot.eliminated.into_iter()
+            .map(|eliminated| {
+                let respawn_at = now + Duration::from_millis(eliminated.respawn_in);
+                (eliminated.player.id, EliminatedPlayer { player: eliminated.player, respawn_at })
+            })
+            .collect();
+
+        match snapshot.nose_goes {
+            NoseGoesSnapshot::Inactive { starts_in } => {
+                let starts_in = cmp::max(Duration::from_millis(starts_in), first_nose_goes_delay());
+                state.nose_goes = NoseGoes::Inactive { next_start_time: now + starts_in };
+            }
+
+            NoseGoesSnapshot::InProgress { ends_in, remaining_players, taps, elimination } => {
+                let resume_time = now + first_nose_goes_delay();
+                state.nose_goes = NoseGoes::InProgress {
+                    start_time: resume_time,
+                    end_time: resume_time + Duration::from_millis(ends_in),
+                    remaining_players,
+                    taps,
+                    last_tap_at: None,
+                    elimination,
+                };
+                state.resume_nose_goes_at = Some(resume_time);
+            }
+        }
+
+        state
+    }
+
+    /// Adds a new player to the game.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(Internal)` if a player with the same ID is already in the game,
+    /// `Err(GamePaused)` if the server is shutting down, or `Err(InvalidRequest)` or
+    /// `Err(CosmeticLocked)` if `cosmetics` has options that a new player can't pick.
+    pub fn register(
+        &mut self,
+        id: PlayerId,
+        name: String,
+        cosmetics: Cosmetics,
+    ) -> error::Result<PlayerData> {
+        if self.paused {
+            return Err(Error::GamePaused);
+        }
+
+        if self.players.contains_key(&id) {
+            return Err(Error::Internal(format!("Player ID {:?} was registered twice", id)));
+        }
+
+        let score = 0;
+        cosmetics.validate(score)?;
+        let lives = self.rules.lives.unwrap_or(1);
+        let scored_at = self.next_score_sequence();
+        self.stats.registrations += 1;
+        self.players.insert(id, Player {
+            id,
+            name: name.clone(),
+            score,
+            lives,
+            cosmetics: cosmetics.clone(),
+            achievements: Vec::new(),
+            progress: AchievementProgress::default(),
+            partial_points: 0,
+            scored_at,
+            peak_score: 0,
+        });
+
+        // Broadcast to all hosts that a new player has joined.
+        self.host_broadcasts.push(HostBroadcast::PlayerRegister {
+            id,
+            name: name.clone(),
+            score,
+            lives,
+            cosmetics: cosmetics.clone(),
+        });
+
+        // The new player might tie for the crown, e.g. if they're the first player.
+        self.update_ranking(id);
+        self.update_crown();
+        self.update_podium();
+        let has_crown = self.crown.contains(&id);
+
+        Ok(PlayerData { id, name, score, lives, cosmetics, achievements: Vec::new(), has_crown })
+    }
+
+    /// Brings a knocked-out player back into the game with the same ID and name.
+    ///
+    /// The player loses part of their score as set by `rules.respawn`, and starts over with a
+    /// full set of lives.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(InvalidPlayer)` if the player was never knocked out or respawning is turned
+    /// off, `Err(NotEliminated)` if the player is still in the game, `Err(RespawnCooldown)` if
+    /// the player has to wait longer before respawning, or `Err(GamePaused)` if the server is
+    /// shutting down.
+    pub fn respawn(&mut self, id: PlayerId, now: Instant) -> error::Result<PlayerData> {
+        if self.paused {
+            return Err(Error::GamePaused);
+        }
+
+        if self.players.contains_key(&id) {
+            return Err(Error::NotEliminated(id));
+        }
+
+        let penalty_percent = match self.rules.respawn {
+            Some(respawn) => cmp::min(respawn.penalty_percent, 100) as usize,
+            None => return Err(Error::InvalidPlayer(id)),
+        };
+
+        let respawn_at = match self.eliminated.get(&id) {
+            Some(eliminated) => eliminated.respawn_at,
+            None => return Err(Error::InvalidPlayer(id)),
+        };
+        if now < respawn_at {
+            return Err(Error::RespawnCooldown { retry_after: as_millis(respawn_at - now) });
+        }
+
+        let mut player = match self.eliminated.remove(&id) {
+            Some(eliminated) => eliminated.player,
+            None => return Err(Error::InvalidPlayer(id)),
+        };
+        player.peak_score = player.best_score();
+        player.score -= player.score * penalty_percent / 100;
+        player.lives = self.rules.lives.unwrap_or(1);
+        player.scored_at = self.next_score_sequence();
+        self.stats.respawns += 1;
+
+        let (name, score, lives) = (player.name.clone(), player.score, player.lives);
+        let (cosmetics, achievements) = (player.cosmetics.clone(), player.achievements.clone());
+        self.players.insert(id, player);
+
+        // Broadcast to all hosts that the player is back.
+        self.host_broadcasts.push(HostBroadcast::PlayerRespawn {
+            id,
+            name: name.clone(),
+            score,
+            lives,
+            cosmetics: cosmetics.clone(),
+        });
+
+        // The player might tie for the crown, e.g. if they're rejoining an empty game.
+        self.update_ranking(id);
+        self.update_crown();
+        self.update_podium();
+        let has_crown = self.crown.contains(&id);
+
+        Ok(PlayerData { id, name, score, lives, cosmetics, achievements, has_crown })
+    }
+
+    /// Changes how the player's hippo looks, notifying the hosts.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(InvalidPlayer)` if the player isn't in the game, `Err(InvalidRequest)` if an
+    /// option isn't in the catalog, or `Err(CosmeticLocked)` if the player's score hasn't unlocked
+    /// an option yet, or `Err(GamePaused)` if the server is shutting down.
+    pub fn set_cosmetics(
+        &mut self,
+        id: PlayerId,
+        cosmetics: Cosmetics,
+    ) -> error::Result<PlayerData> {
+        if self.paused {
+            return Err(Error::GamePaused);
+        }
+
+        {
+            let player = self.players
+                .get_mut(&id)
+                .ok_or(Error::InvalidPlayer(id))?;
+
+            cosmetics.validate(player.best_score())?;
+            player.cosmetics = cosmetics.clone();
+        }
+
+        self.host_broadcasts.push(HostBroadcast::PlayerCosmetics { id, cosmetics });
+
+        self.player(id).ok_or(Error::InvalidPlayer(id))
+    }
+
+    /// Feeds a player's hippo, increasing the player's score.
+    ///
+    /// A feed is worth one point, unless `rules.catch_up` makes it worth more or less. Returns the
+    /// player's new score. Each feed is charged to the same rate limit as batched taps, so feeding
+    /// one tap at a time isn't a way around it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(InvalidPlayer)` if `id` isn't in the game, `Err(RateLimited)` if the player
+    /// has used up their budget, or `Err(GamePaused)` if the server is shutting down.
+    pub fn feed(&mut self, id: PlayerId, now: Instant) -> error::Result<usize> {
+        if self.paused {
+            return Err(Error::GamePaused);
+        }
+
+        if !self.players.contains_key(&id) {
+            return Err(Error::InvalidPlayer(id));
+        }
+
+        self.spend_taps(id, 1, now)?;
+
+        let has_crown = self.crown.contains(&id);
+        let winner_score = self.winner_score();
+        let catch_up = self.rules.catch_up;
+        let (score, scored) = {
+            let player = self.players
+                .get_mut(&id)
+                .ok_or(Error::InvalidPlayer(id))?;
+
+            let value = catch_up.feed_value(has_crown, player.score, winner_score);
+            let scored = player.add_feeds(1, value);
+            (player.score, scored)
+        };
+        self.stats.feeds += 1;
+        if scored {
+            self.mark_scored(id);
+        }
+
+        // Update the host displays.
+        self.host_broadcasts.push(HostBroadcast::HippoEat { id, score });
+        self.record_achievement_event(id, AchievementEvent::Scored);
+        self.update_crown();
+        self.update_podium();
+
+        Ok(score)
+    }
+
+    /// Feeds a player's hippo once for each tap in `taps`.
+    ///
+    /// `taps` are the client-side times of each tap. Taps closer together than anyone can tap
+    /// (see `plausible_taps`) don't count, and neither do taps beyond the player's rate limit.
+    /// Those taps are dropped rather than rejected, since the client can't know exactly how the
+    /// server's clock lines up with its own. The host displays are updated on the next tick,
+    /// rather than once per tap.
+    ///
+    /// Like separate feeds, each tap's value is worked out from `rules.catch_up` as the player's
+    /// score changes. So a trailing player stops being boosted by the tap that catches them up,
+    /// and a player that takes the crown partway through a batch pays the crown penalty on the
+    /// rest of it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(InvalidRequest)` if there are more than `MAX_FEED_BATCH` taps or the taps are
+    /// out of order, `Err(InvalidPlayer)` if `id` isn't in the game, or `Err(GamePaused)` if the
+    /// server is shutting down. Returns `Err(RateLimited)` if none of the taps could be accepted
+    /// because the player has used up their budget.
+    pub fn feed_batch(
+        &mut self,
+        id: PlayerId,
+        taps: &[u64],
+        now: Instant,
+    ) -> error::Result<FeedBatchResponse> {
+        if self.paused {
+            return Err(Error::GamePaused);
+        }
+
+        if taps.len() > MAX_FEED_BATCH {
+            return Err(Error::InvalidRequest(format!(
+                "A batch can have at most {} taps, but {} were sent",
+                MAX_FEED_BATCH,
+                taps.len(),
+            )));
+        }
+
+        if taps.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(Error::InvalidRequest("Taps must be in the order they happened".into()));
+        }
+
+        let mut score = match self.players.get(&id) {
+            Some(player) => player.score,
+            None => return Err(Error::InvalidPlayer(id)),
+        };
+
+        let accepted = self.spend_taps(id, plausible_taps(taps), now)?;
+
+        let catch_up = self.rules.catch_up;
+        for _ in 0 .. accepted {
+            let has_crown = self.crown.contains(&id);
+            let winner_score = self.winner_score();
+            let scored = {
+                let player = self.players
+                    .get_mut(&id)
+                    .ok_or(Error::InvalidPlayer(id))?;
+
+                let value = catch_up.feed_value(has_crown, player.score, winner_score);
+                let scored = player.add_feeds(1, value);
+                score = player.score;
+                scored
+            };
+
+            if scored {
+                // The eat is queued before the crown is updated, so that hosts see the score that
+                // won the crown before the crown changes hands.
+                self.mark_scored(id);
+                self.pending_eats.insert(id);
+                self.update_crown();
+            }
+        }
+        self.stats.feeds += accepted;
+
+        if accepted > 0 {
+            self.record_achievement_event(id, AchievementEvent::Scored);
+            self.update_podium();
+        }
+
+        Ok(FeedBatchResponse { accepted, score })
+    }
+
+    /// Handles a player tapping their poison marble during a nose-goes event.
+    ///
+    /// The tap is only recorded here, and the response is always `Pending`. Who survives is
+    /// decided when the event ends, by ordering the taps by when they happened after compensating
+    /// for latency (see `TapTiming`). That way players on slow connections aren't eliminated just
+    /// because their taps took longer to arrive.
+    ///
+    /// Taps are idempotent: If the player has already tapped during the current event, the
+    /// response to their first tap is returned again and nothing else changes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(NoseGoesNotActive)` if there is no nose-goes event in progress, or
+    /// `Err(NotInRound)` if the player isn't a part of the current event. Returns `Err(GamePaused)`
+    /// if the server is shutting down.
+    pub fn tap(
+        &mut self,
+        id: PlayerId,
+        timing: TapTiming,
+        now: Instant,
+    ) -> error::Result<NoseGoesResponse> {
+        if self.paused {
+            return Err(Error::GamePaused);
+        }
+
+        let received = self.clock.unix_millis(now);
+        match self.nose_goes {
+            NoseGoes::Inactive { .. } => {
+                Err(Error::NoseGoesNotActive)
+            }
+
+            NoseGoes::InProgress {
+                ref mut remaining_players,
+                ref mut taps,
+                ref mut last_tap_at,
+                ..
+            } => {
+                if let Some(tap) = taps.get(&id) {
+                    return Ok(tap.outcome);
+                }
+
+                // It's an error for the player to not be part of the nose-goes event.
+                if !remaining_players.remove(&id) {
+                    return Err(Error::NotInRound(id));
+                }
+
+                taps.insert(id, NoseGoesTap {
+                    received,
+                    time: timing.compensate(received),
+                    outcome: NoseGoesResponse::Pending,
+                });
+                *last_tap_at = Some(now);
+
+                Ok(NoseGoesResponse::Pending)
+            }
+        }
+    }
+
+    /// Advances the game to `now`, starting or ending nose-goes events as necessary.
+    pub fn tick(&mut self, now: Instant) {
+        if self.paused {
+            return;
+        }
+
+        self.flush_pending_eats();
+        self.update_crown_history(now);
+
+        self.resume_nose_goes(now);
+
+        // Match the current nose-goes state, and return the new state.
+        //
+        // NOTE: We use `mem::replace` to move the current state out of `self`, so that we can
+        // safely destructure it while mutating the rest of the game state.
+        self.nose_goes = match mem::replace(&mut self.nose_goes, NoseGoes::default()) {
+            NoseGoes::Inactive { next_start_time } => {
+                if now > next_start_time {
+                    if self.players.len() > 1 {
+                        // Add all players to the nose-goes event.
+                        let remaining_players: HashSet<PlayerId> = self.players.keys().cloned().collect();
+                        let elimination = self.next_elimination_rule();
+                        self.stats.nose_goes_started += 1;
+
+                        let end_time = next_start_time + nose_goes_duration();
+                        self.announce_nose_goes(
+                            now,
+                            next_start_time,
+                            end_time,
+                            &remaining_players,
+                            elimination,
+                        );
+
+                        NoseGoes::InProgress {
+                            start_time: next_start_time,
+                            end_time,
+                            remaining_players,
+                            taps: HashMap::new(),
+                            last_tap_at: None,
+                            elimination,
+                        }
+                    } else {
+                        // There aren't enough players to run the nose-goes event. Delay until
+                        // later.
+                        let next_start_time = next_start_time + nose_goes_interval();
+                        NoseGoes::Inactive { next_start_time }
+                    }
+                } else {
+                    NoseGoes::Inactive { next_start_time }
+                }
+            }
+
+            NoseGoes::InProgress {
+                start_time,
+                end_time,
+                remaining_players,
+                taps,
+                last_tap_at,
+                elimination,
+            } => {
+                // Under most rules the outcome is known once at most one player is left, but a tap
+                // that arrives a little later could still have happened earlier than the last one
+                // to arrive. So we give any such taps time to arrive before ending the event.
+                let settled = match last_tap_at {
+                    Some(last_tap_at) => now >= last_tap_at + nose_goes_settle_time(),
+                    None => true,
+                };
+                let decided = remaining_players.len() <= 1
+                    && settled
+                    && elimination.rule().ends_when_one_remains();
+
+                if now > end_time || decided {
+                    self.end_nose_goes(remaining_players, taps, elimination, end_time, now);
+                    NoseGoes::Inactive { next_start_time: end_time + nose_goes_interval() }
+                } else {
+                    NoseGoes::InProgress {
+                        start_time,
+                        end_time,
+                        remaining_players,
+                        taps,
+                        last_tap_at,
+                        elimination,
+                    }
+                }
+            }
+        };
+    }
+
+    /// Returns the current state of the player with the given ID, if they're in the game.
+    pub fn player(&self, id: PlayerId) -> Option<PlayerData> {
+        self.players.get(&id).map(|player| self.player_data(player))
+    }
+
+    /// Returns the current state of all players in the game.
+    pub fn players(&self) -> Vec<PlayerData> {
+        self.players.values()
+            .map(|player| self.player_data(player))
+            .collect()
+    }
+
+    /// Returns the top `limit` players by score, or every player if `limit` is `None`.
+    pub fn rankings(&self, limit: Option<usize>) -> Vec<RankedPlayer> {
+        let ranks = self.ranking.top(limit);
+        self.ranked_players(&ranks)
+    }
+
+    /// Returns who has worn the crown and for how long, oldest first.
+    ///
+    /// The duration of the current tenure (if any) is measured up to `now`.
+    pub fn crown_history(&self, now: Instant) -> Vec<CrownTenure> {
+        let now_millis = self.clock.unix_millis(now);
+        self.crown_history.iter()
+            .cloned()
+            .map(|mut tenure| {
+                if tenure.ended_at.is_none() {
+                    tenure.duration = now_millis.saturating_sub(tenure.started_at);
+                }
+                tenure
+            })
+            .collect()
+    }
+
+    /// Returns how the player is doing in the current nose-goes event, or how they did in the most
+    /// recent one if no event is in progress.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(InvalidPlayer)` if `id` isn't in the game and wasn't part of the event. Players
+    /// that were eliminated by the event can still check their result.
+    pub fn nose_goes_status(&self, id: PlayerId) -> error::Result<NoseGoesStatus> {
+        let status = match self.nose_goes {
+            NoseGoes::InProgress { ref remaining_players, ref taps, .. } => {
+                let tap = taps.get(&id).cloned();
+                NoseGoesStatus {
+                    active: true,
+                    in_round: tap.is_some() || remaining_players.contains(&id),
+                    outcome: None,
+                    tap,
+                }
+            }
+
+            NoseGoes::Inactive { .. } => {
+                let tap = self.last_round.taps.get(&id).cloned();
+                let outcome = if self.last_round.losers.contains(&id) {
+                    Some(NoseGoesResponse::Died)
+                } else if self.last_round.lost_life.contains(&id) {
+                    Some(NoseGoesResponse::LostLife)
+                } else {
+                    tap.map(|tap| tap.outcome)
+                };
+
+                NoseGoesStatus {
+                    active: false,
+                    in_round: outcome.is_some(),
+                    outcome,
+                    tap,
+                }
+            }
+        };
+
+        if !status.in_round && !self.players.contains_key(&id) {
+            return Err(Error::InvalidPlayer(id));
+        }
+
+        Ok(status)
+    }
+
+    /// Sets the rules for the game.
+    ///
+    /// If `rules` doesn't have any elimination rules the default rule is used for every event.
+    pub fn set_rules(&mut self, mut rules: GameRules) {
+        if rules.elimination.is_empty() {
+            rules.elimination.push(Elimination::default());
+        }
+        self.rules = rules;
+    }
+
+    /// Returns the rules the game is being played by.
+    pub fn rules(&self) -> &GameRules {
+        &self.rules
+    }
+
+    /// Picks the elimination rule for the next nose-goes event, overriding the usual cycle for
+    /// that one event.
+    pub fn set_next_elimination(&mut self, elimination: Elimination) {
+        self.next_elimination = Some(elimination);
+    }
+
+    /// Returns the elimination rule for the nose-goes event that's starting now.
+    fn next_elimination_rule(&mut self) -> Elimination {
+        match self.next_elimination.take() {
+            Some(elimination) => elimination,
+            None => {
+                let index = self.stats.nose_goes_started % self.rules.elimination.len();
+                self.rules.elimination[index]
+            }
+        }
+    }
+
+    /// Pauses the game because the server is shutting down, notifying players and hosts.
+    ///
+    /// Once paused, the game rejects all player input and ticks no longer advance the game.
+    pub fn shut_down(&mut self) {
+        if self.paused {
+            return;
+        }
+
+        self.paused = true;
+        self.flush_pending_eats();
+        self.host_broadcasts.push(HostBroadcast::ServerShuttingDown {});
+        self.player_broadcasts.push(PlayerBroadcast::ServerShuttingDown {});
+    }
+
+    /// Takes a snapshot of the game that can be used to [`restore`] it later.
+    ///
+    /// Nose-goes timers are saved relative to `now`.
+    ///
+    /// [`restore`]: #method.restore
+    pub fn snapshot(&self, now: Instant) -> Snapshot {
+        let nose_goes = match self.nose_goes {
+            NoseGoes::Inactive { next_start_time } => {
+                NoseGoesSnapshot::Inactive {
+                    starts_in: as_millis(duration_until(now, next_start_time)),
+                }
+            }
+
+            NoseGoes::InProgress {
+                start_time,
+                end_time,
+                ref remaining_players,
+                ref taps,
+                elimination,
+                ..
+            } => {
+                // If the event hasn't been re-announced since the game was last restored, then
+                // it hasn't really started yet and the full duration remains.
+                let ends_in = if start_time > now {
+                    duration_until(start_time, end_time)
+                } else {
+                    duration_until(now, end_time)
+                };
+
+                NoseGoesSnapshot::InProgress {
+                    ends_in: as_millis(ends_in),
+                    remaining_players: remaining_players.clone(),
+                    taps: taps.clone(),
+                    elimination,
+                }
+            }
+        };
+
+        Snapshot {
+            players: self.players.values().cloned().collect(),
+            winner: self.crown.first().cloned(),
+            crown: self.crown.clone(),
+            nose_goes,
+            stats: self.stats.clone(),
+            next_elimination: self.next_elimination,
+            claimed_achievements: self.claimed_achievements.clone(),
+
+            // The server is about to go down, so the current tenures end now. If the crown holders
+            // still have the crown once the game is restored, they start new tenures.
+            crown_history: self.crown_history(now).into_iter()
+                .map(|mut tenure| {
+                    if tenure.ended_at.is_none() {
+                        tenure.ended_at = Some(tenure.started_at + tenure.duration);
+                    }
+                    tenure
+                })
+                .collect(),
+            eliminated: self.eliminated.values()
+                .map(|eliminated| EliminatedSnapshot {
+                    player: eliminated.player.clone(),
+                    respawn_in: as_millis(duration_until(now, eliminated.respawn_at)),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns statistics about the game so far.
+    pub fn stats(&self) -> GameStats {
+        GameStats {
+            players: self.players.len(),
+            connections: self.connections,
+            .. self.stats.clone()
+        }
+    }
+
+    /// Updates the number of clients connected to each websocket stream.
+    ///
+    /// Connection counts don't affect the game rules, so they aren't recorded in the event log.
+    pub fn set_connections(&mut self, connections: ConnectionCounts) {
+        self.connections = connections;
+    }
+
+    /// Takes all broadcasts that have been queued since the last call to `take_broadcasts`.
+    ///
+    /// Returns the host broadcasts and the player broadcasts, in the order they were queued.
+    pub fn take_broadcasts(&mut self) -> (Vec<HostBroadcast>, Vec<PlayerBroadcast>) {
+        (
+            mem::replace(&mut self.host_broadcasts, Vec::new()),
+            mem::replace(&mut self.player_broadcasts, Vec::new()),
+        )
+    }
+
+    /// Re-announces a nose-goes event that was in progress when the game was restored, once
+    /// clients have had time to reconnect.
+    fn resume_nose_goes(&mut self, now: Instant) {
+        match self.resume_nose_goes_at {
+            Some(resume_time) if now >= resume_time => {}
+            _ => return,
+        }
+        self.resume_nose_goes_at = None;
+
+        let (start_time, end_time, remaining_players, elimination) = match self.nose_goes {
+            NoseGoes::InProgress {
+                start_time,
+                end_time,
+                ref remaining_players,
+                elimination,
+                ..
+            } => {
+                (start_time, end_time, remaining_players.clone(), elimination)
+            }
+
+            NoseGoes::Inactive { .. } => return,
+        };
+        self.announce_nose_goes(now, start_time, end_time, &remaining_players, elimination);
+    }
+
+    /// Tells players and hosts that a nose-goes event between `start_time` and `end_time` has
+    /// begun.
+    fn announce_nose_goes(
+        &mut self,
+        now: Instant,
+        start_time: Instant,
+        end_time: Instant,
+        players: &HashSet<PlayerId>,
+        elimination: Elimination,
+    ) {
+        let start_time = self.clock.unix_millis(start_time);
+        let duration = as_millis(duration_until(now, end_time));
+        let end_time = self.clock.unix_millis(end_time);
+
+        self.host_broadcasts.push(HostBroadcast::BeginNoseGoes {
+            duration,
+            start_time,
+            end_time,
+            players: players.clone(),
+            elimination,
+        });
+        self.player_broadcasts.push(PlayerBroadcast::BeginNoseGoes { start_time, end_time });
+    }
+
+    /// Counts `event` towards the player's achievements, announcing any achievements that it
+    /// unlocks.
+    fn record_achievement_event(&mut self, id: PlayerId, event: AchievementEvent) {
+        let unlocked = match self.players.get_mut(&id) {
+            Some(player) => {
+                player.progress.record(event);
+                let unlocked = achievements::newly_earned(player, &self.claimed_achievements);
+                player.achievements.extend(unlocked.iter().cloned());
+                unlocked
+            }
+
+            None => return,
+        };
+
+        for achievement in unlocked {
+            if achievement.is_unique() {
+                self.claimed_achievements.insert(achievement);
+            }
+            self.stats.achievements_unlocked += 1;
+
+            self.host_broadcasts.push(HostBroadcast::AchievementUnlocked {
+                id,
+                achievement,
+                title: achievement.title(),
+                description: achievement.description(),
+            });
+            self.player_broadcasts.push(PlayerBroadcast::AchievementUnlocked {
+                id,
+                achievement,
+                title: achievement.title(),
+                description: achievement.description(),
+            });
+        }
+    }
+
+    /// Keeps the crown history up to date, closing the tenures of players that have lost the
+    /// crown and starting new ones for players that have gained it.
+    ///
+    /// Crown changes are only noticed on the next tick, so tenures are accurate to within a tick.
+    /// A shared crown has an open tenure for each holder.
+    fn update_crown_history(&mut self, now: Instant) {
+        let now_millis = self.clock.unix_millis(now);
+        let crown = self.crown.clone();
+
+        let mut reigns = Vec::new();
+        for tenure in self.crown_history.iter_mut().filter(|tenure| tenure.ended_at.is_none()) {
+            tenure.duration = now_millis.saturating_sub(tenure.started_at);
+            if crown.contains(&tenure.id) {
+                reigns.push((tenure.id, tenure.duration));
+            } else {
+                tenure.ended_at = Some(now_millis);
+            }
+        }
+
+        for &(id, held_for) in &reigns {
+            self.record_achievement_event(id, AchievementEvent::HeldCrown(held_for));
+        }
+
+        for id in crown {
+            if reigns.iter().any(|&(holder, _)| holder == id) {
+                continue;
+            }
+
+            let name = self.players.get(&id)
+                .map(|player| player.name.clone())
+                .unwrap_or_default();
+            self.crown_history.push(CrownTenure {
+                id,
+                name,
+                started_at: now_millis,
+                ended_at: None,
+                duration: 0,
+            });
+        }
+
+        if self.crown_history.len() > MAX_CROWN_HISTORY {
+            let excess = self.crown_history.len() - MAX_CROWN_HISTORY;
+            self.crown_history.drain(.. excess);
+        }
+    }
+
+    /// Forgets the players that were knocked out longest ago once there are more than
+    /// `MAX_ELIMINATED`. Forgotten players can't respawn.
+    fn prune_eliminated(&mut self) {
+        while self.eliminated.len() > MAX_ELIMINATED {
+            let oldest = self.eliminated.iter()
+                .min_by_key(|&(&id, eliminated)| (eliminated.respawn_at, id))
+                .map(|(&id, _)| id);
+            match oldest {
+                Some(id) => { self.eliminated.remove(&id); }
+                None => break,
+            }
+        }
+    }
+
+    /// Broadcasts a `HippoEat` with the latest score for each player that was fed by a batch of
+    /// taps since the last flush.
+    ///
+    /// This is done before any other broadcast that depends on scores (e.g. a new winner or the
+    /// start of a nose-goes event) so that the host displays see the scores first.
+    fn flush_pending_eats(&mut self) {
+        for id in mem::replace(&mut self.pending_eats, HashSet::new()) {
+            // The player may have been knocked out since they were fed.
+            if let Some(player) = self.players.get(&id) {
+                self.host_broadcasts.push(HostBroadcast::HippoEat { id, score: player.score });
+            }
+        }
+    }
+
+    /// Knocks out the players picked by the event's elimination rule and awards the bonus points.
+    ///
+    /// The bonus is divided between the survivors by the payout strategy, and only goes to the
+    /// player wearing the crown if the rules allow it. Both the losers and the bonus are decided
+    /// by when the taps happened after compensating for latency, rather than by when they arrived.
+    fn end_nose_goes(
+        &mut self,
+        remaining_players: HashSet<PlayerId>,
+        mut taps: HashMap<PlayerId, NoseGoesTap>,
+        elimination: Elimination,
+        end_time: Instant,
+        now: Instant,
+    ) {
+        // Order the taps by when they happened, breaking ties by when they arrived. Taps that
+        // arrived at the same time are ordered by player ID, so that the order doesn't depend on
+        // the map's iteration order and replays of the event log pick the same losers.
+        let mut order = taps.iter()
+            .map(|(&id, tap)| (tap.time, tap.received, id))
+            .collect::<Vec<_>>();
+        order.sort_by_key(|&(time, received, id)| (time, received, id));
+        let order = order.into_iter().map(|(_, _, id)| id).collect::<Vec<_>>();
+
+        let losers = elimination.rule().losers(&order, &remaining_players);
+
+        let crown = self.crown.clone();
+        let crown_can_win_bonus = self.rules.crown_can_win_bonus;
+        let deadline = self.clock.unix_millis(end_time);
+        let candidates = order.iter()
+            .filter(|id| !losers.contains(*id))
+            .filter(|id| crown_can_win_bonus || !crown.contains(*id))
+            .map(|id| BonusCandidate {
+                id: *id,
+                time_left: deadline.saturating_sub(taps[id].time),
+            })
+            .collect::<Vec<_>>();
+
+        // Losers that have lives left only lose a life, the rest are knocked out.
+        let mut knocked_out = HashSet::new();
+        let mut lost_life = HashSet::new();
+        let mut loser_scores = Vec::new();
+        for loser in &losers {
+            // NOTE: The loser should always be in the player map, but if the state got out of
+            // sync we skip them rather than crash the game loop.
+            let lives = match self.players.get_mut(loser) {
+                Some(player) => {
+                    player.lives = player.lives.saturating_sub(1);
+                    player.lives
+                }
+                None => {
+                    warn!("Nose-goes loser {:?} wasn't in player map", loser);
+                    continue;
+                }
+            };
+
+            if lives > 0 {
+                self.host_broadcasts.push(HostBroadcast::LifeLost { id: *loser, lives });
+                self.player_broadcasts.push(PlayerBroadcast::LifeLost { id: *loser, lives });
+                self.stats.lives_lost += 1;
+                lost_life.insert(*loser);
+
+                // Players that only lose a life keep their score, so they don't add anything to
+                // the bonus pot.
+                continue;
+            }
+
+            // Remove the player from the players map, holding on to them if they can respawn.
+            self.tap_budgets.remove(loser);
+            let loser_info = self.players.remove(loser).expect("Loser wasn't in player map");
+            self.ranking.remove(*loser);
+            let respawn_at = self.rules.respawn
+                .map(|respawn| now + Duration::from_millis(respawn.cooldown));
+            let clock = self.clock;
+            self.player_broadcasts.push(PlayerBroadcast::PlayerLose {
+                id: *loser,
+                score: loser_info.score,
+                respawn_at: respawn_at.map(|respawn_at| clock.unix_millis(respawn_at)),
+            });
+            self.stats.eliminations += 1;
+
+            knocked_out.insert(*loser);
+            loser_scores.push(loser_info.score);
+
+            if let Some(respawn_at) = respawn_at {
+                self.eliminated.insert(*loser, EliminatedPlayer { player: loser_info, respawn_at });
+                self.prune_eliminated();
+            }
+        }
+
+        for (id, tap) in taps.iter_mut() {
+            tap.outcome = if knocked_out.contains(id) {
+                NoseGoesResponse::Died
+            } else if losers.contains(id) {
+                NoseGoesResponse::LostLife
+            } else {
+                NoseGoesResponse::Survived
+            };
+        }
+
+        // Apply the bonus points.
+        let mut payouts = Vec::new();
+        for (id, amount) in self.rules.payout.strategy().payouts(&loser_scores, &candidates) {
+            if amount == 0 {
+                continue;
+            }
+
+            // The bonus winner may have been removed from the map if the state got out of sync,
+            // in which case they don't get the bonus.
+            if let Some(player) = self.players.get_mut(&id) {
+                // NOTE: The sequence is bumped directly since `next_score_sequence` would need
+                // to borrow all of `self` while the player is borrowed.
+                self.score_sequence += 1;
+                player.score += amount;
+                player.scored_at = self.score_sequence;
+                self.ranking.update(id, player.score, player.scored_at);
+                self.stats.bonus_points_awarded += amount;
+                payouts.push(BonusPayout { id, amount, score: player.score });
+            }
+        }
+
+        for payout in &payouts {
+            self.host_broadcasts.push(HostBroadcast::BonusWinner { id: payout.id });
+            self.player_broadcasts.push(PlayerBroadcast::BonusWinner { id: payout.id });
+        }
+        let bonus_winner = payouts.first().map(|payout| (payout.id, payout.score));
+
+        // Count the event towards the survivors' achievements.
+        let survivors = taps.iter()
+            .filter(|&(_, tap)| tap.outcome == NoseGoesResponse::Survived)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        for id in survivors {
+            self.record_achievement_event(id, AchievementEvent::SurvivedNoseGoes);
+        }
+        for payout in &payouts {
+            self.record_achievement_event(payout.id, AchievementEvent::WonBonus);
+        }
+
+        // Recalculate the crown and podium after all losers have been removed.
+        self.update_crown();
+        self.update_podium();
+
+        self.last_round = NoseGoesRound {
+            taps,
+            losers: knocked_out.clone(),
+            lost_life,
+        };
+
+        // Broadcast player loss to players and hosts.
+        self.host_broadcasts.push(HostBroadcast::EndNoseGoes {
+            losers: knocked_out,
+            bonus_winner,
+            payouts: payouts.clone(),
+        });
+        self.player_broadcasts.push(PlayerBroadcast::EndNoseGoes { bonus_winner, payouts });
+    }
+
+    /// Works out who should wear the crown, notifying players and hosts if it has changed hands.
+    ///
+    /// The players with the highest score are taken from the ranking in the order they reached
+    /// their score, and the tie-break policy picks which of them get the crown.
+    fn update_crown(&mut self) {
+        let leaders = self.ranking.leaders();
+        let crown = self.rules.tie_break.policy().holders(&leaders);
+        if crown == self.crown {
+            return;
+        }
+
+        self.flush_pending_eats();
+        self.crown = crown.clone();
+        self.host_broadcasts.push(HostBroadcast::UpdateWinner { holders: crown.clone() });
+        self.player_broadcasts.push(PlayerBroadcast::UpdateWinner { holders: crown });
+    }
+
+    /// Moves the player to their current place in the ranking.
+    ///
+    /// This needs to be called whenever a player's score changes.
+    fn update_ranking(&mut self, id: PlayerId) {
+        match self.players.get(&id) {
+            Some(player) => self.ranking.update(id, player.score, player.scored_at),
+            None => self.ranking.remove(id),
+        }
+    }
+
+    /// Announces the top of the ranking to the hosts if anybody has moved on or off the podium, or
+    /// changed places on it.
+    ///
+    /// Score changes that don't move anybody are left to the `HippoEat` broadcasts.
+    fn update_podium(&mut self) {
+        let podium = self.ranking.top(Some(self.rules.podium_size));
+        if podium == self.podium {
+            return;
+        }
+
+        self.flush_pending_eats();
+        self.podium = podium;
+        let top = self.ranked_players(&self.podium);
+        self.host_broadcasts.push(HostBroadcast::RankingChanged { top });
+    }
+
+    /// Fills in the details for each rank and ID from the ranking.
+    fn ranked_players(&self, ranks: &[(usize, PlayerId)]) -> Vec<RankedPlayer> {
+        ranks.iter()
+            .filter_map(|&(rank, id)| {
+                self.players.get(&id).map(|player| {
+                    RankedPlayer {
+                        rank,
+                        id,
+                        name: player.name.clone(),
+                        score: player.score,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Spends up to `count` feeds from the player's tap budget, returning how many were allowed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(RateLimited)` if none of the feeds were allowed because the player has used up
+    /// their budget.
+    fn spend_taps(&mut self, id: PlayerId, count: usize, now: Instant) -> error::Result<usize> {
+        let budget = self.tap_budgets
+            .entry(id)
+            .or_insert_with(|| TapBudget::new(now));
+        let allowed = budget.spend(count, now);
+        if allowed == 0 && count > 0 {
+            return Err(Error::RateLimited { retry_after: as_millis(budget.retry_after()) });
+        }
+
+        Ok(allowed)
+    }
+
+    /// Records that the player's score has just changed, moving them to their new place in the
+    /// ranking.
+    ///
+    /// Each call advances the sequence that crown ties are broken by, so this is only called once
+    /// the player's score has actually changed.
+    fn mark_scored(&mut self, id: PlayerId) {
+        let sequence = self.next_score_sequence();
+        if let Some(player) = self.players.get_mut(&id) {
+            player.scored_at = sequence;
+        }
+        self.update_ranking(id);
+    }
+
+    /// Returns the next value for `Player::scored_at`.
+    fn next_score_sequence(&mut self) -> u64 {
+        self.score_sequence += 1;
+        self.score_sequence
+    }
+
+    /// Returns the score of the crown holders, or 0 if nobody has the crown.
+    fn winner_score(&self) -> usize {
+        self.crown.iter()
+            .filter_map(|id| self.players.get(id))
+            .map(|player| player.score)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Builds the host-facing data for `player`.
+    fn player_data(&self, player: &Player) -> PlayerData {
+        PlayerData {
+            id: player.id,
+            name: player.name.clone(),
+            score: player.score,
+            lives: player.lives,
+            cosmetics: player.cosmetics.clone(),
+            achievements: player.achievements.clone(),
+            has_crown: self.crown.contains(&player.id),
+        }
+    }
+}
+
+/// A saved copy of the game state, used to resume the game after the server restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub players: Vec<Player>,
+
+    /// The first of the crown holders, for older servers that only support a single one.
+    pub winner: Option<PlayerId>,
+
+    pub nose_goes: NoseGoesSnapshot,
+    pub stats: GameStats,
+
+    #[serde(default)]
+    pub next_elimination: Option<Elimination>,
+
+    #[serde(default)]
+    pub eliminated: Vec<EliminatedSnapshot>,
+
+    #[serde(default)]
+    pub claimed_achievements: HashSet<Achievement>,
+
+    #[serde(default)]
+    pub crown_history: Vec<CrownTenure>,
+
+    #[serde(default)]
+    pub crown: Vec<PlayerId>,
+}
+
+/// A knocked-out player that can still respawn, as saved in a `Snapshot`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EliminatedSnapshot {
+    pub player: Player,
+
+    /// How long until the player can respawn, in milliseconds.
+    pub respawn_in: u64,
+}
+
+/// A saved copy of the nose-goes state.
+///
+/// Times are stored in milliseconds relative to when the snapshot was taken, since `Instant`
+/// values are meaningless once the server restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NoseGoesSnapshot {
+    Inactive {
+        starts_in: u64,
+    },
+
+    InProgress {
+        ends_in: u64,
+        remaining_players: HashSet<PlayerId>,
+
+        #[serde(default)]
+        taps: HashMap<PlayerId, NoseGoesTap>,
+
+        #[serde(default)]
+        elimination: Elimination,
+    },
+}
+
+/// Converts `duration` to a whole number of milliseconds.
+pub fn as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000 + duration.subsec_nanos() as u64 / 1_000_000
+}
+
+/// Returns the time from `now` until `then`, or zero if `then` has already passed.
+pub fn duration_until(now: Instant, then: Instant) -> Duration {
+    if then > now { then - now } else { Duration::from_millis(0) }
+}
+
+// NOTE: These should be `const`, but you can't make a const `Duration`.
+fn first_nose_goes_delay() -> Duration { Duration::from_millis(10_000) }
+fn nose_goes_duration() -> Duration { Duration::from_millis(10_000) }
+fn nose_goes_interval() -> Duration { Duration::from_millis(30_000) }
+fn tap_tolerance() -> Duration { Duration::from_millis(250) }
+
+/// How long to wait after the last tap arrives before ending a nose-goes event early.
+///
+/// A tap's compensated time can be up to twice `tap_tolerance` before it arrived, so any tap that
+/// arrives later than this can't have happened before the last one.
+fn nose_goes_settle_time() -> Duration { tap_tolerance() * 2 }
+
+/// State information for nose-goes events.
+#[derive(Debug)]
+pub enum NoseGoes {
+    Inactive {
+        next_start_time: Instant,
+    },
+
+    InProgress {
+        start_time: Instant,
+        end_time: Instant,
+        /// The players that are part of the event but haven't tapped yet.
+        remaining_players: HashSet<PlayerId>,
+
+        /// The taps made during the event so far.
+        taps: HashMap<PlayerId, NoseGoesTap>,
+
+        /// When the most recent tap arrived.
+        last_tap_at: Option<Instant>,
+
+        /// The rule that decides who is knocked out when the event ends.
+        elimination: Elimination,
+    }
+}
+
+impl Default for NoseGoes {
+    fn default() -> NoseGoes {
+        NoseGoes::Inactive {
+            next_start_time: Instant::now() + first_nose_goes_delay(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::*;
+
+    /// Asserts that `result` is an error matching `pattern`.
+    macro_rules! assert_err {
+        ($result:expr, $pattern:pat) => {
+            match $result {
+                Err($pattern) => {}
+                result => panic!("Expected {}, got {:?}", stringify!($pattern), result),
+            }
+        };
+    }
+
+    /// The wall-clock time that test games start at, in milliseconds since the Unix epoch.
+    const START_MILLIS: u64 = 1_500_000_000_000;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// Starts a game playing by `rules`, returning it along with the instant that it started.
+    fn start_game(rules: GameRules) -> (GameState, Instant) {
+        let start = Instant::now();
+        let mut state = GameState::new(start, Clock::anchored(start, START_MILLIS));
+        state.set_rules(rules);
+        (state, start)
+    }
+
+    /// Registers `count` new players, in order.
+    fn join(state: &mut GameState, count: usize) -> Vec<PlayerId> {
+        let ids = test_player_ids(count);
+        for &id in &ids {
+            state.register(id, format!("Hippo {}", id), Cosmetics::default()).unwrap();
+        }
+        ids
+    }
+
+    /// Sets the player's score, as if they had just fed their way to it.
+    fn set_score(state: &mut GameState, id: PlayerId, score: usize) {
+        state.players.get_mut(&id).unwrap().score = score;
+        state.mark_scored(id);
+        state.update_crown();
+        state.update_podium();
+    }
+
+    fn in_progress(state: &GameState) -> bool {
+        match state.nose_goes {
+            NoseGoes::InProgress { .. } => true,
+            NoseGoes::Inactive { .. } => false,
+        }
+    }
+
+    fn outcome(state: &GameState, id: PlayerId) -> Option<NoseGoesResponse> {
+        state.nose_goes_status(id).unwrap().outcome
+    }
+
+    /// Starts the first nose-goes event of a game that started at `start`, returning when it
+    /// started.
+    fn begin_nose_goes(state: &mut GameState, start: Instant) -> Instant {
+        let begin = start + first_nose_goes_delay() + millis(1);
+        state.tick(begin);
+        assert!(in_progress(state));
+        begin
+    }
+
+    /// Ends the first nose-goes event of a game that started at `start` by running it to its
+    /// deadline, returning when it ended.
+    fn finish_nose_goes(state: &mut GameState, start: Instant) -> Instant {
+        let end = start + first_nose_goes_delay() + nose_goes_duration() + millis(1);
+        state.tick(end);
+        assert!(!in_progress(state));
+        end
+    }
+
+    /// Runs the first nose-goes event with `tappers` tapping in order, 100 ms apart.
+    fn run_nose_goes(state: &mut GameState, start: Instant, tappers: &[PlayerId]) -> Instant {
+        let begin = begin_nose_goes(state, start);
+        for (index, &id) in tappers.iter().enumerate() {
+            let now = begin + millis(100 * (index as u64 + 1));
+            state.tap(id, TapTiming::default(), now).unwrap();
+        }
+        finish_nose_goes(state, start)
+    }
+
+    #[test]
+    fn feeds_are_worth_a_point_without_catch_up() {
+        let catch_up = CatchUp::default();
+        assert_eq!(catch_up.feed_value(true, 500, 500), 100);
+        assert_eq!(catch_up.feed_value(false, 0, 500), 100);
+        assert_eq!(catch_up.feed_value(false, 0, 0), 100);
+    }
+
+    #[test]
+    fn crown_penalty_is_capped() {
+        let catch_up = CatchUp { crown_penalty: 25, ..CatchUp::default() };
+        assert_eq!(catch_up.feed_value(true, 500, 500), 75);
+
+        let catch_up = CatchUp { crown_penalty: 150, ..CatchUp::default() };
+        assert_eq!(catch_up.feed_value(true, 500, 500), 0);
+    }
+
+    #[test]
+    fn trailing_players_are_boosted() {
+        let catch_up = CatchUp { crown_penalty: 0, trailing_boost: 50, trailing_below: 50 };
+        assert_eq!(catch_up.feed_value(false, 249, 500), 150);
+        assert_eq!(catch_up.feed_value(false, 250, 500), 100);
+        assert_eq!(catch_up.feed_value(false, 400, 500), 100);
+    }
+
+    #[test]
+    fn tap_budget_allows_a_burst() {
+        let now = Instant::now();
+        let mut budget = TapBudget::new(now);
+        assert_eq!(budget.spend(MAX_TAP_BURST + 10, now), MAX_TAP_BURST);
+        assert_eq!(budget.spend(1, now), 0);
+    }
+
+    #[test]
+    fn tap_budget_refills_one_tap_at_a_time() {
+        let now = Instant::now();
+        let refill = Duration::from_millis(1_000 / MAX_TAPS_PER_SECOND as u64);
+        let mut budget = TapBudget::new(now);
+        budget.spend(MAX_TAP_BURST, now);
+
+        let mut early = budget;
+        assert_eq!(early.spend(1, now + refill - Duration::from_millis(1)), 0);
+        assert_eq!(budget.spend(5, now + refill), 1);
+        assert_eq!(budget.spend(5, now + refill), 0);
+        assert_eq!(budget.spend(5, now + refill * 3), 2);
+    }
+
+    #[test]
+    fn tap_budget_never_exceeds_the_burst() {
+        let now = Instant::now();
+        let mut budget = TapBudget::new(now);
+        budget.spend(1, now);
+
+        let later = now + Duration::from_secs(60);
+        assert_eq!(budget.spend(MAX_TAP_BURST + 10, later), MAX_TAP_BURST);
+    }
+
+    #[test]
+    fn tap_budget_ignores_time_going_backwards() {
+        let now = Instant::now() + Duration::from_secs(1);
+        let mut budget = TapBudget::new(now);
+        budget.spend(MAX_TAP_BURST, now);
+
+        assert_eq!(budget.spend(1, now - Duration::from_secs(1)), 0);
+        assert_eq!(budget.spend(1, now), 0);
+    }
+
+    #[test]
+    fn tap_budget_retry_after() {
+        let now = Instant::now();
+        let refill = Duration::from_millis(1_000 / MAX_TAPS_PER_SECOND as u64);
+        let mut budget = TapBudget::new(now);
+        assert_eq!(budget.retry_after(), Duration::from_millis(0));
+
+        budget.spend(MAX_TAP_BURST, now);
+        assert_eq!(budget.retry_after(), refill);
+
+        budget.spend(1, now + refill);
+        assert_eq!(budget.retry_after(), refill);
+    }
+
+    #[test]
+    fn plausible_taps_are_spaced_out() {
+        let interval = 1_000 / MAX_TAPS_PER_SECOND as u64;
+        assert_eq!(plausible_taps(&[]), 0);
+        assert_eq!(plausible_taps(&[1_000]), 1);
+        assert_eq!(plausible_taps(&[1_000, 1_000 + interval, 1_000 + interval * 2]), 3);
+    }
+
+    #[test]
+    fn taps_closer_together_than_anyone_can_tap_only_count_once() {
+        let interval = 1_000 / MAX_TAPS_PER_SECOND as u64;
+        assert_eq!(plausible_taps(&[1_000; MAX_FEED_BATCH]), 1);
+        assert_eq!(plausible_taps(&[1_000, 1_000 + interval - 1]), 1);
+
+        // Taps are measured from the last one that counted, not the last one sent.
+        let taps = [1_000, 1_000 + interval / 2, 1_000 + interval, 1_000 + interval * 3 / 2];
+        assert_eq!(plausible_taps(&taps), 2);
+    }
+
+    #[test]
+    fn first_player_gets_the_crown() {
+        let (mut state, _) = start_game(GameRules::default());
+        let ids = join(&mut state, 2);
+
+        assert!(state.player(ids[0]).unwrap().has_crown);
+        assert!(!state.player(ids[1]).unwrap().has_crown);
+        assert_eq!(state.stats().registrations, 2);
+    }
+
+    #[test]
+    fn register_rejects_duplicates_and_locked_cosmetics() {
+        let (mut state, _) = start_game(GameRules::default());
+        let id = join(&mut state, 1)[0];
+        assert_err!(state.register(id, "Again".into(), Cosmetics::default()), Error::Internal(_));
+
+        let gold = Cosmetics { color: "gold".into(), ..Cosmetics::default() };
+        assert_err!(
+            state.register(PlayerId::new(), "Flashy".into(), gold),
+            Error::CosmeticLocked { .. }
+        );
+        assert_eq!(state.players().len(), 1);
+    }
+
+    #[test]
+    fn paused_games_reject_input() {
+        let (mut state, start) = start_game(GameRules::default());
+        let id = join(&mut state, 1)[0];
+        state.shut_down();
+
+        assert_err!(
+            state.register(PlayerId::new(), "Late".into(), Cosmetics::default()),
+            Error::GamePaused
+        );
+        assert_err!(state.feed(id, start), Error::GamePaused);
+        assert_err!(state.feed_batch(id, &[1_000], start), Error::GamePaused);
+        assert_err!(state.tap(id, TapTiming::default(), start), Error::GamePaused);
+        assert_err!(state.respawn(id, start), Error::GamePaused);
+        assert_eq!(state.player(id).unwrap().score, 0);
+    }
+
+    #[test]
+    fn feeding_takes_the_crown() {
+        let (mut state, start) = start_game(GameRules::default());
+        let ids = join(&mut state, 2);
+        state.take_broadcasts();
+
+        assert_eq!(state.feed(ids[1], start).unwrap(), 1);
+        assert!(state.player(ids[1]).unwrap().has_crown);
+        assert!(!state.player(ids[0]).unwrap().has_crown);
+
+        // Hosts see the score that won the crown before the crown changes hands.
+        let (broadcasts, _) = state.take_broadcasts();
+        match broadcasts.get(0) {
+            Some(&HostBroadcast::HippoEat { id, score: 1 }) => assert_eq!(id, ids[1]),
+            broadcast => panic!("Expected HippoEat, got {:?}", broadcast),
+        }
+        match broadcasts.get(1) {
+            Some(&HostBroadcast::UpdateWinner { ref holders }) => {
+                assert_eq!(*holders, vec![ids[1]]);
+            }
+            broadcast => panic!("Expected UpdateWinner, got {:?}", broadcast),
+        }
+    }
+
+    #[test]
+    fn feeding_an_unknown_player_fails_without_scoring() {
+        let (mut state, start) = start_game(GameRules::default());
+        join(&mut state, 1);
+        let sequence = state.score_sequence;
+        let stranger = PlayerId::new();
+
+        assert_err!(state.feed(stranger, start), Error::InvalidPlayer(_));
+        assert_err!(state.feed_batch(stranger, &[1_000], start), Error::InvalidPlayer(_));
+        assert_eq!(state.score_sequence, sequence);
+        assert_eq!(state.stats().feeds, 0);
+    }
+
+    #[test]
+    fn feeds_and_batches_share_a_rate_limit() {
+        let (mut state, start) = start_game(GameRules::default());
+        let id = join(&mut state, 1)[0];
+        for _ in 0 .. MAX_TAP_BURST {
+            state.feed(id, start).unwrap();
+        }
+
+        assert_err!(state.feed(id, start), Error::RateLimited { .. });
+        assert_err!(state.feed_batch(id, &[1_000], start), Error::RateLimited { .. });
+        assert_eq!(state.player(id).unwrap().score, MAX_TAP_BURST);
+
+        let refill = millis(1_000 / MAX_TAPS_PER_SECOND as u64);
+        assert_eq!(state.feed(id, start + refill).unwrap(), MAX_TAP_BURST + 1);
+    }
+
+    #[test]
+    fn feed_batch_counts_spaced_out_taps_and_announces_them_on_the_next_tick() {
+        let (mut state, start) = start_game(GameRules::default());
+        let id = join(&mut state, 1)[0];
+        state.take_broadcasts();
+
+        let interval = 1_000 / MAX_TAPS_PER_SECOND as u64;
+        let taps = (0 .. 10).map(|index| 1_000 + index * interval).collect::<Vec<_>>();
+        let response = state.feed_batch(id, &taps, start).unwrap();
+        assert_eq!((response.accepted, response.score), (10, 10));
+
+        // Taps that all claim to have happened at once only count once.
+        let response = state.feed_batch(id, &[5_000; 10], start).unwrap();
+        assert_eq!((response.accepted, response.score), (1, 11));
+
+        let eats = |broadcasts: Vec<HostBroadcast>| {
+            broadcasts.into_iter()
+                .filter_map(|broadcast| match broadcast {
+                    HostBroadcast::HippoEat { id, score } => Some((id, score)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(eats(state.take_broadcasts().0).is_empty());
+
+        state.tick(start + millis(1));
+        assert_eq!(eats(state.take_broadcasts().0), vec![(id, 11)]);
+    }
+
+    #[test]
+    fn feed_batch_rejects_bad_batches() {
+        let (mut state, start) = start_game(GameRules::default());
+        let id = join(&mut state, 1)[0];
+
+        assert_err!(state.feed_batch(id, &[2_000, 1_000], start), Error::InvalidRequest(_));
+        assert_err!(
+            state.feed_batch(id, &[1_000; MAX_FEED_BATCH + 1], start),
+            Error::InvalidRequest(_)
+        );
+        assert_eq!(state.player(id).unwrap().score, 0);
+    }
+
+    #[test]
+    fn catch_up_boost_stops_partway_through_a_batch() {
+        let rules = GameRules {
+            catch_up: CatchUp { crown_penalty: 0, trailing_boost: 100, trailing_below: 50 },
+            ..GameRules::default()
+        };
+        let (mut state, start) = start_game(rules);
+        let ids = join(&mut state, 2);
+        set_score(&mut state, ids[0], 10);
+
+        // Taps are worth double until the player has half of the leader's score.
+        let interval = 1_000 / MAX_TAPS_PER_SECOND as u64;
+        let taps = (0 .. 5).map(|index| 1_000 + index * interval).collect::<Vec<_>>();
+        let response = state.feed_batch(ids[1], &taps, start).unwrap();
+        assert_eq!(response.score, 2 + 2 + 2 + 1 + 1);
+    }
+
+    #[test]
+    fn taps_only_count_during_nose_goes() {
+        let (mut state, start) = start_game(GameRules::default());
+        let ids = join(&mut state, 2);
+        assert_err!(state.tap(ids[0], TapTiming::default(), start), Error::NoseGoesNotActive);
+
+        let begin = begin_nose_goes(&mut state, start);
+        let late = join(&mut state, 1)[0];
+        assert_err!(state.tap(late, TapTiming::default(), begin), Error::NotInRound(_));
+
+        // Tapping again doesn't change anything.
+        let pending = NoseGoesResponse::Pending;
+        assert_eq!(state.tap(ids[0], TapTiming::default(), begin).unwrap(), pending);
+        assert_eq!(state.tap(ids[0], TapTiming::default(), begin + millis(100)).unwrap(), pending);
+        let tap = state.nose_goes_status(ids[0]).unwrap().tap.unwrap();
+        assert_eq!(tap.received, state.clock.unix_millis(begin));
+    }
+
+    #[test]
+    fn nose_goes_knocks_out_players_that_miss_it_and_pays_the_bonus() {
+        let (mut state, start) = start_game(GameRules::default());
+        let ids = join(&mut state, 3);
+        set_score(&mut state, ids[0], 10);
+        set_score(&mut state, ids[1], 20);
+        set_score(&mut state, ids[2], 50);
+
+        run_nose_goes(&mut state, start, &ids[.. 2]);
+
+        // The crown holder missed the event, so their score goes to the first player to tap.
+        assert!(state.player(ids[2]).is_none());
+        assert_eq!(state.player(ids[0]).unwrap().score, 110);
+        assert_eq!(state.player(ids[1]).unwrap().score, 20);
+        assert!(state.player(ids[0]).unwrap().has_crown);
+
+        assert_eq!(outcome(&state, ids[0]), Some(NoseGoesResponse::Survived));
+        assert_eq!(outcome(&state, ids[1]), Some(NoseGoesResponse::Survived));
+        assert_eq!(outcome(&state, ids[2]), Some(NoseGoesResponse::Died));
+        assert_eq!(state.stats().eliminations, 1);
+        assert_eq!(state.stats().bonus_points_awarded, 100);
+    }
+
+    #[test]
+    fn nose_goes_taps_are_ordered_by_when_they_happened() {
+        let (mut state, start) = start_game(GameRules::default());
+        let ids = join(&mut state, 3);
+        let begin = begin_nose_goes(&mut state, start);
+
+        // The second player's tap arrives last, but the client says it happened 150 ms before it
+        // arrived, which is within the tolerance. So the third player tapped last.
+        let first = begin + millis(1_000);
+        state.tap(ids[0], TapTiming::default(), first).unwrap();
+        state.tap(ids[2], TapTiming::default(), first + millis(100)).unwrap();
+        let received = state.clock.unix_millis(first + millis(200));
+        let timing = TapTiming { tapped_at: Some(received - 150), rtt: None };
+        state.tap(ids[1], timing, first + millis(200)).unwrap();
+        finish_nose_goes(&mut state, start);
+
+        assert_eq!(outcome(&state, ids[0]), Some(NoseGoesResponse::Survived));
+        assert_eq!(outcome(&state, ids[1]), Some(NoseGoesResponse::Survived));
+        assert_eq!(outcome(&state, ids[2]), Some(NoseGoesResponse::Died));
+
+        // The first player has the crown, so the bonus goes to the next player to tap.
+        assert_eq!(state.player(ids[1]).unwrap().score, 100);
+    }
+
+    #[test]
+    fn nose_goes_ends_once_the_last_tap_has_settled() {
+        let (mut state, start) = start_game(GameRules::default());
+        let ids = join(&mut state, 2);
+        let begin = begin_nose_goes(&mut state, start);
+        state.tap(ids[0], TapTiming::default(), begin).unwrap();
+
+        state.tick(begin + nose_goes_settle_time() - millis(1));
+        assert!(in_progress(&state));
+        state.tick(begin + nose_goes_settle_time());
+        assert!(!in_progress(&state));
+        assert!(state.player(ids[1]).is_none());
+    }
+
+    #[test]
+    fn missed_deadline_events_run_until_the_deadline() {
+        let rules = GameRules {
+            elimination: vec![Elimination::MissedDeadline],
+            ..GameRules::default()
+        };
+        let (mut state, start) = start_game(rules);
+        let ids = join(&mut state, 2);
+        let begin = begin_nose_goes(&mut state, start);
+        state.tap(ids[0], TapTiming::default(), begin).unwrap();
+
+        state.tick(begin + nose_goes_settle_time());
+        assert!(in_progress(&state));
+
+        // The last player standing survives by tapping before the deadline.
+        state.tap(ids[1], TapTiming::default(), begin + millis(5_000)).unwrap();
+        finish_nose_goes(&mut state, start);
+        assert_eq!(state.players().len(), 2);
+    }
+
+    #[test]
+    fn losing_with_lives_left_only_costs_a_life() {
+        let rules = GameRules { lives: Some(2), ..GameRules::default() };
+        let (mut state, start) = start_game(rules);
+        let ids = join(&mut state, 2);
+        set_score(&mut state, ids[1], 30);
+        state.take_broadcasts();
+
+        run_nose_goes(&mut state, start, &ids[.. 1]);
+
+        let player = state.player(ids[1]).unwrap();
+        assert_eq!((player.lives, player.score), (1, 30));
+        assert_eq!(outcome(&state, ids[1]), Some(NoseGoesResponse::LostLife));
+        assert_eq!(state.stats().lives_lost, 1);
+
+        // Nobody was knocked out, so there's no bonus.
+        assert_eq!(state.player(ids[0]).unwrap().score, 0);
+
+        let (broadcasts, _) = state.take_broadcasts();
+        assert!(broadcasts.iter().any(|broadcast| match *broadcast {
+            HostBroadcast::LifeLost { id, lives: 1 } => id == ids[1],
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn respawning_waits_for_the_cooldown_and_costs_points() {
+        let rules = GameRules {
+            respawn: Some(RespawnRules { cooldown: 5_000, penalty_percent: 50 }),
+            ..GameRules::default()
+        };
+        let (mut state, start) = start_game(rules);
+        let ids = join(&mut state, 3);
+        set_score(&mut state, ids[2], 200);
+
+        assert_err!(state.respawn(ids[2], start), Error::NotEliminated(_));
+        assert_err!(state.respawn(PlayerId::new(), start), Error::InvalidPlayer(_));
+
+        let end = run_nose_goes(&mut state, start, &ids[.. 2]);
+        assert!(state.player(ids[2]).is_none());
+
+        let cooldown = millis(5_000);
+        assert_err!(
+            state.respawn(ids[2], end + cooldown - millis(1)),
+            Error::RespawnCooldown { .. }
+        );
+
+        let player = state.respawn(ids[2], end + cooldown).unwrap();
+        assert_eq!((player.score, player.lives), (100, 1));
+        assert_eq!(state.players[&ids[2]].best_score(), 200);
+        assert_err!(state.respawn(ids[2], end + cooldown), Error::NotEliminated(_));
+    }
+
+    #[test]
+    fn cosmetics_stay_unlocked_after_respawning() {
+        let rules = GameRules {
+            respawn: Some(RespawnRules { cooldown: 0, penalty_percent: 50 }),
+            ..GameRules::default()
+        };
+        let (mut state, start) = start_game(rules);
+        let ids = join(&mut state, 3);
+        set_score(&mut state, ids[2], 200);
+
+        let end = run_nose_goes(&mut state, start, &ids[.. 2]);
+        assert_eq!(state.respawn(ids[2], end).unwrap().score, 100);
+
+        // The spotted skin unlocks at 200 points, which the player had before respawning.
+        let spotted = Cosmetics { skin: "spotted".into(), ..Cosmetics::default() };
+        assert_eq!(state.set_cosmetics(ids[2], spotted.clone()).unwrap().cosmetics, spotted);
+
+        let striped = Cosmetics { skin: "striped".into(), ..Cosmetics::default() };
+        assert_err!(state.set_cosmetics(ids[2], striped), Error::CosmeticLocked { .. });
+    }
+
+    #[test]
+    fn only_the_first_player_to_a_hundred_unlocks_it() {
+        let (mut state, _) = start_game(GameRules::default());
+        let ids = join(&mut state, 2);
+        state.take_broadcasts();
+
+        for &id in &ids {
+            set_score(&mut state, id, 100);
+            state.record_achievement_event(id, AchievementEvent::Scored);
+        }
+
+        assert_eq!(state.players[&ids[0]].achievements, vec![Achievement::FirstToHundred]);
+        assert!(state.players[&ids[1]].achievements.is_empty());
+
+        let (broadcasts, _) = state.take_broadcasts();
+        let unlocked = broadcasts.iter()
+            .filter_map(|broadcast| match *broadcast {
+                HostBroadcast::AchievementUnlocked { id, achievement, .. } => {
+                    Some((id, achievement))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(unlocked, vec![(ids[0], Achievement::FirstToHundred)]);
+    }
+
+    /// Knocks out the leader of a game where the other two players are tied, returning the
+    /// players and who has the crown afterwards.
+    fn crown_after_the_leader_is_knocked_out(
+        tie_break: TieBreak,
+    ) -> (Vec<PlayerId>, Vec<PlayerId>) {
+        let rules = GameRules {
+            payout: Payout::FixedPot { amount: 0 },
+            tie_break,
+            ..GameRules::default()
+        };
+        let (mut state, start) = start_game(rules);
+        let ids = join(&mut state, 3);
+        set_score(&mut state, ids[0], 50);
+        set_score(&mut state, ids[1], 50);
+        set_score(&mut state, ids[2], 100);
+
+        // The tied players tap in the opposite order to the one they scored in, so that the tap
+        // order can't be mistaken for the score order.
+        run_nose_goes(&mut state, start, &[ids[1], ids[0]]);
+        assert!(state.player(ids[2]).is_none());
+
+        let crown = state.crown.clone();
+        (ids, crown)
+    }
+
+    #[test]
+    fn crown_ties_after_eliminations_follow_the_tie_break() {
+        let (ids, crown) = crown_after_the_leader_is_knocked_out(TieBreak::EarliestToScore);
+        assert_eq!(crown, vec![ids[0]]);
+
+        let (ids, crown) = crown_after_the_leader_is_knocked_out(TieBreak::MostRecent);
+        assert_eq!(crown, vec![ids[1]]);
+
+        let (ids, crown) = crown_after_the_leader_is_knocked_out(TieBreak::Shared);
+        assert_eq!(crown, vec![ids[0], ids[1]]);
+    }
+
+    #[test]
+    fn restore_round_trips_a_snapshot() {
+        let rules = GameRules {
+            tie_break: TieBreak::Shared,
+            podium_size: 2,
+            ..GameRules::default()
+        };
+        let (mut state, start) = start_game(rules.clone());
+        let ids = join(&mut state, 3);
+        set_score(&mut state, ids[0], 40);
+        set_score(&mut state, ids[1], 40);
+        set_score(&mut state, ids[2], 10);
+        state.set_next_elimination(Elimination::MissedDeadline);
+
+        let now = start + millis(1_000);
+        let restored = GameState::restore(state.snapshot(now), rules, now, state.clock);
+
+        assert_eq!(restored.crown, vec![ids[0], ids[1]]);
+        assert_eq!(restored.rankings(None), state.rankings(None));
+        assert_eq!(restored.podium, state.podium);
+        assert_eq!(restored.podium.len(), 2);
+        assert_eq!(restored.score_sequence, state.score_sequence);
+        assert_eq!(restored.next_elimination, Some(Elimination::MissedDeadline));
+        for &id in &ids {
+            let (original, restored) = (state.player(id).unwrap(), restored.player(id).unwrap());
+            assert_eq!((original.score, original.has_crown), (restored.score, restored.has_crown));
+        }
+    }
+
+    #[test]
+    fn restore_gives_players_time_to_reconnect_to_an_event_in_progress() {
+        let (mut state, start) = start_game(GameRules::default());
+        let ids = join(&mut state, 3);
+        let begin = begin_nose_goes(&mut state, start);
+        state.tap(ids[0], TapTiming::default(), begin).unwrap();
+
+        // Save the game with 8 seconds of the event left.
+        let saved_at = start + first_nose_goes_delay() + nose_goes_duration() - millis(8_000);
+        let snapshot = state.snapshot(saved_at);
+        let later = begin + millis(60_000);
+        let mut restored = GameState::restore(snapshot, GameRules::default(), later, state.clock);
+        assert!(in_progress(&restored));
+        assert!(restored.nose_goes_status(ids[0]).unwrap().tap.is_some());
+
+        // The event is announced again once players have had time to reconnect, and runs for as
+        // long as it had left.
+        let resume = later + first_nose_goes_delay();
+        restored.tick(resume);
+        let (broadcasts, _) = restored.take_broadcasts();
+        assert!(broadcasts.iter().any(|broadcast| match *broadcast {
+            HostBroadcast::BeginNoseGoes { duration, .. } => duration == 8_000,
+            _ => false,
+        }));
+
+        restored.tick(resume + millis(8_000) - millis(1));
+        assert!(in_progress(&restored));
+        restored.tick(resume + millis(8_000) + millis(1));
+        assert!(!in_progress(&restored));
+        assert_eq!(restored.players().len(), 1);
+        assert!(restored.player(ids[0]).is_some());
+    }
+}
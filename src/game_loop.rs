@@ -0,0 +1,579 @@
+//! The game loop, which owns the game state and applies commands to it one at a time.
+//!
+//! API endpoints never touch the game state directly. Instead they send a [`Command`] to the game
+//! loop thread through a [`Game`] handle and wait for the reply. Since the game loop is the only
+//! thread that can see the [`GameState`], every command sees a consistent snapshot of the game and
+//! there are no locks to acquire in the wrong order.
+//!
+//! [`Command`]: ./enum.Command.html
+//! [`Game`]: ./struct.Game.html
+//! [`GameState`]: ../game/struct.GameState.html
+
+use api::{FeedBatchResponse, NoseGoesResponse, NoseGoesStatus, PlayerData, RankedPlayer};
+use broadcast::*;
+use clock::Clock;
+use cosmetics::Cosmetics;
+use elimination::Elimination;
+use error::*;
+use event_log::{EventLog, Input};
+use game::{self, CrownTenure, GameRules, GameState, GameStats, PlayerId, Snapshot, TapTiming};
+use std::fs::{self, File};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::*;
+
+pub type GameHandle = Arc<Game>;
+
+/// A command to be applied to the game state by the game loop.
+///
+/// Commands that produce a result carry a `reply` channel that the game loop sends the result
+/// back on.
+#[derive(Debug)]
+pub enum Command {
+    /// Adds a new player to the game.
+    Register {
+        id: PlayerId,
+        name: String,
+        cosmetics: Cosmetics,
+        reply: mpsc::Sender<Result<PlayerData>>,
+    },
+
+    /// Changes how a player's hippo looks.
+    SetCosmetics {
+        id: PlayerId,
+        cosmetics: Cosmetics,
+        reply: mpsc::Sender<Result<PlayerData>>,
+    },
+
+    /// Brings a knocked-out player back into the game.
+    Respawn {
+        id: PlayerId,
+        reply: mpsc::Sender<Result<PlayerData>>,
+    },
+
+    /// Feeds a player's hippo.
+    Feed {
+        id: PlayerId,
+        reply: mpsc::Sender<Result<usize>>,
+    },
+
+    /// Feeds a player's hippo once for each tap in a batch.
+    FeedBatch {
+        id: PlayerId,
+        taps: Vec<u64>,
+        reply: mpsc::Sender<Result<FeedBatchResponse>>,
+    },
+
+    /// A player has tapped their poison marble during a nose-goes event.
+    Tap {
+        id: PlayerId,
+        timing: TapTiming,
+        reply: mpsc::Sender<Result<NoseGoesResponse>>,
+    },
+
+    /// Advances the game, starting and ending nose-goes events as necessary.
+    Tick,
+
+    /// Gets a player's result for the current or most recent nose-goes event.
+    GetNoseGoesStatus {
+        id: PlayerId,
+        reply: mpsc::Sender<Result<NoseGoesStatus>>,
+    },
+
+    /// Gets the current state of a single player.
+    GetPlayer {
+        id: PlayerId,
+        reply: mpsc::Sender<Result<Option<PlayerData>>>,
+    },
+
+    /// Gets the current state of all players.
+    GetPlayers {
+        reply: mpsc::Sender<Result<Vec<PlayerData>>>,
+    },
+
+    /// Gets the top `limit` players by score, or every player if `limit` is `None`.
+    GetRankings {
+        limit: Option<usize>,
+        reply: mpsc::Sender<Result<Vec<RankedPlayer>>>,
+    },
+
+    /// Gets the history of who has worn the crown.
+    GetCrownHistory {
+        reply: mpsc::Sender<Result<Vec<CrownTenure>>>,
+    },
+
+    /// Gets statistics about the game.
+    GetStats {
+        reply: mpsc::Sender<Result<GameStats>>,
+    },
+
+    /// Picks the elimination rule for the next nose-goes event.
+    SetNextElimination {
+        elimination: Elimination,
+        reply: mpsc::Sender<Result<()>>,
+    },
+
+    /// Pauses the game because the server is shutting down, saving the game state if the game
+    /// loop was started with a state file.
+    Shutdown {
+        reply: mpsc::Sender<Result<()>>,
+    },
+}
+
+impl Command {
+    /// Returns the input to record in the event log for this command.
+    ///
+    /// Returns `None` for commands that only read the game state, since they don't need to be
+    /// replayed.
+    fn input(&self) -> Option<Input> {
+        match *self {
+            Command::Register { id, ref name, ref cosmetics, .. } => {
+                Some(Input::Register { id, name: name.clone(), cosmetics: cosmetics.clone() })
+            }
+            Command::SetCosmetics { id, ref cosmetics, .. } => {
+                Some(Input::SetCosmetics { id, cosmetics: cosmetics.clone() })
+            }
+            Command::Respawn { id, .. } => Some(Input::Respawn { id }),
+            Command::Feed { id, .. } => Some(Input::Feed { id }),
+            Command::FeedBatch { id, ref taps, .. } => Some(Input::FeedBatch { id, taps: taps.clone() }),
+            Command::Tap { id, timing, .. } => Some(Input::Tap { id, timing }),
+            Command::Tick => Some(Input::Tick),
+            Command::SetNextElimination { elimination, .. } => {
+                Some(Input::SetNextElimination(elimination))
+            }
+            Command::Shutdown { .. } => Some(Input::Shutdown),
+            Command::GetNoseGoesStatus { .. }
+            | Command::GetPlayer { .. }
+            | Command::GetPlayers { .. }
+            | Command::GetRankings { .. }
+            | Command::GetCrownHistory { .. }
+            | Command::GetStats { .. } => None,
+        }
+    }
+}
+
+/// A handle for sending commands to the game loop.
+#[derive(Debug)]
+pub struct Game {
+    inner: Mutex<mpsc::Sender<Command>>,
+
+    /// The time at which the game loop was started.
+    started: Instant,
+
+    /// The time of the most recently applied `Tick`, in milliseconds since `started`.
+    last_tick: Arc<AtomicUsize>,
+}
+
+impl Game {
+    /// Creates a handle for a new game, along with the receiver that the game loop reads commands
+    /// from.
+    ///
+    /// Commands sent through the handle are queued up until the receiver is passed to
+    /// [`start_game_loop`]. Creating the handle first lets things that are started before the game
+    /// loop (like the player websocket server) send commands to it.
+    ///
+    /// [`start_game_loop`]: ./fn.start_game_loop.html
+    pub fn new() -> (GameHandle, mpsc::Receiver<Command>) {
+        let (command_sender, command_receiver) = mpsc::channel();
+        let game = Arc::new(Game {
+            inner: Mutex::new(command_sender),
+            started: Instant::now(),
+            last_tick: Arc::new(AtomicUsize::new(0)),
+        });
+
+        (game, command_receiver)
+    }
+
+    /// Registers a new player with a randomly generated ID, username and color.
+    pub fn register(&self, cosmetics: Option<Cosmetics>) -> Result<PlayerData> {
+        let id = PlayerId::new();
+        let name = game::generate_username();
+        let cosmetics = cosmetics.unwrap_or_else(Cosmetics::random);
+        self.request(|reply| Command::Register { id, name, cosmetics, reply })
+    }
+
+    /// Changes how a player's hippo looks.
+    pub fn set_cosmetics(&self, id: PlayerId, cosmetics: Cosmetics) -> Result<PlayerData> {
+        self.request(|reply| Command::SetCosmetics { id, cosmetics, reply })
+    }
+
+    /// Brings a knocked-out player back into the game with the same ID and name.
+    pub fn respawn(&self, id: PlayerId) -> Result<PlayerData> {
+        self.request(|reply| Command::Respawn { id, reply })
+    }
+
+    /// Feeds a player's hippo, returning the player's new score, up to the rate limit.
+    pub fn feed(&self, id: PlayerId) -> Result<usize> {
+        self.request(|reply| Command::Feed { id, reply })
+    }
+
+    /// Feeds a player's hippo once for each tap in `taps`, up to the rate limit.
+    pub fn feed_batch(&self, id: PlayerId, taps: Vec<u64>) -> Result<FeedBatchResponse> {
+        self.request(|reply| Command::FeedBatch { id, taps, reply })
+    }
+
+    /// Taps the player's poison marble during a nose-goes event.
+    pub fn tap(&self, id: PlayerId, timing: TapTiming) -> Result<NoseGoesResponse> {
+        self.request(|reply| Command::Tap { id, timing, reply })
+    }
+
+    /// Picks the elimination rule for the next nose-goes event.
+    pub fn set_next_elimination(&self, elimination: Elimination) -> Result<()> {
+        self.request(|reply| Command::SetNextElimination { elimination, reply })
+    }
+
+    /// Gets the player's result for the current or most recent nose-goes event.
+    pub fn nose_goes_status(&self, id: PlayerId) -> Result<NoseGoesStatus> {
+        self.request(|reply| Command::GetNoseGoesStatus { id, reply })
+    }
+
+    /// Gets the current state of the player, if they're still in the game.
+    pub fn player(&self, id: PlayerId) -> Result<Option<PlayerData>> {
+        self.request(|reply| Command::GetPlayer { id, reply })
+    }
+
+    /// Gets the current state of all players in the game.
+    pub fn players(&self) -> Result<Vec<PlayerData>> {
+        self.request(|reply| Command::GetPlayers { reply })
+    }
+
+    /// Gets the top `limit` players by score, or every player if `limit` is `None`.
+    pub fn rankings(&self, limit: Option<usize>) -> Result<Vec<RankedPlayer>> {
+        self.request(|reply| Command::GetRankings { limit, reply })
+    }
+
+    /// Gets the history of who has worn the crown, oldest first.
+    pub fn crown_history(&self) -> Result<Vec<CrownTenure>> {
+        self.request(|reply| Command::GetCrownHistory { reply })
+    }
+
+    /// Gets statistics about the game.
+    pub fn stats(&self) -> Result<GameStats> {
+        self.request(|reply| Command::GetStats { reply })
+    }
+
+    /// Pauses the game and saves the game state, in preparation for the server shutting down.
+    pub fn shut_down(&self) -> Result<()> {
+        self.request(|reply| Command::Shutdown { reply })
+    }
+
+    /// Returns how long it has been since the game loop last applied a `Tick`.
+    pub fn time_since_last_tick(&self) -> Duration {
+        let last_tick = Duration::from_millis(self.last_tick.load(Ordering::SeqCst) as u64);
+        let elapsed = self.started.elapsed();
+        if elapsed > last_tick { elapsed - last_tick } else { Duration::from_millis(0) }
+    }
+
+    /// Checks that the game loop is responsive by making a request and waiting up to `timeout`
+    /// for the reply.
+    pub fn check(&self, timeout: Duration) -> Result<()> {
+        let (reply, response) = mpsc::channel();
+        self.send(Command::GetStats { reply })?;
+        match response.recv_timeout(timeout) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::Internal("The game loop didn't respond in time".into())),
+        }
+    }
+
+    /// Sends `command` to the game loop without waiting for it to be applied.
+    pub fn send(&self, command: Command) -> Result<()> {
+        self.inner
+            .lock().recover("Game command sender")
+            .send(command)
+            .map_err(|_| Error::Internal("The game loop has stopped".into()))
+    }
+
+    /// Sends the command built by `make_command` and waits for the game loop's reply.
+    fn request<T, F>(&self, make_command: F) -> Result<T>
+    where
+        F: FnOnce(mpsc::Sender<Result<T>>) -> Command,
+    {
+        let (reply, response) = mpsc::channel();
+        self.send(make_command(reply))?;
+
+        // The reply sender is only dropped without sending if applying the command panicked.
+        response
+            .recv()
+            .map_err(|_| Error::Internal("The game loop failed to apply a command".into()))
+            .and_then(|result| result)
+    }
+}
+
+/// Runs the main logic of the game on a separate thread.
+///
+/// Spawns a thread that owns the game state and applies commands sent through `game`, broadcasting
+/// updates to the players and hosts after each one. `command_receiver` must be the receiver that
+/// was created along with `game` by [`Game::new`]. A second thread sends a `Tick` command every
+/// 100 ms to drive nose-goes events.
+///
+/// If `event_log` is given, every command that changes the game state is recorded to it before
+/// being applied.
+///
+/// If `state_file` is given, the game state is saved to it when the server shuts down, and a game
+/// saved there previously is resumed when the game loop starts. The file is removed once the game
+/// has been resumed so that a stale game isn't resumed again later.
+///
+/// `clock` is used to convert game times to wall-clock times for broadcasts. It should be the
+/// same clock that the `/api/v1/time` endpoint uses, so that clients see consistent times.
+///
+/// If `score_batch_window` is given, `HippoEat` host broadcasts are coalesced into `ScoreBatch`
+/// broadcasts sent at most once per window. See [`ScoreBatcher`] for details.
+///
+/// Nose-goes events are run according to `rules`.
+///
+/// [`Game::new`]: ./struct.Game.html#method.new
+/// [`ScoreBatcher`]: ../broadcast/struct.ScoreBatcher.html
+pub fn start_game_loop(
+    game: GameHandle,
+    command_receiver: mpsc::Receiver<Command>,
+    host_broadcaster: HostBroadcaster,
+    player_broadcaster: PlayerBroadcaster,
+    mut event_log: Option<EventLog>,
+    state_file: Option<PathBuf>,
+    score_batch_window: Option<Duration>,
+    clock: Clock,
+    rules: GameRules,
+) {
+    let started = game.started;
+    let loop_last_tick = game.last_tick.clone();
+    thread::spawn(move || {
+        let now = Instant::now();
+        record(&mut event_log, Input::Start, clock.unix_millis(now));
+        record(&mut event_log, Input::SetRules(rules.clone()), clock.unix_millis(now));
+        let snapshot = state_file.as_ref().and_then(|path| load_snapshot(path));
+        let mut state = match snapshot {
+            Some(snapshot) => {
+                info!("Resuming game with {} players from saved state", snapshot.players.len());
+                record(&mut event_log, Input::Restore(snapshot.clone()), clock.unix_millis(now));
+                GameState::restore(snapshot, rules, now, clock)
+            }
+
+            None => {
+                let mut state = GameState::new(now, clock);
+                state.set_rules(rules);
+                state
+            }
+        };
+
+        let mut score_batcher = ScoreBatcher::new(score_batch_window);
+        loop {
+            // Wait for the next command, but only until the pending score batch is due so that
+            // it's sent on time even if no commands come in.
+            let command = match score_batcher.deadline() {
+                Some(deadline) => {
+                    let timeout = game::duration_until(Instant::now(), deadline);
+                    match command_receiver.recv_timeout(timeout) {
+                        Ok(command) => Some(command),
+                        Err(mpsc::RecvTimeoutError::Timeout) => None,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+
+                None => {
+                    match command_receiver.recv() {
+                        Ok(command) => Some(command),
+                        Err(_) => break,
+                    }
+                }
+            };
+
+            let command = match command {
+                Some(command) => command,
+                None => {
+                    for broadcast in score_batcher.flush_due(Instant::now()) {
+                        host_broadcaster.send(broadcast);
+                    }
+                    continue;
+                }
+            };
+
+            // The same instant is recorded and applied so that replaying the log applies the
+            // command at exactly the same game time.
+            let now = Instant::now();
+            if let Some(input) = command.input() {
+                record(&mut event_log, input, clock.unix_millis(now));
+            }
+
+            // Keep track of when the last tick happened so that readiness checks can tell whether
+            // the game is still progressing. The connection counts are refreshed at the same time
+            // so that the game always has an up-to-date view of who is connected.
+            if let Command::Tick = command {
+                let elapsed = started.elapsed();
+                let millis = elapsed.as_secs() * 1_000 + elapsed.subsec_nanos() as u64 / 1_000_000;
+                loop_last_tick.store(millis as usize, Ordering::SeqCst);
+
+                state.set_connections(ConnectionCounts {
+                    host: host_broadcaster.connections(),
+                    player: player_broadcaster.connections(),
+                });
+            }
+
+            // A bug in the game rules shouldn't take down the whole game, so if applying a
+            // command panics we log it and move on to the next one. The client that sent the
+            // command will get an internal error since its reply sender was dropped.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                apply(&mut state, command, now, state_file.as_ref().map(|path| &**path));
+            }));
+            if result.is_err() {
+                error!("The game loop panicked while applying a command");
+            }
+
+            let now = Instant::now();
+            let (host_broadcasts, player_broadcasts) = state.take_broadcasts();
+            for broadcast in host_broadcasts {
+                for broadcast in score_batcher.push(broadcast, now) {
+                    host_broadcaster.send(broadcast);
+                }
+            }
+            for broadcast in score_batcher.flush_due(now) {
+                host_broadcaster.send(broadcast);
+            }
+            for broadcast in player_broadcasts {
+                player_broadcaster.send(broadcast);
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            if let Err(error) = game.send(Command::Tick) {
+                error!("Failed to tick the game loop: {:?}", error);
+                return;
+            }
+        }
+    });
+}
+
+/// Appends `input` to the event log, if there is one.
+///
+/// `timestamp` is the game clock's reading of the instant that `input` is applied at.
+///
+/// Failing to record an input shouldn't stop the game, so errors are logged and otherwise ignored.
+fn record(event_log: &mut Option<EventLog>, input: Input, timestamp: u64) {
+    if let Some(ref mut event_log) = *event_log {
+        if let Err(error) = event_log.append(&input, timestamp) {
+            warn!("Failed to record {:?} to the event log: {}", input, error);
+        }
+    }
+}
+
+/// Applies a single command to the game state, replying with the result if necessary.
+///
+/// NOTE: Sending a reply can only fail if the requesting thread has given up on the request, in
+/// which case there's nobody to tell about it, so failed replies are ignored.
+fn apply(state: &mut GameState, command: Command, now: Instant, state_file: Option<&Path>) {
+    match command {
+        Command::Register { id, name, cosmetics, reply } => {
+            let _ = reply.send(state.register(id, name, cosmetics));
+        }
+
+        Command::SetCosmetics { id, cosmetics, reply } => {
+            let _ = reply.send(state.set_cosmetics(id, cosmetics));
+        }
+
+        Command::Respawn { id, reply } => {
+            let _ = reply.send(state.respawn(id, now));
+        }
+
+        Command::Feed { id, reply } => {
+            let _ = reply.send(state.feed(id, now));
+        }
+
+        Command::FeedBatch { id, taps, reply } => {
+            let _ = reply.send(state.feed_batch(id, &taps, now));
+        }
+
+        Command::Tap { id, timing, reply } => {
+            let _ = reply.send(state.tap(id, timing, now));
+        }
+
+        Command::Tick => {
+            state.tick(now);
+        }
+
+        Command::SetNextElimination { elimination, reply } => {
+            state.set_next_elimination(elimination);
+            let _ = reply.send(Ok(()));
+        }
+
+        Command::GetNoseGoesStatus { id, reply } => {
+            let _ = reply.send(state.nose_goes_status(id));
+        }
+
+        Command::GetPlayer { id, reply } => {
+            let _ = reply.send(Ok(state.player(id)));
+        }
+
+        Command::GetPlayers { reply } => {
+            let _ = reply.send(Ok(state.players()));
+        }
+
+        Command::GetRankings { limit, reply } => {
+            let _ = reply.send(Ok(state.rankings(limit)));
+        }
+
+        Command::GetCrownHistory { reply } => {
+            let _ = reply.send(Ok(state.crown_history(now)));
+        }
+
+        Command::GetStats { reply } => {
+            let _ = reply.send(Ok(state.stats()));
+        }
+
+        Command::Shutdown { reply } => {
+            state.shut_down();
+
+            let result = match state_file {
+                Some(path) => {
+                    save_snapshot(path, &state.snapshot(now)).map_err(|error| {
+                        Error::Internal(format!("Failed to save game state to {}: {}", path.display(), error))
+                    })
+                }
+
+                None => Ok(()),
+            };
+            let _ = reply.send(result);
+        }
+    }
+}
+
+/// Loads the game saved in `path`, removing the file afterwards.
+///
+/// Returns `None` if there is no saved game. A saved game that can't be loaded is logged and
+/// ignored, since it's better to start a new game than to not start at all.
+fn load_snapshot(path: &Path) -> Option<Snapshot> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => return None,
+        Err(error) => {
+            error!("Failed to open saved game {}: {}", path.display(), error);
+            return None;
+        }
+    };
+
+    let snapshot = match ::serde_json::from_reader(file) {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            error!("Failed to load saved game {}: {}", path.display(), error);
+            return None;
+        }
+    };
+
+    if let Err(error) = fs::remove_file(path) {
+        warn!("Failed to remove saved game {}: {}", path.display(), error);
+    }
+
+    Some(snapshot)
+}
+
+/// Saves `snapshot` to `path`, replacing any existing file.
+fn save_snapshot(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    let file = File::create(path)?;
+    ::serde_json::to_writer(file, snapshot)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+}
@@ -0,0 +1,70 @@
+//! Health and readiness checks, used by deployment to verify that the server is actually serving.
+//!
+//! - `/healthz` succeeds as long as Rocket is able to handle requests.
+//! - `/readyz` additionally checks that both websocket servers are listening, that the game loop
+//!   is ticking, and that the game state is responsive. It returns a 503 if any check fails.
+
+use broadcast::*;
+use game_loop::GameHandle;
+use rocket::State;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket_contrib::Json;
+use std::time::*;
+
+/// The response for the `/healthz` and `/readyz` endpoints.
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    /// Whether all checks passed.
+    pub ok: bool,
+
+    /// The name of each check that was run and whether it passed.
+    pub checks: Vec<(&'static str, bool)>,
+}
+
+impl HealthReport {
+    fn new(checks: Vec<(&'static str, bool)>) -> HealthReport {
+        let ok = checks.iter().all(|&(_, passed)| passed);
+        HealthReport { ok, checks }
+    }
+
+    fn into_response(self) -> Custom<Json<HealthReport>> {
+        let status = if self.ok { Status::Ok } else { Status::ServiceUnavailable };
+        Custom(status, Json(self))
+    }
+}
+
+/// Reports whether the server is alive.
+///
+/// If Rocket can route the request then the server is alive, so this always succeeds.
+#[get("/healthz")]
+pub fn healthz() -> Custom<Json<HealthReport>> {
+    HealthReport::new(vec![("http", true)]).into_response()
+}
+
+/// Reports whether the server is ready to host a game.
+#[get("/readyz")]
+pub fn readyz(
+    game: State<GameHandle>,
+    host_broadcaster: State<HostBroadcaster>,
+    player_broadcaster: State<PlayerBroadcaster>,
+) -> Custom<Json<HealthReport>> {
+    // The game loop ticks every 100 ms, so if it hasn't ticked in a whole second something is
+    // holding it up.
+    let ticking = game.time_since_last_tick() < Duration::from_millis(1_000);
+
+    let responsive = match game.check(Duration::from_millis(500)) {
+        Ok(()) => true,
+        Err(error) => {
+            warn!("Readiness check failed: {:?}", error);
+            false
+        }
+    };
+
+    HealthReport::new(vec![
+        ("host_websocket", host_broadcaster.is_listening()),
+        ("player_websocket", player_broadcaster.is_listening()),
+        ("game_loop_ticking", ticking),
+        ("game_state", responsive),
+    ]).into_response()
+}
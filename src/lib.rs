@@ -0,0 +1,329 @@
+//! The server for Hangry River Horse.
+//!
+//! The server binary just calls [`run`], everything else lives in this library so that other
+//! tools (like the event log replay tool) can reuse the game rules.
+//!
+//! [`run`]: ./fn.run.html
+
+#![feature(plugin)]
+#![plugin(rocket_codegen)]
+
+extern crate chan;
+extern crate chan_signal;
+#[macro_use] extern crate courier;
+#[macro_use] extern crate log;
+extern crate rand;
+extern crate rmp_serde;
+extern crate rocket;
+extern crate rocket_contrib;
+extern crate serde;
+extern crate serde_cbor;
+#[macro_use] extern crate serde_derive;
+#[macro_use] extern crate serde_json;
+extern crate ws;
+
+use admin::AdminConfig;
+use broadcast::*;
+use clock::Clock;
+use elimination::Elimination;
+use event_log::EventLog;
+use game::{CatchUp, GameRules, RespawnRules};
+use game_loop::Game;
+use metrics::{Metrics, RequestTimer};
+use payout::Payout;
+use rocket::Route;
+use rocket::response::*;
+use schema::VersionHeader;
+use socket_api::PlayerCommandHandler;
+use std::cmp;
+use std::io;
+use std::path::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tie_break::TieBreak;
+
+pub mod achievements;
+pub mod admin;
+pub mod api;
+pub mod broadcast;
+pub mod clock;
+pub mod cosmetics;
+pub mod elimination;
+pub mod encoding;
+pub mod error;
+pub mod event_log;
+pub mod game;
+pub mod game_loop;
+pub mod health;
+pub mod metrics;
+pub mod payout;
+pub mod ranking;
+pub mod schema;
+pub mod shutdown;
+pub mod socket_api;
+pub mod tie_break;
+
+/// Routes `/` to the player landing page.
+///
+/// Serves `www/client.html` when the player navigates directly to `localhost:6767`, that way people
+/// don't have to manually go to `localhost:6767/client.html`.
+#[get("/")]
+fn static_serve_player() -> io::Result<NamedFile> {
+    NamedFile::open(Path::new("www/client.html"))
+}
+
+/// Routes `/host` to the host landing page.
+///
+/// Server `www/host.html` when navigating to to `localhost:6767/host`, that way people don't have
+/// to manually add the `.html` to the end.
+#[get("/host")]
+fn static_serve_display() -> io::Result<NamedFile> {
+    NamedFile::open(Path::new("www/host.html"))
+}
+
+/// Fallback static file server route.
+///
+/// Any requests that aren't matched against an API route and aren't the special case `/` and `/host`
+/// routes will be served as static files, returning a 404 error if the file doesn't exist.
+#[get("/<file..>", rank = 1)]
+fn static_serve(file: PathBuf) -> io::Result<NamedFile> {
+    NamedFile::open(Path::new("www/").join(file))
+}
+
+/// Returns the routes for the game API.
+///
+/// The routes are mounted under both `/api/v1` and the legacy `/api` prefix, see the [`schema`]
+/// module for details.
+///
+/// [`schema`]: ./schema/index.html
+fn api_routes() -> Vec<Route> {
+    routes![
+        api::register_player,
+        api::register_player_with_cosmetics,
+        api::respawn,
+        api::cosmetics,
+        api::set_cosmetics,
+        api::feed_player,
+        api::feed_batch,
+        api::get_player,
+        api::get_players,
+        api::nose_goes,
+        api::nose_goes_status,
+        api::crown_history,
+        api::rankings,
+        api::all_rankings,
+        api::time,
+    ]
+}
+
+/// Starts the game server, blocking until Rocket shuts down.
+pub fn run() {
+    // NOTE: We need to start listening for shutdown signals before any other threads are spawned.
+    let signals = shutdown::listen();
+
+    let rocket = rocket::ignite();
+
+    // Create the handle for the game up front so that the player websocket can send commands to
+    // it. Commands are queued up until the game loop is started below.
+    let (game, command_receiver) = Game::new();
+
+    // The clock used to send server times to clients. The game loop uses it for broadcasts and
+    // the `/time` endpoint uses it so that clients can work out their offset from the server.
+    let clock = Clock::new();
+
+    // Start websocket servers for broadcasting messages to host clients and player clients. The
+    // resulting `Broadcaster<T>` objects are given to the game loop so that it can broadcast
+    // state changes as necessary. They're also given to Rocket as managed state so that the
+    // metrics and readiness endpoints can report on the websocket servers. Players can also send
+    // commands over their websocket, which are applied to the game the same way as the
+    // corresponding API endpoints.
+    let player_commands: Arc<MessageHandler> = Arc::new(PlayerCommandHandler::new(game.clone()));
+    let player_broadcaster = broadcast::start_server::<PlayerBroadcast>(
+        "0.0.0.0:6768",
+        Some(player_commands),
+    );
+    let host_broadcaster = broadcast::start_server::<HostBroadcast>("0.0.0.0:6769", None);
+
+    // Open the event log if one is configured. Every input to the game is recorded to the log so
+    // that the game can be replayed afterwards with the `replay` tool. Failing to open the log
+    // shouldn't stop the game from running, so we log the error and carry on without it.
+    let event_log = match rocket.config().get_str("event_log") {
+        Ok(path) => {
+            match EventLog::open(path) {
+                Ok(event_log) => Some(event_log),
+                Err(error) => {
+                    error!("Failed to open event log {}, events won't be recorded: {}", path, error);
+                    None
+                }
+            }
+        }
+
+        Err(_) => None,
+    };
+
+    // If a state file is configured the game state is saved there when the server shuts down,
+    // and resumed from there when the server starts back up.
+    let state_file = rocket.config().get_str("state_file").ok().map(PathBuf::from);
+
+    // If a score batch window is configured, score updates for the hosts are batched together
+    // rather than sent for every single point.
+    let score_batch_window = rocket.config()
+        .get_int("score_batch_window")
+        .ok()
+        .and_then(|millis| {
+            if millis > 0 { Some(Duration::from_millis(millis as u64)) } else { None }
+        });
+
+    // Nose-goes events cycle through the configured elimination rules. Rules that can't be parsed
+    // are skipped rather than stopping the server; If none are left the default rule is used.
+    let elimination = match rocket.config().get_str("elimination_rules") {
+        Ok(rules) => {
+            rules.split(',')
+                .filter_map(|rule| {
+                    match rule.parse::<Elimination>() {
+                        Ok(rule) => Some(rule),
+                        Err(error) => {
+                            error!("Ignoring elimination rule: {}", error);
+                            None
+                        }
+                    }
+                })
+                .collect()
+        }
+
+        Err(_) => Vec::new(),
+    };
+
+    // The nose-goes bonus is paid out with the configured strategy, falling back to the default
+    // if the strategy can't be parsed.
+    let payout = match rocket.config().get_str("bonus_payout") {
+        Ok(payout) => {
+            payout.parse().unwrap_or_else(|error| {
+                error!("Using the default bonus payout: {}", error);
+                Payout::default()
+            })
+        }
+
+        Err(_) => Payout::default(),
+    };
+
+    // If lives are configured, players are only knocked out once they've lost that many nose-goes
+    // events.
+    let lives = rocket.config()
+        .get_int("lives")
+        .ok()
+        .and_then(|lives| if lives > 0 { Some(lives as usize) } else { None });
+
+    // Knocked-out players can only respawn if a cooldown is configured.
+    let respawn = rocket.config()
+        .get_int("respawn_cooldown")
+        .ok()
+        .map(|cooldown| {
+            RespawnRules {
+                cooldown: cmp::max(cooldown, 0) as u64,
+                penalty_percent: rocket.config()
+                    .get_int("respawn_penalty")
+                    .map(|penalty| cmp::max(cmp::min(penalty, 100), 0) as u32)
+                    .unwrap_or(0),
+            }
+        });
+
+    // Catch-up is turned off unless a crown penalty or trailing boost is configured.
+    let percent = |name: &str, default: u32| {
+        rocket.config()
+            .get_int(name)
+            .map(|percent| cmp::max(percent, 0) as u32)
+            .unwrap_or(default)
+    };
+    let catch_up = CatchUp {
+        crown_penalty: cmp::min(percent("crown_penalty", 0), 100),
+        trailing_boost: percent("trailing_boost", 0),
+        trailing_below: percent("trailing_below", 50),
+    };
+
+    // Ties for the crown are broken with the configured policy, falling back to the default if the
+    // policy can't be parsed.
+    let tie_break = match rocket.config().get_str("crown_tie_break") {
+        Ok(tie_break) => {
+            tie_break.parse().unwrap_or_else(|error| {
+                error!("Using the default crown tie-break: {}", error);
+                TieBreak::default()
+            })
+        }
+
+        Err(_) => TieBreak::default(),
+    };
+
+    let rules = GameRules {
+        elimination,
+        payout,
+        crown_can_win_bonus: rocket.config().get_bool("crown_can_win_bonus").unwrap_or(false),
+        lives,
+        respawn,
+        catch_up,
+        tie_break,
+        podium_size: rocket.config()
+            .get_int("podium_size")
+            .map(|size| cmp::max(size, 0) as usize)
+            .unwrap_or(3),
+    };
+
+    // Start the game loop, which owns all of the game state. API endpoints send commands to the
+    // game loop through the `GameHandle`.
+    game_loop::start_game_loop(
+        game.clone(),
+        command_receiver,
+        host_broadcaster.clone(),
+        player_broadcaster.clone(),
+        event_log,
+        state_file,
+        score_batch_window,
+        clock,
+        rules,
+    );
+
+    shutdown::handle_shutdown(
+        signals,
+        game.clone(),
+        host_broadcaster.clone(),
+        player_broadcaster.clone(),
+    );
+
+    let metrics = Arc::new(Metrics::new());
+
+    // The admin endpoints are only enabled if an admin token is configured.
+    let admin_config = AdminConfig {
+        token: rocket.config().get_str("admin_token").ok().map(String::from),
+    };
+    if admin_config.token.is_none() {
+        info!("No admin token configured, admin endpoints are disabled");
+    }
+
+    // Start the main Rocket application.
+    rocket
+        .mount("/", routes![
+            static_serve,
+            static_serve_player,
+            static_serve_display,
+            metrics::metrics,
+            health::healthz,
+            health::readyz,
+        ])
+        .mount("/api", api_routes())
+        .mount("/api/v1", api_routes())
+        .mount("/api", routes![schema::schema])
+        .mount("/admin", routes![
+            admin::connections,
+            admin::set_next_elimination,
+        ])
+        .catch(errors![error::unauthorized, schema::unsupported_version])
+        .manage(game)
+        .manage(clock)
+        .manage(host_broadcaster)
+        .manage(player_broadcaster)
+        .manage(metrics.clone())
+        .manage(admin_config)
+        .attach(RequestTimer::new(metrics))
+        .attach(VersionHeader)
+        .launch();
+}
@@ -0,0 +1,214 @@
+//! Prometheus-style metrics for a running game.
+//!
+//! The `/metrics` endpoint reports metrics in the Prometheus text exposition format. Game
+//! counters come from the game loop, websocket gauges come from the broadcasters, and request
+//! latencies for the `/api` routes are recorded by the [`RequestTimer`] fairing.
+//!
+//! [`RequestTimer`]: ./struct.RequestTimer.html
+
+use broadcast::*;
+use error::*;
+use game_loop::GameHandle;
+use rocket::{Data, Request, Response, State};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Header};
+use rocket::response::content::Content;
+use std::collections::HashMap;
+use std::sync::*;
+use std::time::*;
+
+pub type MetricsHandle = Arc<Metrics>;
+
+/// The upper bounds (in seconds) of the buckets for request latency histograms.
+static LATENCY_BUCKETS: &'static [f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// The header used to pass a request's start time from `on_request` to `on_response`.
+///
+/// Rocket doesn't give fairings anywhere to store per-request data, so we smuggle the start time
+/// through a header. Any value sent by the client is overwritten.
+static START_HEADER: &'static str = "X-Hrh-Request-Start";
+
+/// Metrics that are collected outside of the game loop.
+#[derive(Debug)]
+pub struct Metrics {
+    /// The reference point for request start times.
+    epoch: Instant,
+
+    /// Request latency histograms, keyed by route.
+    latencies: Mutex<HashMap<String, Histogram>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            epoch: Instant::now(),
+            latencies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records that a request to `route` took `latency` to handle.
+    fn observe_latency(&self, route: &str, latency: Duration) {
+        let seconds = latency.as_secs() as f64 + latency.subsec_nanos() as f64 / 1_000_000_000.0;
+        let mut latencies = self.latencies.lock().recover("Request latencies");
+        latencies
+            .entry(route.into())
+            .or_insert_with(Histogram::new)
+            .observe(seconds);
+    }
+}
+
+/// A cumulative histogram with buckets defined by `LATENCY_BUCKETS`.
+#[derive(Debug)]
+struct Histogram {
+    /// The number of observations less than or equal to the corresponding bucket bound.
+    buckets: Vec<usize>,
+
+    /// The total number of observations.
+    count: usize,
+
+    /// The sum of all observations.
+    sum: f64,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bucket, &bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+/// Fairing that records how long each request to an `/api` route takes.
+#[derive(Debug)]
+pub struct RequestTimer {
+    metrics: MetricsHandle,
+}
+
+impl RequestTimer {
+    pub fn new(metrics: MetricsHandle) -> RequestTimer {
+        RequestTimer { metrics }
+    }
+}
+
+impl Fairing for RequestTimer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Timer",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let elapsed = self.metrics.epoch.elapsed();
+        let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+        request.replace_header(Header::new(START_HEADER, nanos.to_string()));
+    }
+
+    fn on_response(&self, request: &Request, _: &mut Response) {
+        let route = match request.route() {
+            Some(route) if route.uri.path().starts_with("/api") => route.uri.path(),
+            _ => return,
+        };
+
+        let start = request.headers()
+            .get_one(START_HEADER)
+            .and_then(|nanos| nanos.parse::<u64>().ok());
+        if let Some(start) = start {
+            let start = Duration::new(start / 1_000_000_000, (start % 1_000_000_000) as u32);
+            let elapsed = self.metrics.epoch.elapsed();
+            if elapsed > start {
+                self.metrics.observe_latency(route, elapsed - start);
+            }
+        }
+    }
+}
+
+/// Reports metrics for the running game in the Prometheus text format.
+#[get("/metrics")]
+pub fn metrics(
+    game: State<GameHandle>,
+    host_broadcaster: State<HostBroadcaster>,
+    player_broadcaster: State<PlayerBroadcaster>,
+    metrics: State<MetricsHandle>,
+) -> Result<Content<String>> {
+    let stats = game.stats()?;
+
+    let mut output = String::new();
+    write_metric(&mut output, "hrh_registrations_total", "counter", "Number of players that have registered.", &[
+        ("", stats.registrations),
+    ]);
+    write_metric(&mut output, "hrh_feeds_total", "counter", "Number of times a hippo has been fed.", &[
+        ("", stats.feeds),
+    ]);
+    write_metric(&mut output, "hrh_nose_goes_started_total", "counter", "Number of nose-goes events that have started.", &[
+        ("", stats.nose_goes_started),
+    ]);
+    write_metric(&mut output, "hrh_eliminations_total", "counter", "Number of players that have been knocked out.", &[
+        ("", stats.eliminations),
+    ]);
+    write_metric(&mut output, "hrh_lives_lost_total", "counter", "Number of lives lost by players that weren't knocked out.", &[
+        ("", stats.lives_lost),
+    ]);
+    write_metric(&mut output, "hrh_respawns_total", "counter", "Number of times a knocked-out player has rejoined the game.", &[
+        ("", stats.respawns),
+    ]);
+    write_metric(&mut output, "hrh_achievements_unlocked_total", "counter", "Number of achievements unlocked by all players.", &[
+        ("", stats.achievements_unlocked),
+    ]);
+    write_metric(&mut output, "hrh_bonus_points_awarded_total", "counter", "Number of bonus points awarded to nose-goes bonus winners.", &[
+        ("", stats.bonus_points_awarded),
+    ]);
+    write_metric(&mut output, "hrh_players", "gauge", "Number of players currently in the game.", &[
+        ("", stats.players),
+    ]);
+    write_metric(&mut output, "hrh_websocket_connections", "gauge", "Number of connected websockets.", &[
+        ("{stream=\"host\"}", host_broadcaster.connections()),
+        ("{stream=\"player\"}", player_broadcaster.connections()),
+    ]);
+    write_metric(&mut output, "hrh_broadcast_queue_depth", "gauge", "Number of broadcasts waiting to be sent.", &[
+        ("{stream=\"host\"}", host_broadcaster.queue_depth()),
+        ("{stream=\"player\"}", player_broadcaster.queue_depth()),
+    ]);
+
+    let name = "hrh_request_duration_seconds";
+    output.push_str(&format!("# HELP {} Time taken to handle requests to the /api routes.\n", name));
+    output.push_str(&format!("# TYPE {} histogram\n", name));
+    let latencies = metrics.latencies.lock().recover("Request latencies");
+    for (route, histogram) in latencies.iter() {
+        for (&bound, &count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+            output.push_str(&format!("{}_bucket{{route=\"{}\",le=\"{}\"}} {}\n", name, route, bound, count));
+        }
+        output.push_str(&format!("{}_bucket{{route=\"{}\",le=\"+Inf\"}} {}\n", name, route, histogram.count));
+        output.push_str(&format!("{}_sum{{route=\"{}\"}} {}\n", name, route, histogram.sum));
+        output.push_str(&format!("{}_count{{route=\"{}\"}} {}\n", name, route, histogram.count));
+    }
+
+    Ok(Content(ContentType::Plain, output))
+}
+
+/// Writes a metric with the given name, type and help text to `output`.
+///
+/// `samples` is a list of label sets and the value for each one. An empty label set is written
+/// as a sample without labels.
+fn write_metric(output: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, usize)]) {
+    output.push_str(&format!("# HELP {} {}\n", name, help));
+    output.push_str(&format!("# TYPE {} {}\n", name, kind));
+    for &(labels, value) in samples {
+        output.push_str(&format!("{}{} {}\n", name, labels, value));
+    }
+}
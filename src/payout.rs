@@ -0,0 +1,285 @@
+//! Strategies for paying out the nose-goes bonus.
+//!
+//! When a nose-goes event ends, the players that survived are put in the order that they tapped
+//! and handed to a [`PayoutStrategy`] along with the scores of the players that were knocked out.
+//! The strategy decides how many bonus points each survivor gets.
+//!
+//! The strategy is picked with the `bonus_payout` option in `Rocket.toml`:
+//!
+//! - `winner-takes-all` - The first player to tap gets the whole pot. This is the default.
+//! - `split:<count>` - The pot is split evenly between the first `count` players to tap.
+//! - `proportional` - The pot is split between everyone that tapped, weighted by how much time
+//!   was left when they tapped.
+//! - `fixed:<amount>` - The first player to tap gets `amount` points, no matter who lost.
+//!
+//! Unless the `crown_can_win_bonus` option is set, the player wearing the crown never gets any of
+//! the bonus.
+//!
+//! [`PayoutStrategy`]: ./trait.PayoutStrategy.html
+
+use game::PlayerId;
+use std::cmp;
+use std::str::FromStr;
+
+/// A surviving player that can be paid part of the bonus.
+#[derive(Debug, Clone, Copy)]
+pub struct BonusCandidate {
+    pub id: PlayerId,
+
+    /// How long before the end of the event the player tapped, in milliseconds.
+    pub time_left: u64,
+}
+
+/// Decides how the nose-goes bonus is divided up.
+pub trait PayoutStrategy {
+    /// Returns how many bonus points each player gets.
+    ///
+    /// `losers` are the scores of the players that were knocked out. `candidates` are the players
+    /// that can be paid, in the order that they tapped.
+    fn payouts(&self, losers: &[usize], candidates: &[BonusCandidate]) -> Vec<(PlayerId, usize)>;
+}
+
+/// Pays the whole pot to the first player to tap.
+#[derive(Debug, Clone, Copy)]
+pub struct WinnerTakesAll;
+
+impl PayoutStrategy for WinnerTakesAll {
+    fn payouts(&self, losers: &[usize], candidates: &[BonusCandidate]) -> Vec<(PlayerId, usize)> {
+        candidates.first()
+            .map(|candidate| vec![(candidate.id, loser_pot(losers))])
+            .unwrap_or_default()
+    }
+}
+
+/// Splits the pot evenly between the first `count` players to tap.
+///
+/// If the pot doesn't split evenly, the earliest players get the leftover points.
+#[derive(Debug, Clone, Copy)]
+pub struct SplitFirst {
+    pub count: usize,
+}
+
+impl PayoutStrategy for SplitFirst {
+    fn payouts(&self, losers: &[usize], candidates: &[BonusCandidate]) -> Vec<(PlayerId, usize)> {
+        let winners = &candidates[.. cmp::min(self.count, candidates.len())];
+        if winners.is_empty() {
+            return Vec::new();
+        }
+
+        let pot = loser_pot(losers);
+        let share = pot / winners.len();
+        let leftover = pot % winners.len();
+        winners.iter()
+            .enumerate()
+            .map(|(index, winner)| (winner.id, share + if index < leftover { 1 } else { 0 }))
+            .collect()
+    }
+}
+
+/// Splits the pot between every player that tapped, weighted by how much time was left when they
+/// tapped.
+///
+/// Any points lost to rounding go to the first player to tap.
+#[derive(Debug, Clone, Copy)]
+pub struct Proportional;
+
+impl PayoutStrategy for Proportional {
+    fn payouts(&self, losers: &[usize], candidates: &[BonusCandidate]) -> Vec<(PlayerId, usize)> {
+        // Everyone gets some weight, even if they tapped at the last possible moment.
+        let weights = candidates.iter()
+            .map(|candidate| cmp::max(candidate.time_left, 1))
+            .collect::<Vec<_>>();
+        let total_weight = weights.iter().sum::<u64>();
+        if total_weight == 0 {
+            return Vec::new();
+        }
+
+        let pot = loser_pot(losers) as u64;
+        let mut payouts = candidates.iter()
+            .zip(&weights)
+            .map(|(candidate, &weight)| (candidate.id, (pot * weight / total_weight) as usize))
+            .collect::<Vec<_>>();
+
+        let paid = payouts.iter().map(|&(_, amount)| amount).sum::<usize>();
+        payouts[0].1 += pot as usize - paid;
+        payouts
+    }
+}
+
+/// Pays a fixed number of points to the first player to tap, no matter who was knocked out.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedPot {
+    pub amount: usize,
+}
+
+impl PayoutStrategy for FixedPot {
+    fn payouts(&self, _losers: &[usize], candidates: &[BonusCandidate]) -> Vec<(PlayerId, usize)> {
+        candidates.first()
+            .map(|candidate| vec![(candidate.id, self.amount)])
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the bonus pot for a nose-goes event: Each loser adds their score, or 100 points if they
+/// had fewer than that.
+fn loser_pot(losers: &[usize]) -> usize {
+    losers.iter().map(|&score| cmp::max(score, 100)).sum()
+}
+
+/// Selects one of the payout strategies.
+///
+/// Unlike the strategies themselves this can be saved in the event log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Payout {
+    WinnerTakesAll,
+    SplitFirst { count: usize },
+    Proportional,
+    FixedPot { amount: usize },
+}
+
+impl Payout {
+    /// Returns the strategy that this selects.
+    pub fn strategy(&self) -> Box<PayoutStrategy> {
+        match *self {
+            Payout::WinnerTakesAll => Box::new(WinnerTakesAll),
+            Payout::SplitFirst { count } => Box::new(SplitFirst { count }),
+            Payout::Proportional => Box::new(Proportional),
+            Payout::FixedPot { amount } => Box::new(FixedPot { amount }),
+        }
+    }
+}
+
+impl Default for Payout {
+    fn default() -> Payout {
+        Payout::WinnerTakesAll
+    }
+}
+
+impl FromStr for Payout {
+    type Err = String;
+
+    /// Parses a strategy in the form used by the `bonus_payout` option.
+    fn from_str(payout: &str) -> Result<Payout, String> {
+        let mut parts = payout.trim().splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let argument = parts.next().map(str::trim);
+
+        match (name, argument) {
+            ("winner-takes-all", None) => Ok(Payout::WinnerTakesAll),
+            ("proportional", None) => Ok(Payout::Proportional),
+
+            ("split", Some(count)) => {
+                match count.parse() {
+                    Ok(count) if count > 0 => Ok(Payout::SplitFirst { count }),
+                    _ => Err(format!("Invalid count in bonus payout {:?}", payout)),
+                }
+            }
+
+            ("fixed", Some(amount)) => {
+                match amount.parse() {
+                    Ok(amount) => Ok(Payout::FixedPot { amount }),
+                    _ => Err(format!("Invalid amount in bonus payout {:?}", payout)),
+                }
+            }
+
+            _ => Err(format!("Unknown bonus payout {:?}", payout)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use game::{PlayerId, test_player_ids};
+    use super::*;
+
+    /// Returns candidates that tapped in order, with `time_left` for each.
+    fn candidates(time_left: &[u64]) -> Vec<BonusCandidate> {
+        test_player_ids(time_left.len()).into_iter()
+            .zip(time_left)
+            .map(|(id, &time_left)| BonusCandidate { id, time_left })
+            .collect()
+    }
+
+    fn paid(payouts: &[(PlayerId, usize)]) -> Vec<usize> {
+        payouts.iter().map(|&(_, amount)| amount).collect()
+    }
+
+    #[test]
+    fn losers_add_at_least_100_points() {
+        assert_eq!(loser_pot(&[]), 0);
+        assert_eq!(loser_pot(&[0, 50, 100, 250]), 550);
+    }
+
+    #[test]
+    fn winner_takes_all_pays_the_first_tap() {
+        let candidates = candidates(&[500, 300]);
+        let payouts = WinnerTakesAll.payouts(&[20, 300], &candidates);
+        assert_eq!(payouts, vec![(candidates[0].id, 400)]);
+    }
+
+    #[test]
+    fn split_gives_leftovers_to_the_earliest_players() {
+        let candidates = candidates(&[500, 300, 100, 50]);
+        let payouts = SplitFirst { count: 3 }.payouts(&[100], &candidates);
+        assert_eq!(payouts.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+                   candidates[.. 3].iter().map(|candidate| candidate.id).collect::<Vec<_>>());
+        assert_eq!(paid(&payouts), vec![34, 33, 33]);
+    }
+
+    #[test]
+    fn split_between_fewer_candidates_than_count() {
+        let payouts = SplitFirst { count: 5 }.payouts(&[100], &candidates(&[500, 300]));
+        assert_eq!(paid(&payouts), vec![50, 50]);
+    }
+
+    #[test]
+    fn proportional_weights_by_time_left() {
+        let payouts = Proportional.payouts(&[200], &candidates(&[300, 100]));
+        assert_eq!(paid(&payouts), vec![150, 50]);
+    }
+
+    #[test]
+    fn proportional_gives_rounding_to_the_first_tap() {
+        let payouts = Proportional.payouts(&[100], &candidates(&[10, 10, 10]));
+        assert_eq!(paid(&payouts), vec![34, 33, 33]);
+    }
+
+    #[test]
+    fn proportional_pays_last_moment_taps() {
+        let payouts = Proportional.payouts(&[100], &candidates(&[0, 0]));
+        assert_eq!(paid(&payouts), vec![50, 50]);
+    }
+
+    #[test]
+    fn fixed_ignores_the_losers() {
+        let candidates = candidates(&[500, 300]);
+        let payouts = FixedPot { amount: 250 }.payouts(&[1000, 1000], &candidates);
+        assert_eq!(payouts, vec![(candidates[0].id, 250)]);
+    }
+
+    #[test]
+    fn no_candidates_means_no_payouts() {
+        let payouts = [
+            Payout::WinnerTakesAll,
+            Payout::SplitFirst { count: 2 },
+            Payout::Proportional,
+            Payout::FixedPot { amount: 100 },
+        ];
+
+        for payout in &payouts {
+            assert!(payout.strategy().payouts(&[500], &[]).is_empty());
+        }
+    }
+
+    #[test]
+    fn parse_payouts() {
+        assert_eq!("winner-takes-all".parse(), Ok(Payout::WinnerTakesAll));
+        assert_eq!(" proportional ".parse(), Ok(Payout::Proportional));
+        assert_eq!("split: 3".parse(), Ok(Payout::SplitFirst { count: 3 }));
+        assert_eq!("fixed:0".parse(), Ok(Payout::FixedPot { amount: 0 }));
+
+        for payout in &["", "split", "split:0", "fixed:-1", "proportional:2", "everyone"] {
+            assert!(payout.parse::<Payout>().is_err(), "{:?} should be rejected", payout);
+        }
+    }
+}
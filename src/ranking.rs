@@ -0,0 +1,200 @@
+//! A ranking of every player in the game, kept sorted as scores change.
+//!
+//! Players are ordered by score, highest first. Players with the same score are ordered by when
+//! they reached it (see `Player::scored_at`), the same order that the crown's tie-break policy
+//! sees them in. Tied players share a rank, so two players tied for first are both ranked 1 and
+//! the next player is ranked 3.
+//!
+//! The game state updates the ranking whenever a player's score changes, and announces changes to
+//! the top of the ranking (the podium) to the hosts with a `RankingChanged` broadcast.
+
+use game::PlayerId;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+/// Where a player sits in the ranking.
+///
+/// Ordered so that the highest score comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RankKey {
+    score: usize,
+    scored_at: u64,
+    id: PlayerId,
+}
+
+impl Ord for RankKey {
+    fn cmp(&self, other: &RankKey) -> Ordering {
+        other.score.cmp(&self.score)
+            .then(self.scored_at.cmp(&other.scored_at))
+            .then(self.id.cmp(&other.id))
+    }
+}
+
+impl PartialOrd for RankKey {
+    fn partial_cmp(&self, other: &RankKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Every player in the game, sorted by score.
+#[derive(Debug, Clone, Default)]
+pub struct Ranking {
+    /// The players in rank order.
+    sorted: BTreeMap<RankKey, PlayerId>,
+
+    /// Where each player currently is in `sorted`, so that they can be moved when their score
+    /// changes.
+    keys: HashMap<PlayerId, RankKey>,
+}
+
+impl Ranking {
+    pub fn new() -> Ranking {
+        Ranking::default()
+    }
+
+    /// Adds the player to the ranking, or moves them if they're already in it.
+    ///
+    /// `scored_at` is when the player reached `score`, which decides their place among players
+    /// with the same score.
+    pub fn update(&mut self, id: PlayerId, score: usize, scored_at: u64) {
+        let key = RankKey { score, scored_at, id };
+        if let Some(old_key) = self.keys.insert(id, key) {
+            self.sorted.remove(&old_key);
+        }
+        self.sorted.insert(key, id);
+    }
+
+    /// Removes the player from the ranking, e.g. once they've been knocked out.
+    pub fn remove(&mut self, id: PlayerId) {
+        if let Some(key) = self.keys.remove(&id) {
+            self.sorted.remove(&key);
+        }
+    }
+
+    /// Returns the players tied for the highest score, in the order they reached it.
+    pub fn leaders(&self) -> Vec<PlayerId> {
+        let top_score = match self.sorted.keys().next() {
+            Some(key) => key.score,
+            None => return Vec::new(),
+        };
+
+        self.sorted.iter()
+            .take_while(|&(key, _)| key.score == top_score)
+            .map(|(_, &id)| id)
+            .collect()
+    }
+
+    /// Returns the rank and ID of the top `limit` players, or of every player if `limit` is
+    /// `None`.
+    pub fn top(&self, limit: Option<usize>) -> Vec<(usize, PlayerId)> {
+        let limit = limit.unwrap_or(self.sorted.len());
+        let mut top = Vec::new();
+        let mut previous: Option<(usize, usize)> = None;
+        for (position, (key, &id)) in self.sorted.iter().take(limit).enumerate() {
+            let rank = match previous {
+                Some((score, rank)) if score == key.score => rank,
+                _ => position + 1,
+            };
+            previous = Some((key.score, rank));
+            top.push((rank, id));
+        }
+
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use game::{PlayerId, test_player_ids};
+    use super::*;
+
+    #[test]
+    fn empty_ranking() {
+        let ranking = Ranking::new();
+        assert!(ranking.leaders().is_empty());
+        assert!(ranking.top(None).is_empty());
+        assert!(ranking.top(Some(3)).is_empty());
+    }
+
+    #[test]
+    fn highest_score_first() {
+        let ids = test_player_ids(3);
+        let mut ranking = Ranking::new();
+        ranking.update(ids[0], 10, 1);
+        ranking.update(ids[1], 30, 2);
+        ranking.update(ids[2], 20, 3);
+
+        assert_eq!(ranking.top(None), vec![(1, ids[1]), (2, ids[2]), (3, ids[0])]);
+    }
+
+    #[test]
+    fn tied_players_share_a_rank_in_the_order_they_scored() {
+        let ids = test_player_ids(4);
+        let mut ranking = Ranking::new();
+        ranking.update(ids[0], 20, 4);
+        ranking.update(ids[1], 20, 2);
+        ranking.update(ids[2], 10, 3);
+        ranking.update(ids[3], 30, 1);
+
+        assert_eq!(ranking.top(None), vec![(1, ids[3]), (2, ids[1]), (2, ids[0]), (4, ids[2])]);
+    }
+
+    #[test]
+    fn leaders_are_everyone_tied_for_first() {
+        let ids = test_player_ids(3);
+        let mut ranking = Ranking::new();
+        ranking.update(ids[0], 20, 2);
+        ranking.update(ids[1], 20, 1);
+        ranking.update(ids[2], 10, 3);
+
+        assert_eq!(ranking.leaders(), vec![ids[1], ids[0]]);
+    }
+
+    #[test]
+    fn update_moves_players() {
+        let ids = test_player_ids(2);
+        let mut ranking = Ranking::new();
+        ranking.update(ids[0], 20, 1);
+        ranking.update(ids[1], 10, 2);
+        ranking.update(ids[1], 25, 3);
+
+        assert_eq!(ranking.top(None), vec![(1, ids[1]), (2, ids[0])]);
+        assert_eq!(ranking.leaders(), vec![ids[1]]);
+    }
+
+    #[test]
+    fn remove_players() {
+        let ids = test_player_ids(2);
+        let mut ranking = Ranking::new();
+        ranking.update(ids[0], 20, 1);
+        ranking.update(ids[1], 10, 2);
+        ranking.remove(ids[0]);
+        ranking.remove(PlayerId::new());
+
+        assert_eq!(ranking.top(None), vec![(1, ids[1])]);
+    }
+
+    #[test]
+    fn top_is_limited() {
+        let ids = test_player_ids(4);
+        let mut ranking = Ranking::new();
+        for (index, &id) in ids.iter().enumerate() {
+            ranking.update(id, 100 - index, index as u64);
+        }
+
+        assert_eq!(ranking.top(Some(2)), vec![(1, ids[0]), (2, ids[1])]);
+        assert!(ranking.top(Some(0)).is_empty());
+        assert_eq!(ranking.top(Some(10)).len(), 4);
+    }
+
+    #[test]
+    fn ties_past_the_limit_are_cut_off() {
+        let ids = test_player_ids(3);
+        let mut ranking = Ranking::new();
+        ranking.update(ids[0], 30, 1);
+        ranking.update(ids[1], 20, 2);
+        ranking.update(ids[2], 20, 3);
+
+        assert_eq!(ranking.top(Some(2)), vec![(1, ids[0]), (2, ids[1])]);
+    }
+}
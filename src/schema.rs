@@ -0,0 +1,1033 @@
+//! The versioned contract between the server and its clients.
+//!
+//! The API is served under `/api/v1`. The unversioned `/api` routes are kept around for clients
+//! that haven't been updated yet, but new clients should use the versioned routes. Clients can
+//! send the `X-Hrh-Api-Version` header to say which version of the API they expect: If the server
+//! doesn't support that version the request fails with a 406 rather than the client silently
+//! misreading the response. Every API response includes the header with the server's version.
+//!
+//! `/api/schema` describes every request, response and websocket message as a [JSON Schema]. The
+//! schemas are built from the Rust types through the [`Schema`] trait, so any type that's sent to
+//! clients needs a `Schema` impl, and the impl needs to be updated along with the type. Since
+//! field types supply their own schemas, changing how e.g. `PlayerId` is serialized only needs to
+//! be updated in one place.
+//!
+//! [JSON Schema]: http://json-schema.org/
+//! [`Schema`]: ./trait.Schema.html
+
+use achievements::{Achievement, ACHIEVEMENTS};
+use api::*;
+use broadcast::*;
+use cosmetics::*;
+use elimination::Elimination;
+use encoding;
+use error::Error;
+use game::{CrownTenure, NoseGoesTap, PlayerId};
+use rocket::{Outcome, Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest};
+use rocket_contrib::Json;
+use serde_json::Value;
+use socket_api::*;
+use std::collections::HashSet;
+
+/// The current version of the HTTP API.
+pub const API_VERSION: u32 = 1;
+
+/// The header used to negotiate the API version.
+static VERSION_HEADER: &'static str = "X-Hrh-Api-Version";
+
+/// Request guard that rejects requests for an API version the server doesn't support.
+///
+/// Requests without the version header are assumed to be for the current version.
+#[derive(Debug)]
+pub struct ApiVersion;
+
+impl<'a, 'r> FromRequest<'a, 'r> for ApiVersion {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ApiVersion, ()> {
+        match request.headers().get_one(VERSION_HEADER) {
+            None => Outcome::Success(ApiVersion),
+            Some(version) if version.trim() == API_VERSION.to_string() => {
+                Outcome::Success(ApiVersion)
+            }
+
+            Some(version) => {
+                warn!("Rejected request for unsupported API version {:?}: {}", version, request);
+                Outcome::Failure((Status::NotAcceptable, ()))
+            }
+        }
+    }
+}
+
+/// Sends an `UnsupportedVersion` error body when `ApiVersion` rejects a request.
+///
+/// Without this catcher the client would get Rocket's default HTML error page, which doesn't say
+/// which versions the server supports.
+#[error(406)]
+pub fn unsupported_version() -> Error {
+    Error::UnsupportedVersion { supported: vec![API_VERSION] }
+}
+
+/// Fairing that adds the `X-Hrh-Api-Version` header to every API response.
+#[derive(Debug)]
+pub struct VersionHeader;
+
+impl Fairing for VersionHeader {
+    fn info(&self) -> Info {
+        Info {
+            name: "API Version Header",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if request.uri().path().starts_with("/api") {
+            response.set_header(Header::new(VERSION_HEADER, API_VERSION.to_string()));
+        }
+    }
+}
+
+/// A type that can describe its serialized form as a JSON Schema.
+pub trait Schema {
+    fn schema() -> Value;
+}
+
+impl Schema for usize {
+    fn schema() -> Value {
+        json!({ "type": "integer", "minimum": 0 })
+    }
+}
+
+impl Schema for u64 {
+    fn schema() -> Value {
+        json!({ "type": "integer", "minimum": 0 })
+    }
+}
+
+impl Schema for bool {
+    fn schema() -> Value {
+        json!({ "type": "boolean" })
+    }
+}
+
+impl Schema for String {
+    fn schema() -> Value {
+        json!({ "type": "string" })
+    }
+}
+
+impl<T: Schema> Schema for Vec<T> {
+    fn schema() -> Value {
+        json!({ "type": "array", "items": T::schema() })
+    }
+}
+
+impl<T: Schema> Schema for HashSet<T> {
+    fn schema() -> Value {
+        json!({ "type": "array", "items": T::schema(), "uniqueItems": true })
+    }
+}
+
+impl<T: Schema> Schema for Option<T> {
+    fn schema() -> Value {
+        json!({ "anyOf": [T::schema(), { "type": "null" }] })
+    }
+}
+
+impl<A: Schema, B: Schema> Schema for (A, B) {
+    fn schema() -> Value {
+        json!({
+            "type": "array",
+            "items": [A::schema(), B::schema()],
+            "minItems": 2,
+            "maxItems": 2
+        })
+    }
+}
+
+impl<T: Schema> Schema for ::std::result::Result<T, Error> {
+    fn schema() -> Value {
+        one_of(vec![
+            variant("Ok", T::schema()),
+            variant("Err", Error::schema()),
+        ])
+    }
+}
+
+impl Schema for PlayerId {
+    fn schema() -> Value {
+        json!({ "type": "string", "pattern": "^[0-9]+$" })
+    }
+}
+
+impl Schema for Error {
+    fn schema() -> Value {
+        let error = |code: &str, details: Value| {
+            object(vec![
+                ("code", json!({ "enum": [code] })),
+                ("message", String::schema()),
+                ("details", details),
+            ])
+        };
+        let null = json!({ "type": "null" });
+        let player = object(vec![("id", PlayerId::schema())]);
+
+        one_of(vec![
+            error("invalid_player", player.clone()),
+            error("nose_goes_not_active", null.clone()),
+            error("player_not_in_round", player.clone()),
+            error("player_not_eliminated", player),
+            error("respawn_cooldown", object(vec![("retry_after", u64::schema())])),
+            error("invalid_request", null.clone()),
+            error("cosmetic_locked", object(vec![
+                ("item", String::schema()),
+                ("unlock_score", usize::schema()),
+            ])),
+            error("game_paused", null.clone()),
+            error("rate_limited", object(vec![("retry_after", u64::schema())])),
+            error("unauthorized", null.clone()),
+            error("unsupported_api_version", object(vec![("supported", Vec::<u64>::schema())])),
+            error("internal", null),
+        ])
+    }
+}
+
+impl Schema for Elimination {
+    fn schema() -> Value {
+        one_of(vec![
+            json!({ "enum": ["LastTapper", "MissedDeadline"] }),
+            variant("BottomTappers", object(vec![("count", usize::schema())])),
+            variant("FieldPercentage", object(vec![("percent", u64::schema())])),
+        ])
+    }
+}
+
+impl Schema for PlayerData {
+    fn schema() -> Value {
+        object(vec![
+            ("id", PlayerId::schema()),
+            ("name", String::schema()),
+            ("score", usize::schema()),
+            ("lives", usize::schema()),
+            ("cosmetics", Cosmetics::schema()),
+            ("achievements", Vec::<Achievement>::schema()),
+            ("has_crown", bool::schema()),
+        ])
+    }
+}
+
+impl Schema for Achievement {
+    fn schema() -> Value {
+        let names = ACHIEVEMENTS.iter()
+            .map(|achievement| format!("{:?}", achievement))
+            .collect::<Vec<_>>();
+        json!({ "enum": names })
+    }
+}
+
+impl Schema for Cosmetics {
+    fn schema() -> Value {
+        let ids = |items: &[CosmeticItem]| {
+            json!({ "enum": items.iter().map(|item| item.id).collect::<Vec<_>>() })
+        };
+
+        object_with_optional(
+            vec![("color", ids(COLORS)), ("skin", ids(SKINS))],
+            vec![("hat", json!({ "anyOf": [ids(HATS), { "type": "null" }] }))],
+        )
+    }
+}
+
+impl Schema for CosmeticItem {
+    fn schema() -> Value {
+        object(vec![
+            ("id", String::schema()),
+            ("name", String::schema()),
+            ("unlock_score", usize::schema()),
+        ])
+    }
+}
+
+impl Schema for CosmeticCatalog {
+    fn schema() -> Value {
+        object(vec![
+            ("colors", Vec::<CosmeticItem>::schema()),
+            ("hats", Vec::<CosmeticItem>::schema()),
+            ("skins", Vec::<CosmeticItem>::schema()),
+        ])
+    }
+}
+
+impl Schema for FeedMeRequest {
+    fn schema() -> Value {
+        object(vec![("id", PlayerId::schema())])
+    }
+}
+
+impl Schema for FeedMeResponse {
+    fn schema() -> Value {
+        object(vec![("score", usize::schema())])
+    }
+}
+
+impl Schema for FeedBatchRequest {
+    fn schema() -> Value {
+        object(vec![
+            ("id", PlayerId::schema()),
+            ("taps", Vec::<u64>::schema()),
+        ])
+    }
+}
+
+impl Schema for FeedBatchResponse {
+    fn schema() -> Value {
+        object(vec![
+            ("accepted", usize::schema()),
+            ("score", usize::schema()),
+        ])
+    }
+}
+
+impl Schema for NoseGoesResponse {
+    fn schema() -> Value {
+        json!({ "enum": ["Pending", "Survived", "LostLife", "Died"] })
+    }
+}
+
+impl Schema for NoseGoesTapRequest {
+    fn schema() -> Value {
+        object_with_optional(vec![], vec![("tapped_at", Option::<u64>::schema())])
+    }
+}
+
+impl Schema for NoseGoesTap {
+    fn schema() -> Value {
+        object(vec![
+            ("received", u64::schema()),
+            ("time", u64::schema()),
+            ("outcome", NoseGoesResponse::schema()),
+        ])
+    }
+}
+
+impl Schema for NoseGoesStatus {
+    fn schema() -> Value {
+        object(vec![
+            ("active", bool::schema()),
+            ("in_round", bool::schema()),
+            ("tap", Option::<NoseGoesTap>::schema()),
+            ("outcome", Option::<NoseGoesResponse>::schema()),
+        ])
+    }
+}
+
+impl Schema for PlayersResponse {
+    fn schema() -> Value {
+        object(vec![("players", Vec::<PlayerData>::schema())])
+    }
+}
+
+impl Schema for RankedPlayer {
+    fn schema() -> Value {
+        object(vec![
+            ("rank", usize::schema()),
+            ("id", PlayerId::schema()),
+            ("name", String::schema()),
+            ("score", usize::schema()),
+        ])
+    }
+}
+
+impl Schema for RankingsResponse {
+    fn schema() -> Value {
+        object(vec![("rankings", Vec::<RankedPlayer>::schema())])
+    }
+}
+
+impl Schema for CrownTenure {
+    fn schema() -> Value {
+        object(vec![
+            ("id", PlayerId::schema()),
+            ("name", String::schema()),
+            ("started_at", u64::schema()),
+            ("ended_at", Option::<u64>::schema()),
+            ("duration", u64::schema()),
+        ])
+    }
+}
+
+impl Schema for CrownHistoryResponse {
+    fn schema() -> Value {
+        object(vec![("history", Vec::<CrownTenure>::schema())])
+    }
+}
+
+impl Schema for TimeResponse {
+    fn schema() -> Value {
+        object(vec![("server_time", u64::schema())])
+    }
+}
+
+impl Schema for ScoreUpdate {
+    fn schema() -> Value {
+        object(vec![
+            ("id", PlayerId::schema()),
+            ("score", usize::schema()),
+            ("delta", usize::schema()),
+        ])
+    }
+}
+
+impl Schema for BonusPayout {
+    fn schema() -> Value {
+        object(vec![
+            ("id", PlayerId::schema()),
+            ("amount", usize::schema()),
+            ("score", usize::schema()),
+        ])
+    }
+}
+
+impl Schema for HostBroadcast {
+    fn schema() -> Value {
+        one_of(vec![
+            variant("PlayerRegister", object(vec![
+                ("id", PlayerId::schema()),
+                ("name", String::schema()),
+                ("score", usize::schema()),
+                ("lives", usize::schema()),
+                ("cosmetics", Cosmetics::schema()),
+            ])),
+            variant("PlayerRespawn", object(vec![
+                ("id", PlayerId::schema()),
+                ("name", String::schema()),
+                ("score", usize::schema()),
+                ("lives", usize::schema()),
+                ("cosmetics", Cosmetics::schema()),
+            ])),
+            variant("PlayerCosmetics", object(vec![
+                ("id", PlayerId::schema()),
+                ("cosmetics", Cosmetics::schema()),
+            ])),
+            variant("AchievementUnlocked", object(vec![
+                ("id", PlayerId::schema()),
+                ("achievement", Achievement::schema()),
+                ("title", String::schema()),
+                ("description", String::schema()),
+            ])),
+            variant("HippoEat", object(vec![
+                ("id", PlayerId::schema()),
+                ("score", usize::schema()),
+            ])),
+            variant("BeginNoseGoes", object(vec![
+                ("duration", u64::schema()),
+                ("start_time", u64::schema()),
+                ("end_time", u64::schema()),
+                ("players", HashSet::<PlayerId>::schema()),
+                ("elimination", Elimination::schema()),
+            ])),
+            variant("BonusWinner", object(vec![("id", PlayerId::schema())])),
+            variant("LifeLost", object(vec![
+                ("id", PlayerId::schema()),
+                ("lives", usize::schema()),
+            ])),
+            variant("EndNoseGoes", object(vec![
+                ("losers", HashSet::<PlayerId>::schema()),
+                ("bonus_winner", Option::<(PlayerId, usize)>::schema()),
+                ("payouts", Vec::<BonusPayout>::schema()),
+            ])),
+            variant("UpdateWinner", object(vec![("holders", Vec::<PlayerId>::schema())])),
+            variant("ServerShuttingDown", object(vec![])),
+            variant("ScoreBatch", object(vec![("scores", Vec::<ScoreUpdate>::schema())])),
+            variant("RankingChanged", object(vec![("top", Vec::<RankedPlayer>::schema())])),
+        ])
+    }
+}
+
+impl Schema for PlayerBroadcast {
+    fn schema() -> Value {
+        one_of(vec![
+            variant("BeginNoseGoes", object(vec![
+                ("start_time", u64::schema()),
+                ("end_time", u64::schema()),
+            ])),
+            variant("BonusWinner", object(vec![("id", PlayerId::schema())])),
+            variant("AchievementUnlocked", object(vec![
+                ("id", PlayerId::schema()),
+                ("achievement", Achievement::schema()),
+                ("title", String::schema()),
+                ("description", String::schema()),
+            ])),
+            variant("LifeLost", object(vec![
+                ("id", PlayerId::schema()),
+                ("lives", usize::schema()),
+            ])),
+            variant("EndNoseGoes", object(vec![
+                ("bonus_winner", Option::<(PlayerId, usize)>::schema()),
+                ("payouts", Vec::<BonusPayout>::schema()),
+            ])),
+            variant("PlayerLose", object(vec![
+                ("id", PlayerId::schema()),
+                ("score", usize::schema()),
+                ("respawn_at", Option::<u64>::schema()),
+            ])),
+            variant("UpdateWinner", object(vec![("holders", Vec::<PlayerId>::schema())])),
+            variant("ServerShuttingDown", object(vec![])),
+        ])
+    }
+}
+
+impl Schema for PlayerRequest {
+    fn schema() -> Value {
+        object(vec![
+            ("request_id", u64::schema()),
+            ("command", PlayerCommand::schema()),
+        ])
+    }
+}
+
+impl Schema for PlayerCommand {
+    fn schema() -> Value {
+        one_of(vec![
+            variant("Feed", object(vec![("id", PlayerId::schema())])),
+            variant("FeedBatch", object(vec![
+                ("id", PlayerId::schema()),
+                ("taps", Vec::<u64>::schema()),
+            ])),
+            variant("NoseGoesTap", object_with_optional(
+                vec![("id", PlayerId::schema())],
+                vec![("tapped_at", Option::<u64>::schema())],
+            )),
+        ])
+    }
+}
+
+impl Schema for CommandResponse {
+    fn schema() -> Value {
+        one_of(vec![
+            variant("Feed", FeedMeResponse::schema()),
+            variant("FeedBatch", FeedBatchResponse::schema()),
+            variant("NoseGoesTap", NoseGoesResponse::schema()),
+        ])
+    }
+}
+
+impl Schema for PlayerReply {
+    fn schema() -> Value {
+        one_of(vec![
+            variant("Reply", object(vec![
+                ("request_id", Option::<u64>::schema()),
+                ("result", ::std::result::Result::<CommandResponse, Error>::schema()),
+            ])),
+        ])
+    }
+}
+
+/// Builds the schema for a struct, where every field is required.
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    let required = fields.iter().map(|&(name, _)| name.to_string()).collect::<Vec<_>>();
+    let properties = fields.into_iter()
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect::<::serde_json::Map<_, _>>();
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false
+    })
+}
+
+/// Builds the schema for a struct with some fields that can be left out.
+fn object_with_optional(fields: Vec<(&str, Value)>, optional: Vec<(&str, Value)>) -> Value {
+    let mut schema = object(fields);
+    for (name, field) in optional {
+        schema["properties"][name] = field;
+    }
+    schema
+}
+
+/// Builds the schema for an enum variant with data, which serde serializes as an object with a
+/// single member named after the variant.
+fn variant(name: &str, schema: Value) -> Value {
+    object(vec![(name, schema)])
+}
+
+/// Builds the schema for a value that matches exactly one of `schemas`.
+fn one_of(schemas: Vec<Value>) -> Value {
+    json!({ "oneOf": schemas })
+}
+
+/// Describes an HTTP endpoint.
+fn endpoint(method: &str, path: &str, request: Option<Value>, response: Value) -> Value {
+    json!({
+        "method": method,
+        "path": path,
+        "request": request,
+        "response": response
+    })
+}
+
+/// Describes the API, the websocket messages and their schemas.
+#[get("/schema")]
+pub fn schema() -> Json<Value> {
+    let prefix = format!("/api/v{}", API_VERSION);
+    let path = |path: &str| format!("{}{}", prefix, path);
+
+    Json(json!({
+        "api_version": API_VERSION,
+        "version_header": VERSION_HEADER,
+        "endpoints": [
+            endpoint("GET", &path("/register-player"), None, PlayerData::schema()),
+            endpoint(
+                "POST",
+                &path("/register-player"),
+                Some(Cosmetics::schema()),
+                PlayerData::schema()
+            ),
+            endpoint("GET", &path("/cosmetics"), None, CosmeticCatalog::schema()),
+            endpoint(
+                "POST",
+                &path("/cosmetics/{id}"),
+                Some(Cosmetics::schema()),
+                PlayerData::schema()
+            ),
+            endpoint(
+                "POST",
+                &path("/feed-me"),
+                Some(FeedMeRequest::schema()),
+                FeedMeResponse::schema()
+            ),
+            endpoint(
+                "POST",
+                &path("/feed-batch"),
+                Some(FeedBatchRequest::schema()),
+                FeedBatchResponse::schema()
+            ),
+            endpoint(
+                "POST",
+                &path("/nose-goes/{id}"),
+                Some(NoseGoesTapRequest::schema()),
+                NoseGoesResponse::schema()
+            ),
+            endpoint("GET", &path("/nose-goes/status/{id}"), None, NoseGoesStatus::schema()),
+            endpoint("GET", &path("/crown-history"), None, CrownHistoryResponse::schema()),
+            endpoint("GET", &path("/player/{id}"), None, Option::<PlayerData>::schema()),
+            endpoint("GET", &path("/players"), None, PlayersResponse::schema()),
+            endpoint("GET", &path("/rankings?limit={limit}"), None, RankingsResponse::schema()),
+            endpoint("POST", &path("/respawn/{id}"), None, PlayerData::schema()),
+            endpoint("GET", &path("/time"), None, TimeResponse::schema())
+        ],
+        "errors": Error::schema(),
+        "websockets": {
+            "schema_version": encoding::SCHEMA_VERSION,
+            "host": {
+                "port": 6769,
+                "broadcasts": HostBroadcast::schema()
+            },
+            "player": {
+                "port": 6768,
+                "broadcasts": PlayerBroadcast::schema(),
+                "requests": PlayerRequest::schema(),
+                "replies": PlayerReply::schema()
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use achievements::Achievement;
+    use api::*;
+    use broadcast::*;
+    use cosmetics::{CosmeticCatalog, Cosmetics};
+    use elimination::Elimination;
+    use error::Error;
+    use game::{CrownTenure, NoseGoesTap, PlayerId};
+    use serde::Serialize;
+    use serde::de::DeserializeOwned;
+    use serde_json::{self, Value};
+    use socket_api::*;
+    use super::Schema;
+
+    /// Checks `value` against `schema`, returning where the first mismatch is.
+    ///
+    /// Only the parts of JSON Schema that the `Schema` impls use are supported, and anything else
+    /// fails the test so that the checks don't silently get weaker.
+    fn validate(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+        if let Some(schemas) = schema.get("anyOf").and_then(Value::as_array) {
+            if schemas.iter().any(|schema| validate(schema, value, path).is_ok()) {
+                return Ok(());
+            }
+            return Err(format!("{}: {} doesn't match any of {}", path, value, schema));
+        }
+
+        if let Some(schemas) = schema.get("oneOf").and_then(Value::as_array) {
+            let matches = schemas.iter()
+                .filter(|schema| validate(schema, value, path).is_ok())
+                .count();
+            if matches == 1 {
+                return Ok(());
+            }
+            return Err(format!("{}: {} matches {} of {}", path, value, matches, schema));
+        }
+
+        if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+            if options.contains(value) {
+                return Ok(());
+            }
+            return Err(format!("{}: {} isn't one of {}", path, value, schema));
+        }
+
+        let valid = match schema["type"].as_str() {
+            Some("null") => value.is_null(),
+            Some("boolean") => value.is_boolean(),
+            Some("integer") => {
+                assert_eq!(schema["minimum"], json!(0), "Unsupported minimum in {}", schema);
+                value.is_u64()
+            }
+
+            Some("string") => {
+                match (value.as_str(), schema.get("pattern").and_then(Value::as_str)) {
+                    (Some(_), None) => true,
+                    (Some(string), Some("^[0-9]+$")) => {
+                        !string.is_empty() && string.chars().all(|c| c.is_digit(10))
+                    }
+                    (Some(_), Some(pattern)) => panic!("Unsupported pattern {:?}", pattern),
+                    (None, _) => false,
+                }
+            }
+
+            Some("array") => {
+                let items = match value.as_array() {
+                    Some(items) => items,
+                    None => return Err(format!("{}: {} isn't an array", path, value)),
+                };
+
+                let min = schema.get("minItems").and_then(Value::as_u64).unwrap_or(0);
+                let max = schema.get("maxItems").and_then(Value::as_u64);
+                let len = items.len() as u64;
+                if len < min || max.map(|max| len > max).unwrap_or(false) {
+                    return Err(format!("{}: {} has the wrong number of items", path, value));
+                }
+
+                for (index, item) in items.iter().enumerate() {
+                    // Tuples have a schema for each item, other arrays share one schema.
+                    let item_schema = match schema["items"].as_array() {
+                        Some(schemas) => &schemas[index],
+                        None => &schema["items"],
+                    };
+                    validate(item_schema, item, &format!("{}[{}]", path, index))?;
+                }
+                true
+            }
+
+            Some("object") => {
+                let fields = match value.as_object() {
+                    Some(fields) => fields,
+                    None => return Err(format!("{}: {} isn't an object", path, value)),
+                };
+
+                for name in schema["required"].as_array().expect("Missing required fields") {
+                    let name = name.as_str().expect("Field names should be strings");
+                    if !fields.contains_key(name) {
+                        return Err(format!("{}: {} is missing {:?}", path, value, name));
+                    }
+                }
+
+                for (name, field) in fields {
+                    let field_path = format!("{}.{}", path, name);
+                    match schema["properties"].get(name) {
+                        Some(field_schema) => validate(field_schema, field, &field_path)?,
+                        None => return Err(format!("{}: Unexpected field", field_path)),
+                    }
+                }
+                true
+            }
+
+            _ => panic!("Unsupported schema {}", schema),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("{}: {} doesn't match {}", path, value, schema))
+        }
+    }
+
+    /// Panics if `value` doesn't serialize to something that matches its schema.
+    fn assert_serializes<T: Schema + Serialize>(value: &T) {
+        let value = serde_json::to_value(value).expect("Failed to serialize value");
+        if let Err(error) = validate(&T::schema(), &value, "$") {
+            panic!("{}", error);
+        }
+    }
+
+    /// Panics if `value` doesn't match the schema for `T`, or if `T` can't be deserialized from
+    /// it.
+    fn assert_deserializes<T: Schema + DeserializeOwned>(value: Value) {
+        if let Err(error) = validate(&T::schema(), &value, "$") {
+            panic!("{}", error);
+        }
+        if let Err(error) = serde_json::from_value::<T>(value) {
+            panic!("Value matches the schema but failed to deserialize: {}", error);
+        }
+    }
+
+    fn player_data() -> PlayerData {
+        PlayerData {
+            id: PlayerId::new(),
+            name: "Hungry Hippo".into(),
+            score: 120,
+            lives: 2,
+            cosmetics: Cosmetics {
+                color: "pink".into(),
+                hat: Some("bow".into()),
+                skin: "plain".into(),
+            },
+            achievements: vec![Achievement::FirstToHundred],
+            has_crown: true,
+        }
+    }
+
+    fn ranked_player() -> RankedPlayer {
+        RankedPlayer { rank: 1, id: PlayerId::new(), name: "Hungry Hippo".into(), score: 120 }
+    }
+
+    fn payout() -> BonusPayout {
+        BonusPayout { id: PlayerId::new(), amount: 100, score: 220 }
+    }
+
+    #[test]
+    fn responses_match_schemas() {
+        assert_serializes(&player_data());
+        assert_serializes(&PlayerData { cosmetics: Cosmetics::default(), ..player_data() });
+        assert_serializes(&PlayersResponse { players: vec![player_data(), player_data()] });
+        assert_serializes(&CosmeticCatalog::new());
+        assert_serializes(&FeedMeResponse { score: 12 });
+        assert_serializes(&FeedBatchResponse { accepted: 3, score: 15 });
+        assert_serializes(&TimeResponse { server_time: 1_500_000_000_000 });
+        assert_serializes(&RankingsResponse { rankings: vec![ranked_player()] });
+
+        for &response in &[
+            NoseGoesResponse::Pending,
+            NoseGoesResponse::Survived,
+            NoseGoesResponse::LostLife,
+            NoseGoesResponse::Died,
+        ] {
+            assert_serializes(&response);
+        }
+
+        let tap = NoseGoesTap { received: 10, time: 5, outcome: NoseGoesResponse::Survived };
+        assert_serializes(&NoseGoesStatus {
+            active: false,
+            in_round: true,
+            tap: Some(tap),
+            outcome: Some(NoseGoesResponse::Survived),
+        });
+        assert_serializes(&NoseGoesStatus {
+            active: true,
+            in_round: false,
+            tap: None,
+            outcome: None,
+        });
+
+        let tenure = |ended_at| {
+            CrownTenure {
+                id: PlayerId::new(),
+                name: "Hungry Hippo".into(),
+                started_at: 1_000,
+                ended_at,
+                duration: 500,
+            }
+        };
+        let history = vec![tenure(Some(1_500)), tenure(None)];
+        assert_serializes(&CrownHistoryResponse { history });
+    }
+
+    #[test]
+    fn errors_match_schema() {
+        let id = PlayerId::new();
+        let errors = vec![
+            Error::InvalidPlayer(id),
+            Error::NoseGoesNotActive,
+            Error::NotInRound(id),
+            Error::InvalidRequest("Bad request".into()),
+            Error::NotEliminated(id),
+            Error::RespawnCooldown { retry_after: 100 },
+            Error::CosmeticLocked { item: "gold".into(), unlock_score: 500 },
+            Error::GamePaused,
+            Error::RateLimited { retry_after: 25 },
+            Error::Unauthorized,
+            Error::UnsupportedVersion { supported: vec![1] },
+            Error::Internal("Oops".into()),
+        ];
+
+        for error in &errors {
+            assert_serializes(error);
+        }
+    }
+
+    #[test]
+    fn host_broadcasts_match_schema() {
+        let id = PlayerId::new();
+        let broadcasts = vec![
+            HostBroadcast::PlayerRegister {
+                id,
+                name: "Hungry Hippo".into(),
+                score: 0,
+                lives: 3,
+                cosmetics: Cosmetics::default(),
+            },
+            HostBroadcast::PlayerRespawn {
+                id,
+                name: "Hungry Hippo".into(),
+                score: 50,
+                lives: 3,
+                cosmetics: Cosmetics::default(),
+            },
+            HostBroadcast::PlayerCosmetics { id, cosmetics: Cosmetics::default() },
+            HostBroadcast::AchievementUnlocked {
+                id,
+                achievement: Achievement::Survivor,
+                title: Achievement::Survivor.title(),
+                description: Achievement::Survivor.description(),
+            },
+            HostBroadcast::HippoEat { id, score: 10 },
+            HostBroadcast::BeginNoseGoes {
+                duration: 5_000,
+                start_time: 1_000,
+                end_time: 6_000,
+                players: vec![id, PlayerId::new()].into_iter().collect(),
+                elimination: Elimination::BottomTappers { count: 2 },
+            },
+            HostBroadcast::BeginNoseGoes {
+                duration: 5_000,
+                start_time: 1_000,
+                end_time: 6_000,
+                players: Default::default(),
+                elimination: Elimination::MissedDeadline,
+            },
+            HostBroadcast::BonusWinner { id },
+            HostBroadcast::LifeLost { id, lives: 1 },
+            HostBroadcast::EndNoseGoes {
+                losers: vec![PlayerId::new()].into_iter().collect(),
+                bonus_winner: Some((id, 220)),
+                payouts: vec![payout()],
+            },
+            HostBroadcast::EndNoseGoes {
+                losers: Default::default(),
+                bonus_winner: None,
+                payouts: Vec::new(),
+            },
+            HostBroadcast::UpdateWinner { holders: vec![id, PlayerId::new()] },
+            HostBroadcast::UpdateWinner { holders: Vec::new() },
+            HostBroadcast::ServerShuttingDown {},
+            HostBroadcast::ScoreBatch { scores: vec![ScoreUpdate { id, score: 12, delta: 2 }] },
+            HostBroadcast::RankingChanged { top: vec![ranked_player(), ranked_player()] },
+        ];
+
+        for broadcast in &broadcasts {
+            assert_serializes(broadcast);
+        }
+    }
+
+    #[test]
+    fn player_broadcasts_match_schema() {
+        let id = PlayerId::new();
+        let broadcasts = vec![
+            PlayerBroadcast::BeginNoseGoes { start_time: 1_000, end_time: 6_000 },
+            PlayerBroadcast::BonusWinner { id },
+            PlayerBroadcast::AchievementUnlocked {
+                id,
+                achievement: Achievement::LongReign,
+                title: Achievement::LongReign.title(),
+                description: Achievement::LongReign.description(),
+            },
+            PlayerBroadcast::LifeLost { id, lives: 2 },
+            PlayerBroadcast::EndNoseGoes { bonus_winner: Some((id, 220)), payouts: vec![payout()] },
+            PlayerBroadcast::EndNoseGoes { bonus_winner: None, payouts: Vec::new() },
+            PlayerBroadcast::PlayerLose { id, score: 40, respawn_at: Some(10_000) },
+            PlayerBroadcast::PlayerLose { id, score: 40, respawn_at: None },
+            PlayerBroadcast::UpdateWinner { holders: vec![id] },
+            PlayerBroadcast::ServerShuttingDown {},
+        ];
+
+        for broadcast in &broadcasts {
+            assert_serializes(broadcast);
+        }
+    }
+
+    #[test]
+    fn replies_match_schema() {
+        let replies = vec![
+            PlayerReply::Reply {
+                request_id: Some(7),
+                result: Ok(CommandResponse::Feed(FeedMeResponse { score: 12 })),
+            },
+            PlayerReply::Reply {
+                request_id: Some(8),
+                result: Ok(CommandResponse::FeedBatch(FeedBatchResponse {
+                    accepted: 2,
+                    score: 14,
+                })),
+            },
+            PlayerReply::Reply {
+                request_id: Some(9),
+                result: Ok(CommandResponse::NoseGoesTap(NoseGoesResponse::Pending)),
+            },
+            PlayerReply::Reply {
+                request_id: None,
+                result: Err(Error::InvalidRequest("Bad request".into())),
+            },
+        ];
+
+        for reply in &replies {
+            assert_serializes(reply);
+        }
+    }
+
+    #[test]
+    fn requests_match_schemas() {
+        assert_deserializes::<FeedMeRequest>(json!({ "id": "1234" }));
+        assert_deserializes::<FeedBatchRequest>(json!({ "id": "1234", "taps": [1, 2, 3] }));
+        assert_deserializes::<NoseGoesTapRequest>(json!({}));
+        assert_deserializes::<NoseGoesTapRequest>(json!({ "tapped_at": 1_000 }));
+        assert_deserializes::<Cosmetics>(json!({ "color": "blue", "skin": "spotted" }));
+        assert_deserializes::<Cosmetics>(json!({ "color": "blue", "hat": "bow", "skin": "plain" }));
+        assert_deserializes::<PlayerRequest>(json!({
+            "request_id": 7,
+            "command": { "Feed": { "id": "1234" } }
+        }));
+        assert_deserializes::<PlayerRequest>(json!({
+            "request_id": 8,
+            "command": { "FeedBatch": { "id": "1234", "taps": [5] } }
+        }));
+        assert_deserializes::<PlayerRequest>(json!({
+            "request_id": 9,
+            "command": { "NoseGoesTap": { "id": "1234", "tapped_at": 1_000 } }
+        }));
+        assert_deserializes::<PlayerRequest>(json!({
+            "request_id": 10,
+            "command": { "NoseGoesTap": { "id": "1234" } }
+        }));
+    }
+
+    #[test]
+    fn validate_rejects_mismatches() {
+        // Make sure the checks above would actually catch a schema that has drifted.
+        let mut value = serde_json::to_value(&player_data()).unwrap();
+        value["new_field"] = json!(true);
+        assert!(validate(&PlayerData::schema(), &value, "$").is_err());
+
+        let mut value = serde_json::to_value(&player_data()).unwrap();
+        value.as_object_mut().unwrap().remove("lives");
+        assert!(validate(&PlayerData::schema(), &value, "$").is_err());
+
+        let value = json!({ "HippoEat": { "id": "1234", "score": "12" } });
+        assert!(validate(&HostBroadcast::schema(), &value, "$").is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! Graceful shutdown when the server is asked to stop.
+//!
+//! When the server receives `SIGTERM` (e.g. from `scripts/stop_server`) or `SIGINT` (`ctrl+C`), we
+//! pause the game, notify all clients that the server is going away, save the game state if a
+//! state file is configured, and close all websockets before exiting. That way clients see a clean
+//! close rather than a dropped connection, and the game can pick up where it left off once the
+//! server comes back.
+
+use broadcast::*;
+use chan::Receiver;
+use chan_signal::{self, Signal};
+use game_loop::GameHandle;
+use std::process;
+use std::thread;
+use std::time::*;
+
+/// Starts listening for shutdown signals.
+///
+/// NOTE: This must be called before any other threads are spawned, otherwise the signals may be
+/// delivered to those threads instead of being caught.
+pub fn listen() -> Receiver<Signal> {
+    chan_signal::notify(&[Signal::INT, Signal::TERM])
+}
+
+/// Spawns a thread that shuts down the server once a shutdown signal is received.
+pub fn handle_shutdown(
+    signals: Receiver<Signal>,
+    game: GameHandle,
+    host_broadcaster: HostBroadcaster,
+    player_broadcaster: PlayerBroadcaster,
+) {
+    thread::spawn(move || {
+        let signal = match signals.recv() {
+            Some(signal) => signal,
+            None => return,
+        };
+        info!("Received {:?}, shutting down", signal);
+
+        // Pause the game first so that the `ServerShuttingDown` broadcasts are the last thing
+        // clients hear, and so that nothing changes after the game state has been saved.
+        if let Err(error) = game.shut_down() {
+            error!("Failed to shut down the game cleanly: {:?}", error);
+        }
+
+        host_broadcaster.close(Duration::from_millis(2_000));
+        player_broadcaster.close(Duration::from_millis(2_000));
+
+        // Closing the websockets only queues up the close frames, so give the websocket servers a
+        // moment to actually send them before the process goes away.
+        thread::sleep(Duration::from_millis(500));
+        process::exit(0);
+    });
+}
@@ -0,0 +1,154 @@
+//! Player commands sent over the player websocket.
+//!
+//! Tapping rapidly on a phone means a lot of requests, and every HTTP request pays for its own
+//! round trip and headers. Players already have a websocket open for broadcasts, so they can send
+//! commands over it instead. Commands are routed to the same game logic as the HTTP endpoints.
+//!
+//! Each request carries a request ID chosen by the client, and the reply (which is only sent to
+//! the client that made the request) echoes the ID back so that the client can match replies to
+//! requests. A request (shown as JSON, see the [`encoding`] module for the other wire formats)
+//! looks like:
+//!
+//! ```json
+//! { "request_id": 7, "command": { "Feed": { "id": "1234" } } }
+//! ```
+//!
+//! And the reply looks like:
+//!
+//! ```json
+//! { "Reply": { "request_id": 7, "result": { "Ok": { "Feed": { "score": 12 } } } } }
+//! ```
+//!
+//! [`encoding`]: ../encoding/index.html
+
+use api::{FeedBatchResponse, FeedMeResponse, NoseGoesResponse};
+use broadcast::MessageHandler;
+use error::*;
+use game::{PlayerId, TapTiming};
+use game_loop::GameHandle;
+use serde_json::{self, Value};
+
+/// A request sent by a player over the websocket.
+#[derive(Debug, Deserialize)]
+pub struct PlayerRequest {
+    /// An ID chosen by the client, which is included in the reply.
+    pub request_id: u64,
+
+    /// The command to apply to the game.
+    pub command: PlayerCommand,
+}
+
+/// A command that a player can send over the websocket.
+#[derive(Debug, Deserialize)]
+pub enum PlayerCommand {
+    /// Feeds the player's hippo. Equivalent to `POST /api/feed-me`.
+    Feed {
+        id: PlayerId,
+    },
+
+    /// Feeds the player's hippo once for each tap in a batch. Equivalent to
+    /// `POST /api/feed-batch`.
+    FeedBatch {
+        id: PlayerId,
+        taps: Vec<u64>,
+    },
+
+    /// Taps the player's poison marble during a nose-goes event. Equivalent to
+    /// `POST /api/nose-goes/<id>`.
+    ///
+    /// Taps sent over the websocket are also compensated using the connection's round trip time.
+    NoseGoesTap {
+        id: PlayerId,
+
+        /// When the player tapped, in milliseconds since the Unix epoch (server time).
+        #[serde(default)]
+        tapped_at: Option<u64>,
+    },
+}
+
+/// The result of successfully applying a `PlayerCommand`.
+#[derive(Debug, Serialize)]
+pub enum CommandResponse {
+    Feed(FeedMeResponse),
+    FeedBatch(FeedBatchResponse),
+    NoseGoesTap(NoseGoesResponse),
+}
+
+/// A message sent back to the player that made a request.
+///
+/// This is an enum (rather than a struct) so that the client can tell replies apart from
+/// broadcasts the same way it tells broadcasts apart from each other.
+#[derive(Debug, Serialize)]
+pub enum PlayerReply {
+    Reply {
+        /// The ID of the request that this is a reply to.
+        ///
+        /// `None` if the request was so malformed that its ID couldn't be read.
+        request_id: Option<u64>,
+
+        /// The result of applying the command.
+        result: Result<CommandResponse>,
+    },
+}
+
+/// Applies commands sent over the player websocket to the game.
+#[derive(Debug)]
+pub struct PlayerCommandHandler {
+    game: GameHandle,
+}
+
+impl PlayerCommandHandler {
+    pub fn new(game: GameHandle) -> PlayerCommandHandler {
+        PlayerCommandHandler { game }
+    }
+
+    fn apply(&self, command: PlayerCommand, rtt: Option<u64>) -> Result<CommandResponse> {
+        match command {
+            PlayerCommand::Feed { id } => {
+                let score = self.game.feed(id)?;
+                Ok(CommandResponse::Feed(FeedMeResponse { score }))
+            }
+
+            PlayerCommand::FeedBatch { id, taps } => {
+                let response = self.game.feed_batch(id, taps)?;
+                Ok(CommandResponse::FeedBatch(response))
+            }
+
+            PlayerCommand::NoseGoesTap { id, tapped_at } => {
+                let response = self.game.tap(id, TapTiming { tapped_at, rtt })?;
+                Ok(CommandResponse::NoseGoesTap(response))
+            }
+        }
+    }
+}
+
+impl MessageHandler for PlayerCommandHandler {
+    fn handle(&self, message: Result<Value>, rtt: Option<u64>) -> Option<Value> {
+        // Parse the message in two steps so that a request with a valid ID but an invalid command
+        // still gets a reply the client can match up with the request.
+        let reply = match message {
+            Ok(value) => {
+                let request_id = value.get("request_id").and_then(Value::as_u64);
+                let result = serde_json::from_value::<PlayerRequest>(value)
+                    .map_err(|error| Error::InvalidRequest(error.to_string()))
+                    .and_then(|request| self.apply(request.command, rtt));
+                PlayerReply::Reply { request_id, result }
+            }
+
+            Err(error) => {
+                PlayerReply::Reply {
+                    request_id: None,
+                    result: Err(error),
+                }
+            }
+        };
+
+        match serde_json::to_value(&reply) {
+            Ok(reply) => Some(reply),
+            Err(error) => {
+                error!("Failed to serialize websocket reply, dropping it: {}", error);
+                None
+            }
+        }
+    }
+}
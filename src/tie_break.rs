@@ -0,0 +1,136 @@
+//! Policies for deciding who wears the crown when players are tied for the lead.
+//!
+//! Whenever scores change, the players with the highest score are handed to a [`TieBreakPolicy`]
+//! in the order that they reached their score. The policy picks which of them wear the crown.
+//!
+//! The policy is picked with the `crown_tie_break` option in `Rocket.toml`:
+//!
+//! - `earliest` - The player that reached the score first keeps the crown, so a challenger has to
+//!   beat the crown holder's score to take the crown. This is the default.
+//! - `most-recent` - The player that reached the score last gets the crown, so a challenger only
+//!   has to match the crown holder's score.
+//! - `shared` - Every tied player wears the crown.
+//!
+//! [`TieBreakPolicy`]: ./trait.TieBreakPolicy.html
+
+use game::PlayerId;
+use std::str::FromStr;
+
+/// Decides which of the tied leaders wear the crown.
+pub trait TieBreakPolicy {
+    /// Returns the players that should wear the crown.
+    ///
+    /// `leaders` are the players tied for the highest score, in the order that they reached the
+    /// score.
+    fn holders(&self, leaders: &[PlayerId]) -> Vec<PlayerId>;
+}
+
+/// Gives the crown to the first player to reach the score.
+#[derive(Debug, Clone, Copy)]
+pub struct EarliestToScore;
+
+impl TieBreakPolicy for EarliestToScore {
+    fn holders(&self, leaders: &[PlayerId]) -> Vec<PlayerId> {
+        leaders.first().cloned().into_iter().collect()
+    }
+}
+
+/// Gives the crown to the last player to reach the score.
+#[derive(Debug, Clone, Copy)]
+pub struct MostRecent;
+
+impl TieBreakPolicy for MostRecent {
+    fn holders(&self, leaders: &[PlayerId]) -> Vec<PlayerId> {
+        leaders.last().cloned().into_iter().collect()
+    }
+}
+
+/// Gives every tied player a crown.
+#[derive(Debug, Clone, Copy)]
+pub struct Shared;
+
+impl TieBreakPolicy for Shared {
+    fn holders(&self, leaders: &[PlayerId]) -> Vec<PlayerId> {
+        leaders.to_vec()
+    }
+}
+
+/// Selects one of the tie-break policies.
+///
+/// Unlike the policies themselves this can be saved in the event log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TieBreak {
+    EarliestToScore,
+    MostRecent,
+    Shared,
+}
+
+impl TieBreak {
+    /// Returns the policy that this selects.
+    pub fn policy(&self) -> Box<TieBreakPolicy> {
+        match *self {
+            TieBreak::EarliestToScore => Box::new(EarliestToScore),
+            TieBreak::MostRecent => Box::new(MostRecent),
+            TieBreak::Shared => Box::new(Shared),
+        }
+    }
+}
+
+impl Default for TieBreak {
+    fn default() -> TieBreak {
+        TieBreak::EarliestToScore
+    }
+}
+
+impl FromStr for TieBreak {
+    type Err = String;
+
+    /// Parses a policy in the form used by the `crown_tie_break` option.
+    fn from_str(tie_break: &str) -> Result<TieBreak, String> {
+        match tie_break.trim() {
+            "earliest" => Ok(TieBreak::EarliestToScore),
+            "most-recent" => Ok(TieBreak::MostRecent),
+            "shared" => Ok(TieBreak::Shared),
+            _ => Err(format!("Unknown crown tie-break policy {:?}", tie_break)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use game::test_player_ids;
+    use super::*;
+
+    #[test]
+    fn earliest_keeps_the_crown() {
+        let leaders = test_player_ids(3);
+        assert_eq!(EarliestToScore.holders(&leaders), vec![leaders[0]]);
+    }
+
+    #[test]
+    fn most_recent_takes_the_crown() {
+        let leaders = test_player_ids(3);
+        assert_eq!(MostRecent.holders(&leaders), vec![leaders[2]]);
+    }
+
+    #[test]
+    fn shared_crowns_everyone() {
+        let leaders = test_player_ids(3);
+        assert_eq!(Shared.holders(&leaders), leaders);
+    }
+
+    #[test]
+    fn no_leaders_means_no_crown() {
+        for tie_break in &[TieBreak::EarliestToScore, TieBreak::MostRecent, TieBreak::Shared] {
+            assert!(tie_break.policy().holders(&[]).is_empty());
+        }
+    }
+
+    #[test]
+    fn parse_policies() {
+        assert_eq!("earliest".parse(), Ok(TieBreak::EarliestToScore));
+        assert_eq!(" most-recent ".parse(), Ok(TieBreak::MostRecent));
+        assert_eq!("shared".parse(), Ok(TieBreak::Shared));
+        assert!("latest".parse::<TieBreak>().is_err());
+    }
+}
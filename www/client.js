@@ -8,8 +8,19 @@ let app = new Vue({
         id: null,
         hippoName: null,
         score: null,
+        lives: null,
+
+        // The player's picked cosmetics, and the catalog of options they can pick from.
+        cosmetics: null,
+        catalog: null,
+
         isPlaying: true,
         hasCrown: false,
+
+        // When the player can respawn after being knocked out, in server time, or null if they
+        // can't respawn.
+        respawnAt: null,
+        canRespawn: false,
         noseGoes: {
             isActive: false,
             showMarble: true,
@@ -26,9 +37,8 @@ let app = new Vue({
                 return;
             }
 
-            post('/api/feed-me', { id: this.id }, response => {
-                this.score = response.score;
-            });
+            // Taps are collected and sent to the server in batches, see `sendTaps`.
+            pendingTaps.push(Date.now());
 
             // Animate the text in the center of the screen to give the user some feedback when
             // they tap.
@@ -50,10 +60,40 @@ let app = new Vue({
             window.location.reload(false);
         },
 
+        setCosmetics: function () {
+            post(`/api/v1/cosmetics/${this.id}`, this.cosmetics, response => {
+                app.cosmetics = response.cosmetics;
+            }, (status, error) => {
+                console.error('Failed to change cosmetics:', status, error);
+            });
+        },
+
+        respawn: function () {
+            post(`/api/v1/respawn/${this.id}`, null, response => {
+                app.score = response.score;
+                app.lives = response.lives;
+                app.hasCrown = response.has_crown;
+                app.isPlaying = true;
+                app.respawnAt = null;
+                app.canRespawn = false;
+
+                localStorage.setItem('id', response.id);
+            }, (status, error) => {
+                console.error('Failed to respawn:', status, error);
+            });
+        },
+
         poisonMarble: function () {
             this.noseGoes.showMarble = false;
-            post(`/api/nose-goes/${this.id}`, {}, response => {
-                if (response === 'Survived') {
+
+            // Tell the server when we tapped so that it can make up for a slow connection.
+            let body = { id: this.id, tapped_at: Math.round(serverNow()) };
+            sendCommand('NoseGoesTap', body, `/api/v1/nose-goes/${this.id}`, response => {
+                if (response === 'Pending') {
+                    // The outcome is decided when the event ends, see `EndNoseGoes`.
+                } else if (response === 'LostLife') {
+                    // The player's remaining lives are sent in a `LifeLost` event.
+                } else if (response === 'Survived') {
                     // TODO: What do we do if the player survived?
                 } else if (response === 'Died') {
                     // TODO: Do we handle the player's death now or what?
@@ -68,15 +108,80 @@ let app = new Vue({
 // Initialize WebSocket connetion without waiting for the DOM to be ready. I don't know if that's
 // actually a good idea, but whatevs.
 let socket = new WebSocket('ws://' + window.location.hostname + ':6768');
-socket.onmessage = function(event) {
-    // Ignore websocket events if the game is over or there's a nose-goes event.
-    if (!app.isPlaying && !app.noseGoes.isActive) {
+
+// Callbacks for commands sent over the websocket that are still waiting for a reply, keyed by
+// request ID.
+let pendingRequests = {};
+let nextRequestId = 0;
+
+// Sends a command to the server, calling `onSuccess` with the response.
+//
+// Commands are sent over the websocket when it's open since that's a lot cheaper than making an
+// HTTP request for every tap. If the websocket isn't open the command is `POST`ed to `url` with
+// `body` instead.
+function sendCommand(name, body, url, onSuccess) {
+    if (socket.readyState !== WebSocket.OPEN) {
+        post(url, body, onSuccess);
         return;
     }
 
+    let requestId = nextRequestId++;
+    pendingRequests[requestId] = { name: name, onSuccess: onSuccess };
+
+    let command = {};
+    command[name] = body;
+    socket.send(JSON.stringify({ request_id: requestId, command: command }));
+}
+
+function handleReply(reply) {
+    let request = pendingRequests[reply.request_id];
+    delete pendingRequests[reply.request_id];
+
+    let error = reply.result['Err'];
+    if (error != null) {
+        console.error('Command failed (' + error.code + '):', error.message);
+    } else if (request != null) {
+        request.onSuccess(reply.result['Ok'][request.name]);
+    } else {
+        console.error('Received reply for unknown request:', reply);
+    }
+}
+
+// Taps that haven't been sent to the server yet, as timestamps in milliseconds.
+let pendingTaps = [];
+
+// Sends any taps collected since the last batch was sent.
+//
+// Sending each tap separately floods the server with requests when players are tapping as fast as
+// they can, so instead we collect taps and send them in batches.
+function sendTaps() {
+    if (pendingTaps.length === 0 || app.id == null) {
+        return;
+    }
+
+    let taps = pendingTaps;
+    pendingTaps = [];
+    sendCommand('FeedBatch', { id: app.id, taps: taps }, '/api/v1/feed-batch', response => {
+        app.score = response.score;
+    });
+}
+
+setInterval(sendTaps, 100);
+
+socket.onmessage = function(event) {
     // TODO: Do some kind of validation.
     let payload = JSON.parse(event.data);
 
+    if (payload['Reply']) {
+        handleReply(payload['Reply']);
+        return;
+    }
+
+    // Ignore websocket events if the game is over or there's a nose-goes event.
+    if (!app.isPlaying && !app.noseGoes.isActive) {
+        return;
+    }
+
     if (payload['BeginNoseGoes']) {
         app.noseGoes.isActive = true;
         app.noseGoes.showMarble = true;
@@ -93,8 +198,10 @@ socket.onmessage = function(event) {
         // TODO: Do some kind of animation when the player is the one who lost?
         let event = payload['EndNoseGoes'];
         console.log(event);
-        if (event.bonus_winner != null && event.bonus_winner[0] === app.id) {
-            app.score = event.bonus_winner[1];
+        for (let payout of event.payouts) {
+            if (payout.id === app.id) {
+                app.score = payout.score;
+            }
         }
 
         app.noseGoes.isActive = false;
@@ -104,17 +211,40 @@ socket.onmessage = function(event) {
             app.score = event.score;
             app.numMarbles = event.num_marbles;
         }
+    } else if (payload['AchievementUnlocked']) {
+        let event = payload['AchievementUnlocked'];
+        if (event.id === app.id) {
+            // TODO: Show the achievement on the player's screen too.
+            console.log('Unlocked achievement:', event.title);
+            window.navigator.vibrate([100, 50, 100]);
+        }
+    } else if (payload['LifeLost']) {
+        let event = payload['LifeLost'];
+        if (event.id === app.id) {
+            app.lives = event.lives;
+            window.navigator.vibrate(500);
+        }
     } else if (payload['PlayerLose']) {
         let event = payload['PlayerLose'];
         if (event.id === app.id) {
             app.score = event.score;
             app.isPlaying = false;
 
+            if (event.respawn_at != null) {
+                app.respawnAt = event.respawn_at;
+                let wait = Math.max(event.respawn_at - serverNow(), 0);
+                setTimeout(() => { app.canRespawn = true; }, wait);
+            }
+
             localStorage.removeItem('id');
         }
     } else if (payload['UpdateWinner']) {
         let event = payload['UpdateWinner'];
-        app.hasCrown = (event.id == app.id);
+        app.hasCrown = event.holders.includes(app.id);
+    } else if (payload['ServerShuttingDown']) {
+        // The server is going away. Once the socket closes we'll keep trying to reconnect, and
+        // reload once the server is back up.
+        app.noseGoes.isActive = false;
     } else {
         console.error('Unrecognized player event:', payload);
     }
@@ -127,7 +257,7 @@ socket.onerror = function(error) {
 socket.onclose = (event) => {
     function tryReconnect() {
         get(
-            '/api/players',
+            '/api/v1/players',
             () => { window.location.reload(true); },
             () => { setTimeout(tryReconnect, 1000); },
         );
@@ -138,10 +268,12 @@ socket.onclose = (event) => {
 
 function registerPlayer() {
     // Register the player with the backend.
-    get('/api/register-player', response => {
+    get('/api/v1/register-player', response => {
         app.id = response.id;
         app.hippoName = response.name;
         app.score = response.score;
+        app.lives = response.lives;
+        app.cosmetics = response.cosmetics;
         app.hasCrown = response.has_crown;
 
         localStorage.setItem('id', response.id);
@@ -151,11 +283,13 @@ function registerPlayer() {
 let cachedId = localStorage.getItem('id');
 if (cachedId != null) {
     get(
-        `/api/player/${cachedId}`,
+        `/api/v1/player/${cachedId}`,
         response => {
             app.id = response.id;
             app.hippoName = response.name;
             app.score = response.score;
+            app.lives = response.lives;
+            app.cosmetics = response.cosmetics;
             app.hasCrown = response.has_crown;
         },
 
@@ -185,3 +319,7 @@ TweenMax.fromTo(
     { scale: 1, opacity: 1 },
     { scale: 3, opacity: 0, repeat: -1 },
 );
+
+get('/api/v1/cosmetics', response => {
+    app.catalog = response;
+});
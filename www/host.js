@@ -10,6 +10,15 @@ const LEFT_SIDE = 3;
 
 const SIDE_CSS_NAME = ['top', 'right', 'bottom', 'left'];
 
+// How each hat in the server's cosmetics catalog is drawn on top of a hippo's head.
+const HATS = {
+    'bow': '🎀',
+    'top-hat': '🎩',
+    'party-hat': '🥳',
+    'pirate-hat': '🏴‍☠️',
+    'wizard-hat': '🧙',
+};
+
 // Initialize the VueJS app. This is used for app rendering.
 let app = new Vue({
     el: '#vue-root',
@@ -26,12 +35,37 @@ let app = new Vue({
 
         noseGoes: {
             isActive: false,
+
+            // When the nose-goes event ends, in server time, and how many seconds are left.
+            endTime: null,
+            secondsLeft: null,
+
+            // Describes who will be knocked out when the event ends.
+            eliminationText: null,
         },
 
         deathMessage: {
             isActive: false,
             hippoName: null,
-        }
+        },
+
+        achievementMessage: {
+            isActive: false,
+            hippoName: null,
+            title: null,
+            description: null,
+        },
+
+        // The top players, as ranked by the server. The scores are only current as of the last
+        // ranking change, so the live scores are looked up in the hippo map instead.
+        podium: [],
+    },
+
+    methods: {
+        podiumScore: function (entry) {
+            let hippo = this.hippoMap[entry.id];
+            return hippo != null ? hippo.player.score : entry.score;
+        },
     },
 });
 
@@ -43,9 +77,15 @@ Vue.component('hippo-head', {
         <div class="hippo-text">
             <div class="name">{{ hippo.player.name }}</div>
             <div class="score">{{ hippo.player.score }}</div>
+            <div class="lives" v-if="hippo.player.lives > 1">{{ '♥'.repeat(hippo.player.lives) }}</div>
         </div>
         <div class="head-image-root" :id="hippo.player.id">
-            <img src="assets/hippo.png" class="head">
+            <img
+                src="assets/hippo.png"
+                class="head"
+                :class="['color-' + hippo.player.cosmetics.color, 'skin-' + hippo.player.cosmetics.skin]"
+            >
+            <div class="hat" v-if="hippo.player.cosmetics.hat && !hippo.hasCrown">{{ hatText(hippo.player.cosmetics.hat) }}</div>
             <transition name="crown">
                 <img src="assets/crown.png" class="crown" v-if="hippo.hasCrown">
             </transition>
@@ -60,6 +100,12 @@ Vue.component('hippo-head', {
         </transition>
     </div>
     `,
+
+    methods: {
+        hatText: function (hat) {
+            return HATS[hat];
+        },
+    },
 });
 
 // Helpers to allow us to place hippos in clockwise order. By cycling through this array, we choose
@@ -78,6 +124,22 @@ const SIDES = [
 ];
 let currentSide = 0;
 
+// Animate the hippo head to match a score increase. The direction of the chomp animation depends on
+// the side of the screen that the hippo is on, so we dynamically set the animation property that
+// moves the hippo relative to its side of the screen.
+function chomp(hippo) {
+    let element = document.getElementById(hippo.player.id);
+    let sideName = SIDE_CSS_NAME[hippo.side.side];
+
+    let from = {};
+    from[sideName] = 0;
+
+    let to = { repeat: 1, yoyo: true, overwrite: 'none' };
+    to[sideName] = '100px';
+
+    TweenMax.fromTo(element, 0.2, from, to);
+}
+
 // Setup a websocket to listen for updates from the server.
 let socket = new WebSocket('ws://' + window.location.hostname + ':6769');
 socket.onmessage = (event) => {
@@ -86,6 +148,14 @@ socket.onmessage = (event) => {
 
     if (payload['PlayerRegister']) {
         addPlayer(payload['PlayerRegister']);
+    } else if (payload['PlayerRespawn']) {
+        addPlayer(payload['PlayerRespawn']);
+    } else if (payload['PlayerCosmetics']) {
+        let info = payload['PlayerCosmetics'];
+        let hippo = app.hippoMap[info.id];
+        assert(hippo != null, 'Unable to find hippo for ID: ' + info.id);
+
+        hippo.player.cosmetics = info.cosmetics;
     } else if (payload['HippoEat']) {
         let info = payload['HippoEat'];
 
@@ -95,22 +165,20 @@ socket.onmessage = (event) => {
 
         // Updated the local score for the player.
         hippo.player.score = info.score;
-
-        // Animate the hippo head to match the score increase. The direction of the chomp animation
-        // depends on the side of the screen that the hippo is on, so we dynamically set the
-        // animation property that moves the hippo relative to its side of the screen.
-        let element = document.getElementById(hippo.player.id);
-        let sideName = SIDE_CSS_NAME[hippo.side.side];
-
-        let from = {};
-        from[sideName] = 0;
-
-        let to = { repeat: 1, yoyo: true, overwrite: 'none' };
-        to[sideName] = '100px';
-
-        TweenMax.fromTo(element, 0.2, from, to);
+        chomp(hippo);
+    } else if (payload['ScoreBatch']) {
+        for (let update of payload['ScoreBatch'].scores) {
+            let hippo = app.hippoMap[update.id];
+            assert(hippo != null, 'Unable to find hippo for ID: ' + update.id);
+
+            hippo.player.score = update.score;
+            chomp(hippo);
+        }
     } else if (payload['BeginNoseGoes']) {
         app.noseGoes.isActive = true;
+        app.noseGoes.endTime = payload['BeginNoseGoes'].end_time;
+        app.noseGoes.eliminationText = describeElimination(payload['BeginNoseGoes'].elimination);
+        updateCountdown();
     } else if (payload['BonusWinner']) {
         let event = payload['BonusWinner'];
         let bonusWinner = app.hippoMap[event.id];
@@ -121,6 +189,12 @@ socket.onmessage = (event) => {
             let element = document.getElementById('bonus-text');
             TweenMax.to(element, 0.5, { scale: 1.2, repeat: -1, yoyo: true });
         });
+    } else if (payload['LifeLost']) {
+        let info = payload['LifeLost'];
+        let hippo = app.hippoMap[info.id];
+        assert(hippo != null, 'Unable to find hippo for ID: ' + info.id);
+
+        hippo.player.lives = info.lives;
     } else if (payload['EndNoseGoes']) {
         app.noseGoes.isActive = false;
 
@@ -129,19 +203,39 @@ socket.onmessage = (event) => {
             removePlayer(loser);
         }
 
-        if (info.bonus_winner != null) {
-            let bonusWinner = app.hippoMap[info.bonus_winner[0]];
-            bonusWinner.player.score = info.bonus_winner[1];
-            bonusWinner.wonBonus = false;
+        for (let payout of info.payouts) {
+            let bonusWinner = app.hippoMap[payout.id];
+            bonusWinner.player.score = payout.score;
+
+            // The bonus winners are announced right as the event ends, so leave the bonus text up
+            // for a moment so that people get a chance to see it.
+            setTimeout(() => { bonusWinner.wonBonus = false; }, 2000);
         }
+    } else if (payload['AchievementUnlocked']) {
+        let info = payload['AchievementUnlocked'];
+        let hippo = app.hippoMap[info.id];
+        assert(hippo != null, 'Unable to find hippo for ID: ' + info.id);
+
+        app.achievementMessage.isActive = true;
+        app.achievementMessage.hippoName = hippo.player.name;
+        app.achievementMessage.title = info.title;
+        app.achievementMessage.description = info.description;
+        setTimeout(() => { app.achievementMessage.isActive = false; }, 4000);
     } else if (payload['UpdateWinner']) {
         for (let key in app.hippoMap) {
             let hippo = app.hippoMap[key];
             hippo.hasCrown = false;
         }
 
-        let winner = payload['UpdateWinner'].id;
-        app.hippoMap[winner].hasCrown = true;
+        for (let id of payload['UpdateWinner'].holders) {
+            app.hippoMap[id].hasCrown = true;
+        }
+    } else if (payload['RankingChanged']) {
+        app.podium = payload['RankingChanged'].top;
+    } else if (payload['ServerShuttingDown']) {
+        // The server is going away. Once the socket closes we'll keep trying to reconnect, and
+        // reload once the server is back up.
+        app.noseGoes.isActive = false;
     } else {
         console.error('Unrecognized host event:', payload);
     }
@@ -150,7 +244,7 @@ socket.onmessage = (event) => {
 socket.onclose = (event) => {
     function tryReconnect() {
         get(
-            '/api/players',
+            '/api/v1/players',
             () => { window.location.reload(true); },
             () => { setTimeout(tryReconnect, 1000); },
         );
@@ -161,9 +255,9 @@ socket.onclose = (event) => {
 
 
 // When we first boot up we need to get the current list of players.
-get('/api/players', response => {
+get('/api/v1/players', response => {
     let players = response['players'];
-    assert(players != null, '/api/players response was missing a "players" member');
+    assert(players != null, '/api/v1/players response was missing a "players" member');
 
     // Add players to the player map, so we can find them by ID.
     for (let player of players) {
@@ -171,6 +265,15 @@ get('/api/players', response => {
     }
 });
 
+// Get the current podium, which is otherwise only sent when it changes. The limit should match the
+// server's `podium_size`.
+get('/api/v1/rankings?limit=3', response => {
+    let rankings = response['rankings'];
+    assert(rankings != null, '/api/v1/rankings response was missing a "rankings" member');
+
+    app.podium = rankings;
+});
+
 /**
  * Creates a hippo for the new player and adds it to one side of the screen.
  */
@@ -237,3 +340,31 @@ TweenMax.fromTo(
     { rotation: -2 },
     { rotation: 2, repeat: -1, yoyo: true },
 );
+
+// Updates the nose-goes countdown from the event's end time.
+function updateCountdown() {
+    if (!app.noseGoes.isActive || app.noseGoes.endTime == null) {
+        return;
+    }
+
+    let remaining = app.noseGoes.endTime - serverNow();
+    app.noseGoes.secondsLeft = Math.max(0, Math.ceil(remaining / 1000));
+}
+
+setInterval(updateCountdown, 250);
+
+// Returns a description of a nose-goes elimination rule to show on the host display.
+function describeElimination(elimination) {
+    if (elimination === 'LastTapper') {
+        return 'Last one to tap is out!';
+    } else if (elimination === 'MissedDeadline') {
+        return "Don't run out of time!";
+    } else if (elimination['BottomTappers']) {
+        return 'Slowest ' + elimination['BottomTappers'].count + ' are out!';
+    } else if (elimination['FieldPercentage']) {
+        return 'Slowest ' + elimination['FieldPercentage'].percent + '% are out!';
+    }
+
+    console.error('Unrecognized elimination rule:', elimination);
+    return null;
+}
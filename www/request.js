@@ -1,5 +1,21 @@
 'use strict';
 
+// The version of the server API that the client was written against. This is sent with every
+// request so that the server can reject the request rather than sending back something the
+// client doesn't understand.
+const API_VERSION = 1;
+
+// Parses the `{ code, message, details }` error body sent by the server, or returns null if the
+// response isn't an error body (e.g. if the request never reached the game server).
+function parseError(response) {
+    try {
+        let error = JSON.parse(response);
+        return error != null && error.code != null ? error : null;
+    } catch (e) {
+        return null;
+    }
+}
+
 function get(endpoint, onResponse, onError) {
     let request = new XMLHttpRequest();
     request.addEventListener('load', () => {
@@ -7,12 +23,13 @@ function get(endpoint, onResponse, onError) {
             let response = JSON.parse(request.response);
             onResponse(response, request.status);
         } else if (onError != null) {
-            onError(request.status);
+            onError(request.status, parseError(request.response));
         }
     });
 
     request.addEventListener('error', onError);
     request.open('GET', endpoint);
+    request.setRequestHeader('X-Hrh-Api-Version', API_VERSION);
     request.send();
 }
 
@@ -23,10 +40,34 @@ function post(endpoint, payload, onResponse, onError) {
             let response = JSON.parse(request.response);
             onResponse(response, request.status);
         } else if (onError != null) {
-            onError(request.status);
+            onError(request.status, parseError(request.response));
         }
     });
     request.open('POST', endpoint);
+    request.setRequestHeader('X-Hrh-Api-Version', API_VERSION);
     request.setRequestHeader('Content-Type', 'application/json;charset=UTF-8');
     request.send(JSON.stringify(payload));
 }
+
+// The estimated difference between the server's clock and ours, in milliseconds. Broadcasts send
+// times using the server's clock, so add this to `Date.now()` to compare against them.
+let serverClockOffset = 0;
+
+// Estimates `serverClockOffset` by asking the server for its time.
+//
+// We assume the server read its clock halfway between us sending the request and getting the
+// response, so the estimate is off by at most half of the round trip.
+function syncServerClock() {
+    let sent = Date.now();
+    get('/api/v1/time', response => {
+        let received = Date.now();
+        serverClockOffset = response.server_time - (sent + received) / 2;
+    });
+}
+
+// Returns the current server time, in milliseconds since the Unix epoch.
+function serverNow() {
+    return Date.now() + serverClockOffset;
+}
+
+syncServerClock();